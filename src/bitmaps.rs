@@ -0,0 +1,591 @@
+//! Commands treating string values as arrays of bits, the most significant bit
+//! of each byte first.
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    keyspace::{Keyspace, Value},
+    protocol::{
+        BitFieldOp, BitFieldOverflow, BitFieldType, BitOperation, BitPos, BitRange, BitUnit,
+        Element,
+    },
+    strings::{check_length, edit},
+};
+
+/// Sets a bit, growing the string with zero bytes if needed, and replies with
+/// the previous value of the bit.
+pub fn setbit(db: &mut Keyspace, key: Bytes, offset: u64, bit: bool) -> Result<Element> {
+    let string = string_for_write(db, key, offset / 8 + 1)?;
+    let previous = get_bits(string, offset, 1);
+    edit(string, |string| set_bits(string, offset, 1, bit as u64));
+    Ok(Element::Integer(previous as i64))
+}
+
+pub fn getbit(db: &mut Keyspace, key: &[u8], offset: u64) -> Result<Element> {
+    let bit = db
+        .get_string(key)?
+        .map_or(0, |string| get_bits(string, offset, 1));
+    Ok(Element::Integer(bit as i64))
+}
+
+pub fn bitcount(db: &mut Keyspace, key: &[u8], range: Option<BitRange>) -> Result<Element> {
+    let string = db.get_string(key)?.cloned().unwrap_or_default();
+
+    // Like `GETRANGE`, a range entirely before the start of the string is empty
+    if let Some(BitRange {
+        start,
+        end: Some(end),
+        ..
+    }) = range
+    {
+        if start < 0 && end < 0 && start > end {
+            return Ok(Element::Integer(0));
+        }
+    }
+    let Some((first, last)) = resolve_range(&string, range.as_ref()) else {
+        return Ok(Element::Integer(0));
+    };
+
+    let count: u32 = masked_bytes(&string, first, last)
+        .map(|(byte, mask)| (byte & mask).count_ones())
+        .sum();
+    Ok(Element::Integer(count as i64))
+}
+
+/// Replies with the position of the first bit set to `bit` in the range, or -1.
+///
+/// When looking for a clear bit without an explicit end, the string is
+/// considered to be padded with zeros, so the bit past its end is returned if
+/// every bit is set.
+pub fn bitpos(db: &mut Keyspace, bit_pos: BitPos) -> Result<Element> {
+    let Some(string) = db.get_string(&bit_pos.key)?.cloned() else {
+        return Ok(Element::Integer(if bit_pos.bit { -1 } else { 0 }));
+    };
+    let Some((first, last)) = resolve_range(&string, bit_pos.range.as_ref()) else {
+        return Ok(Element::Integer(-1));
+    };
+
+    let position = masked_bytes(&string, first, last)
+        .enumerate()
+        .find_map(|(i, (byte, mask))| {
+            let matching = if bit_pos.bit { byte } else { !byte } & mask;
+            (matching != 0).then(|| (first / 8 + i as u64) * 8 + matching.leading_zeros() as u64)
+        });
+    let end_given = matches!(bit_pos.range, Some(BitRange { end: Some(_), .. }));
+    Ok(Element::Integer(match position {
+        Some(position) => position as i64,
+        None if !bit_pos.bit && !end_given => last as i64 + 1,
+        None => -1,
+    }))
+}
+
+/// Stores the result of a bitwise operation between strings in `destination`,
+/// and replies with its length. Shorter strings are padded with zero bytes.
+pub fn bitop(
+    db: &mut Keyspace,
+    operation: BitOperation,
+    destination: Bytes,
+    keys: Vec<Bytes>,
+) -> Result<Element> {
+    let mut strings = Vec::with_capacity(keys.len());
+    for key in &keys {
+        strings.push(db.get_string(key)?.cloned().unwrap_or_default());
+    }
+    let len = strings.iter().map(Bytes::len).max().unwrap_or(0);
+
+    let byte = |string: &Bytes, i: usize| string.get(i).copied().unwrap_or(0);
+    let result: Vec<u8> = (0..len)
+        .map(|i| {
+            let mut bytes = strings.iter().map(|string| byte(string, i));
+            let first = bytes.next().unwrap_or(0);
+            match operation {
+                BitOperation::And => bytes.fold(first, |result, byte| result & byte),
+                BitOperation::Or => bytes.fold(first, |result, byte| result | byte),
+                BitOperation::Xor => bytes.fold(first, |result, byte| result ^ byte),
+                BitOperation::Not => !first,
+            }
+        })
+        .collect();
+
+    if result.is_empty() {
+        db.remove(&destination);
+    } else {
+        db.insert(destination, Value::string(result.into()));
+    }
+    Ok(Element::Integer(len as i64))
+}
+
+/// Reads and writes integers of arbitrary widths at arbitrary bit offsets,
+/// replying with the result of each operation.
+pub fn bitfield(db: &mut Keyspace, key: Bytes, operations: Vec<BitFieldOp>) -> Result<Element> {
+    // Like Redis, grow the string up front to fit every field written
+    let write_len = operations
+        .iter()
+        .filter_map(|operation| match operation {
+            BitFieldOp::Set(field_type, offset, _) | BitFieldOp::IncrBy(field_type, offset, _) => {
+                Some((offset + field_type.bits as u64 - 1) / 8 + 1)
+            }
+            _ => None,
+        })
+        .max();
+
+    let Some(write_len) = write_len else {
+        let string = db.get_string(&key)?.cloned().unwrap_or_default();
+        return Ok(Element::Array(
+            operations
+                .into_iter()
+                .filter_map(|operation| match operation {
+                    BitFieldOp::Get(field_type, offset) => {
+                        Some(Element::Integer(get_field(&string, field_type, offset)))
+                    }
+                    _ => None,
+                })
+                .collect(),
+        ));
+    };
+
+    let mut replies = Vec::new();
+    edit(string_for_write(db, key, write_len)?, |string| {
+        let mut overflow = BitFieldOverflow::default();
+        for operation in operations {
+            let (field_type, offset, value) = match operation {
+                BitFieldOp::Overflow(behavior) => {
+                    overflow = behavior;
+                    continue;
+                }
+                BitFieldOp::Get(field_type, offset) => {
+                    replies.push(Element::Integer(get_field(string, field_type, offset)));
+                    continue;
+                }
+                BitFieldOp::Set(field_type, offset, value) if field_type.signed => {
+                    (field_type, offset, value as i128)
+                }
+                // The value is taken as the unsigned integer with the same bits
+                BitFieldOp::Set(field_type, offset, value) => {
+                    (field_type, offset, value as u64 as i128)
+                }
+                BitFieldOp::IncrBy(field_type, offset, increment) => (
+                    field_type,
+                    offset,
+                    get_field(string, field_type, offset) as i128 + increment as i128,
+                ),
+            };
+
+            let previous = get_field(string, field_type, offset);
+            replies.push(match fit(field_type, value, overflow) {
+                Some(value) => {
+                    set_bits(string, offset, field_type.bits, value as u64);
+                    // `SET` replies with the previous value, `INCRBY` with the new one
+                    Element::Integer(if matches!(operation, BitFieldOp::Set(..)) {
+                        previous
+                    } else {
+                        value
+                    })
+                }
+                None => Element::Null,
+            });
+        }
+    });
+    Ok(Element::Array(replies))
+}
+
+/// Brings a value back within the range of the type, according to the overflow
+/// behavior. Returns `None` if the value doesn't fit and overflows fail.
+fn fit(field_type: BitFieldType, value: i128, overflow: BitFieldOverflow) -> Option<i64> {
+    let bits = field_type.bits;
+    let (min, max) = if field_type.signed {
+        (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+    } else {
+        (0, (1i128 << bits) - 1)
+    };
+    if (min..=max).contains(&value) {
+        return Some(value as i64);
+    }
+
+    match overflow {
+        BitFieldOverflow::Wrap => {
+            let wrapped = value & ((1i128 << bits) - 1);
+            Some(if field_type.signed && wrapped > max {
+                (wrapped - (1i128 << bits)) as i64
+            } else {
+                wrapped as i64
+            })
+        }
+        BitFieldOverflow::Sat => Some(value.clamp(min, max) as i64),
+        BitFieldOverflow::Fail => None,
+    }
+}
+
+fn get_field(string: &[u8], field_type: BitFieldType, offset: u64) -> i64 {
+    let raw = get_bits(string, offset, field_type.bits);
+    let unused = 64 - field_type.bits;
+    if field_type.signed {
+        // Sign extend
+        ((raw << unused) as i64) >> unused
+    } else {
+        raw as i64
+    }
+}
+
+/// Reads `bits` bits starting at `offset` as an unsigned integer. Bits past the
+/// end of the string are zero.
+fn get_bits(string: &[u8], offset: u64, bits: u32) -> u64 {
+    let mut value = 0;
+    for bit in offset..offset + bits as u64 {
+        let byte = string.get((bit / 8) as usize).copied().unwrap_or(0);
+        value = (value << 1) | ((byte >> (7 - bit % 8)) & 1) as u64;
+    }
+    value
+}
+
+/// Writes the `bits` least significant bits of `value` starting at `offset`.
+/// The string must be long enough to hold them.
+fn set_bits(string: &mut [u8], offset: u64, bits: u32, value: u64) {
+    for (i, bit) in (offset..offset + bits as u64).enumerate() {
+        let set = (value >> (bits as usize - 1 - i)) & 1 == 1;
+        let byte = &mut string[(bit / 8) as usize];
+        let mask = 1 << (7 - bit % 8);
+        if set {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+}
+
+/// Resolves a range of a string to the first and last bits it covers, if any.
+fn resolve_range(string: &[u8], range: Option<&BitRange>) -> Option<(u64, u64)> {
+    let Some(range) = range else {
+        return (!string.is_empty()).then(|| (0, string.len() as u64 * 8 - 1));
+    };
+
+    let len = match range.unit {
+        BitUnit::Byte => string.len() as i64,
+        BitUnit::Bit => string.len() as i64 * 8,
+    };
+    let start = if range.start < 0 {
+        len + range.start
+    } else {
+        range.start
+    }
+    .max(0);
+    let end = match range.end {
+        Some(end) if end < 0 => len + end,
+        Some(end) => end,
+        None => len - 1,
+    }
+    .max(0)
+    .min(len - 1);
+    if start > end {
+        return None;
+    }
+
+    Some(match range.unit {
+        BitUnit::Byte => (start as u64 * 8, end as u64 * 8 + 7),
+        BitUnit::Bit => (start as u64, end as u64),
+    })
+}
+
+/// The bytes covering bits `first` to `last`, along with masks of the bits of
+/// each byte that fall within that range.
+fn masked_bytes(string: &[u8], first: u64, last: u64) -> impl Iterator<Item = (u8, u8)> + '_ {
+    let (first_byte, last_byte) = (first / 8, last / 8);
+    string[first_byte as usize..=last_byte as usize]
+        .iter()
+        .enumerate()
+        .map(move |(i, &byte)| {
+            let i = first_byte + i as u64;
+            let mut mask = 0xff;
+            if i == first_byte {
+                mask &= 0xff >> (first % 8);
+            }
+            if i == last_byte {
+                mask &= 0xff << (7 - last % 8);
+            }
+            (byte, mask)
+        })
+}
+
+/// The string stored at `key`, created or padded with zero bytes to be at least
+/// `len` bytes long.
+fn string_for_write(db: &mut Keyspace, key: Bytes, len: u64) -> Result<&mut Bytes> {
+    check_length(len as usize)?;
+    let len = len as usize;
+    if db.get_string(&key)?.is_none() {
+        db.insert(key.clone(), Value::string(Bytes::new()));
+    }
+    let string = db
+        .get_mut(&key)
+        .expect("inserted above")
+        .as_string_mut()
+        .expect("checked above");
+    if string.len() < len {
+        edit(string, |string| string.resize(len, 0));
+    }
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_with(string: &[u8]) -> Keyspace {
+        let mut db = Keyspace::default();
+        db.insert(
+            Bytes::from("k"),
+            Value::string(Bytes::copy_from_slice(string)),
+        );
+        db
+    }
+
+    fn integer(reply: Element) -> i64 {
+        match reply {
+            Element::Integer(i) => i,
+            other => panic!("replied {other:?}"),
+        }
+    }
+
+    /// Replies to each `BITFIELD` operation, with `None` for failed overflows.
+    fn fields(reply: Element) -> Vec<Option<i64>> {
+        let Element::Array(elements) = reply else {
+            panic!("replied {reply:?}");
+        };
+        elements
+            .into_iter()
+            .map(|element| match element {
+                Element::Integer(i) => Some(i),
+                Element::Null => None,
+                other => panic!("replied {other:?}"),
+            })
+            .collect()
+    }
+
+    fn range(start: i64, end: Option<i64>, unit: BitUnit) -> Option<BitRange> {
+        Some(BitRange { start, end, unit })
+    }
+
+    fn int(bits: u32) -> BitFieldType {
+        BitFieldType { signed: true, bits }
+    }
+
+    fn uint(bits: u32) -> BitFieldType {
+        BitFieldType {
+            signed: false,
+            bits,
+        }
+    }
+
+    #[test]
+    fn bitcount_counts_bytes_or_bits_ranges() {
+        let mut db = db_with(b"foobar");
+        for (range, count) in [
+            (None, 26),
+            (range(0, Some(0), BitUnit::Byte), 4),
+            (range(1, Some(1), BitUnit::Byte), 6),
+            (range(-2, Some(-1), BitUnit::Byte), 7),
+            (range(5, Some(30), BitUnit::Bit), 17),
+            (range(-1, Some(-1), BitUnit::Bit), 0),
+            (range(-8, Some(-1), BitUnit::Bit), 4),
+            (range(2, Some(100), BitUnit::Byte), 16),
+            (range(4, Some(2), BitUnit::Byte), 0),
+            (range(-1, Some(-2), BitUnit::Byte), 0),
+            // Like Redis, ranges before the start are clamped to the first byte
+            (range(-100, Some(-50), BitUnit::Byte), 4),
+            (range(-100, Some(-200), BitUnit::Byte), 0),
+        ] {
+            let context = format!("{range:?}");
+            let reply = bitcount(&mut db, b"k", range);
+            assert_eq!(integer(reply.unwrap()), count, "{context}");
+        }
+        assert_eq!(integer(bitcount(&mut db, b"x", None).unwrap()), 0);
+    }
+
+    #[test]
+    fn bitpos_finds_bits_in_bytes_or_bits_ranges() {
+        for (string, bit, range, position) in [
+            (&b"\xff\xf0\x00"[..], false, None, 12),
+            (b"\x00\xff\xf0", true, range(0, None, BitUnit::Byte), 8),
+            (b"\x00\xff\xf0", true, range(2, None, BitUnit::Byte), 16),
+            (b"\x00\xff\xf0", true, range(2, Some(-1), BitUnit::Byte), 16),
+            (b"\x00\xff\xf0", true, range(7, Some(15), BitUnit::Bit), 8),
+            (b"\x00\xff\xf0", true, range(9, Some(15), BitUnit::Bit), 9),
+            (b"\x00\xff\xf0", false, range(-4, None, BitUnit::Bit), 20),
+            (b"\x00\x00\x00", true, None, -1),
+            (b"\x00\x00\x00", true, range(7, Some(-3), BitUnit::Bit), -1),
+            // Clear bits are found past the end, unless the range has an end
+            (b"\xff\xff\xff", false, None, 24),
+            (b"\xff\xff\xff", false, range(1, None, BitUnit::Byte), 24),
+            (
+                b"\xff\xff\xff",
+                false,
+                range(0, Some(-1), BitUnit::Byte),
+                -1,
+            ),
+            (b"\xff\xff\xff", false, range(3, None, BitUnit::Byte), -1),
+        ] {
+            let context = format!("{string:?} {bit} {range:?}");
+            let mut db = db_with(string);
+            let bit_pos = BitPos {
+                key: Bytes::from("k"),
+                bit,
+                range,
+            };
+            let reply = bitpos(&mut db, bit_pos).unwrap();
+            assert_eq!(integer(reply), position, "{context}");
+        }
+
+        // Missing keys are empty strings padded with zeros
+        let mut db = Keyspace::default();
+        for (bit, position) in [(false, 0), (true, -1)] {
+            let bit_pos = BitPos {
+                key: Bytes::from("k"),
+                bit,
+                range: None,
+            };
+            assert_eq!(integer(bitpos(&mut db, bit_pos).unwrap()), position);
+        }
+    }
+
+    #[test]
+    fn bitop_pads_shorter_strings_with_zeros() {
+        for (operation, keys, result) in [
+            (BitOperation::And, &["k", "k2"][..], &b"\xf0\x00"[..]),
+            (BitOperation::And, &["k", "k2", "missing"], b"\x00\x00"),
+            (BitOperation::Or, &["k", "k2", "missing"], b"\xff\x0f"),
+            (BitOperation::Xor, &["k", "k2"], b"\x0f\x0f"),
+            (BitOperation::Not, &["k"], b"\x00\xf0"),
+        ] {
+            let mut db = db_with(b"\xff\x0f");
+            db.insert(
+                Bytes::from("k2"),
+                Value::string(Bytes::from_static(b"\xf0")),
+            );
+            let keys = keys
+                .iter()
+                .map(|&key| Bytes::from(key.to_string()))
+                .collect();
+            let reply = bitop(&mut db, operation, Bytes::from("d"), keys).unwrap();
+            assert_eq!(integer(reply), 2);
+            assert_eq!(
+                db.get_string(b"d").unwrap().unwrap(),
+                result,
+                "{operation:?}"
+            );
+        }
+
+        // An empty result deletes the destination
+        let mut db = db_with(b"\xff");
+        let keys = vec![Bytes::from("missing")];
+        let reply = bitop(&mut db, BitOperation::Or, Bytes::from("k"), keys).unwrap();
+        assert_eq!(integer(reply), 0);
+        assert!(db.get(b"k").is_none());
+    }
+
+    #[test]
+    fn bitfield_handles_overflows() {
+        use BitFieldOp::{Get, IncrBy, Overflow, Set};
+        use BitFieldOverflow::{Fail, Sat, Wrap};
+
+        let mut db = Keyspace::default();
+        let mut run = |operations| fields(bitfield(&mut db, Bytes::from("k"), operations).unwrap());
+
+        // `SET` replies with the previous value, `INCRBY` with the new one
+        assert_eq!(
+            run(vec![
+                Set(int(8), 0, 127),
+                IncrBy(int(8), 0, 1),
+                Get(uint(8), 0)
+            ]),
+            [Some(0), Some(-128), Some(128)]
+        );
+        assert_eq!(
+            run(vec![
+                Overflow(Sat),
+                IncrBy(int(8), 0, -1),
+                Overflow(Fail),
+                IncrBy(int(8), 0, -1),
+                Get(int(8), 0),
+                Overflow(Wrap),
+                IncrBy(int(8), 0, -1),
+            ]),
+            [Some(-128), None, Some(-128), Some(127)]
+        );
+
+        // Like the example of the Redis documentation
+        let replies: Vec<_> = (0..4)
+            .map(|_| {
+                run(vec![
+                    IncrBy(uint(2), 100, 1),
+                    Overflow(Sat),
+                    IncrBy(uint(2), 102, 1),
+                ])
+            })
+            .collect();
+        assert_eq!(
+            replies,
+            [
+                [Some(1), Some(1)],
+                [Some(2), Some(2)],
+                [Some(3), Some(3)],
+                [Some(0), Some(3)]
+            ]
+        );
+
+        // Values are set as the unsigned integers with the same bits
+        for (overflow, value, stored) in [
+            (Wrap, -1, 255),
+            (Wrap, 300, 44),
+            (Sat, -1, 255),
+            (Sat, 300, 255),
+            (Fail, -1, 0),
+            (Fail, 300, 0),
+        ] {
+            run(vec![Set(uint(8), 8, 0)]);
+            run(vec![Overflow(overflow), Set(uint(8), 8, value)]);
+            assert_eq!(
+                run(vec![Get(uint(8), 8)]),
+                [Some(stored)],
+                "{overflow:?} {value}"
+            );
+        }
+
+        // The widest types
+        assert_eq!(
+            run(vec![
+                Set(int(64), 128, i64::MIN),
+                IncrBy(int(64), 128, -1),
+                Overflow(Sat),
+                IncrBy(int(64), 128, 1),
+                Set(uint(63), 128, -1),
+                Get(uint(63), 128),
+            ]),
+            [
+                Some(0),
+                Some(i64::MAX),
+                Some(i64::MAX),
+                Some((1 << 62) - 1),
+                Some(i64::MAX)
+            ]
+        );
+    }
+
+    #[test]
+    fn bitfield_reads_without_creating_the_key() {
+        let mut db = Keyspace::default();
+        let operations = vec![BitFieldOp::Get(int(8), 1000)];
+        let reply = bitfield(&mut db, Bytes::from("k"), operations).unwrap();
+        assert_eq!(fields(reply), [Some(0)]);
+        assert!(db.get(b"k").is_none());
+
+        // Writes grow the string to fit every field up front
+        let operations = vec![
+            BitFieldOp::Overflow(BitFieldOverflow::Fail),
+            BitFieldOp::Set(uint(8), 0, 256),
+            BitFieldOp::Set(uint(8), 16, 1),
+        ];
+        let reply = bitfield(&mut db, Bytes::from("k"), operations).unwrap();
+        assert_eq!(fields(reply), [None, Some(0)]);
+        assert_eq!(db.get_string(b"k").unwrap().unwrap(), &b"\x00\x00\x01"[..]);
+    }
+}
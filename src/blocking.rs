@@ -0,0 +1,463 @@
+//! Clients blocked by commands such as `BLPOP` until one of their keys can
+//! serve them. Like Redis, writes only mark the keys clients wait on as ready,
+//! and the clients are served once the command that made them ready is done,
+//! in the order they blocked.
+
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use crate::{
+    error::RedisError,
+    keyspace::{Keyspace, Value},
+    lists,
+    protocol::{BlockingOp, Element, LMPop, LMove, StreamId, XRead, ZMPop},
+    sorted_set::SortedSet,
+    sorted_sets,
+    stream::Stream,
+    streams,
+};
+
+#[derive(Debug)]
+struct Waiter {
+    keys: Vec<Bytes>,
+    operation: BlockingOp,
+    reply: oneshot::Sender<Element>,
+}
+
+#[derive(Debug, Default)]
+pub struct Blocked {
+    next_id: u64,
+    waiters: HashMap<u64, Waiter>,
+    /// Clients blocked on each key, in the order they blocked
+    queues: HashMap<Bytes, VecDeque<u64>>,
+    /// Keys that clients are blocked on and that may be able to serve them
+    ready_keys: VecDeque<Bytes>,
+}
+
+impl Blocked {
+    /// Blocks a client on `keys`, returning an id to unblock it with and where
+    /// the reply will be sent once it's served.
+    fn block(
+        &mut self,
+        keys: Vec<Bytes>,
+        operation: BlockingOp,
+    ) -> (u64, oneshot::Receiver<Element>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &keys {
+            let queue = self.queues.entry(key.clone()).or_default();
+            // A key given more than once still only takes one place in line
+            if queue.back() != Some(&id) {
+                queue.push_back(id);
+            }
+        }
+        let (reply, receiver) = oneshot::channel();
+        self.waiters.insert(
+            id,
+            Waiter {
+                keys,
+                operation,
+                reply,
+            },
+        );
+        (id, receiver)
+    }
+
+    /// Stops waiting for the keys, returning whether the client was still
+    /// blocked, rather than already served.
+    pub fn unblock(&mut self, id: u64) -> bool {
+        self.remove(id).is_some()
+    }
+
+    /// Marks a key as possibly able to serve the clients blocked on it.
+    pub fn signal_ready(&mut self, key: &[u8]) {
+        if self.queues.contains_key(key) && !self.ready_keys.iter().any(|ready| ready == key) {
+            self.ready_keys.push_back(Bytes::copy_from_slice(key));
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Waiter> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in &waiter.keys {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|&waiting| waiting != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+}
+
+/// Blocks a client on `keys`, see [`Blocked::block`]. Clients reading streams
+/// from their last ID (`$`) wait for the entries added after the current one.
+pub fn block(
+    db: &mut Keyspace,
+    keys: Vec<Bytes>,
+    mut operation: BlockingOp,
+) -> Result<(u64, oneshot::Receiver<Element>)> {
+    if let BlockingOp::XRead(xread) = &mut operation {
+        for (key, id) in &mut xread.streams {
+            if id.is_none() {
+                *id = Some(db.get_stream(key)?.map_or(StreamId::MIN, Stream::last_id));
+            }
+        }
+    }
+    Ok(db.blocked().block(keys, operation))
+}
+
+/// Runs a blocking command without blocking, replying with `None` if none of
+/// the keys can serve it.
+pub fn serve(db: &mut Keyspace, keys: &[Bytes], operation: &BlockingOp) -> Result<Option<Element>> {
+    // Unlike other commands, `XREAD` replies with every stream that has entries
+    if let BlockingOp::XRead(xread) = operation {
+        let streams = xread
+            .streams
+            .iter()
+            .filter(|(key, _)| keys.contains(key))
+            .cloned()
+            .collect();
+        return streams::xread(
+            db,
+            &XRead {
+                streams,
+                count: xread.count,
+            },
+        );
+    }
+
+    for key in keys {
+        match db.get(key).map(Value::value_type) {
+            None => continue,
+            Some(value_type) if value_type == operation.value_type() => {}
+            Some(_) => bail!(RedisError::WrongType),
+        }
+        return Ok(Some(match operation {
+            BlockingOp::Pop(end) => {
+                let element = lists::pop_elements(db, key, *end, 1)?
+                    .pop()
+                    .expect("lists are never empty");
+                Element::Array(vec![
+                    Element::BulkString(key.clone()),
+                    Element::BulkString(element),
+                ])
+            }
+            BlockingOp::LMove {
+                destination,
+                from,
+                to,
+            } => lists::lmove(
+                db,
+                LMove {
+                    source: key.clone(),
+                    destination: destination.clone(),
+                    from: *from,
+                    to: *to,
+                },
+            )?,
+            BlockingOp::LMPop { end, count } => lists::lmpop(
+                db,
+                LMPop {
+                    keys: vec![key.clone()],
+                    end: *end,
+                    count: *count,
+                },
+            )?,
+            BlockingOp::ZPop(end) => {
+                let (member, score) = sorted_sets::pop_elements(db, key, *end, 1)?
+                    .pop()
+                    .expect("sorted sets are never empty");
+                Element::Array(vec![
+                    Element::BulkString(key.clone()),
+                    Element::BulkString(member),
+                    Element::Double(score),
+                ])
+            }
+            BlockingOp::ZMPop { end, count } => sorted_sets::zmpop(
+                db,
+                ZMPop {
+                    keys: vec![key.clone()],
+                    end: *end,
+                    count: *count,
+                },
+            )?,
+            BlockingOp::XRead(_) => unreachable!("served above"),
+        }));
+    }
+    Ok(None)
+}
+
+/// Serves the clients blocked on the keys that became ready, for as long as
+/// each key has data for them.
+pub fn serve_blocked(db: &mut Keyspace) {
+    // Serving a client may in turn make other keys ready, such as the
+    // destination of `BLMOVE`
+    while let Some(key) = db.blocked().ready_keys.pop_front() {
+        let Some(queue) = db.blocked().queues.get(&key) else {
+            continue;
+        };
+        for id in queue.clone() {
+            // Keys of another type than the one a client waits for don't
+            // unblock it, and clients further in line may be waiting for
+            // another type
+            let Some(value_type) = db.get(&key).map(Value::value_type) else {
+                break;
+            };
+            let Some(waiter) = db.blocked().waiters.get(&id) else {
+                continue;
+            };
+            // Clients that hung up before unblocking themselves can't be served
+            if waiter.reply.is_closed() {
+                db.blocked().remove(id);
+                continue;
+            }
+            if waiter.operation.value_type() != value_type {
+                continue;
+            }
+
+            let operation = waiter.operation.clone();
+            let reply = match serve(db, std::slice::from_ref(&key), &operation) {
+                Ok(Some(reply)) => reply,
+                // Streams may not have entries past the ones a client has read
+                Ok(None) => continue,
+                Err(e) => Element::Error(RedisError::reply_message(&e)),
+            };
+            let waiter = db.blocked().remove(id).expect("checked above");
+            // The client may still have hung up since, in which case what it
+            // was served goes back for the next clients in line
+            if let Err(reply) = waiter.reply.send(reply) {
+                restore(db, &key, &operation, reply).expect("served keys hold the right type");
+            }
+        }
+    }
+}
+
+/// Puts back what serving a blocked client popped from `key`, given the reply
+/// it was served with.
+fn restore(db: &mut Keyspace, key: &Bytes, operation: &BlockingOp, reply: Element) -> Result<()> {
+    if matches!(reply, Element::Error(_)) {
+        return Ok(());
+    }
+    // Replies to pops start with the key, followed by what was popped
+    let mut popped = Vec::new();
+    if let Element::Array(reply) = reply {
+        reply
+            .into_iter()
+            .skip(1)
+            .for_each(|element| flatten(element, &mut popped));
+    }
+
+    match operation {
+        BlockingOp::Pop(end) | BlockingOp::LMPop { end, .. } => {
+            // Elements are pushed back in the reverse order they were popped
+            let elements = popped
+                .into_iter()
+                .rev()
+                .filter_map(|element| match element {
+                    Element::BulkString(element) => Some(element),
+                    _ => None,
+                })
+                .collect();
+            lists::push(db, *end, key.clone(), elements, true)?;
+        }
+        BlockingOp::LMove {
+            destination,
+            from,
+            to,
+        } => {
+            lists::lmove(
+                db,
+                LMove {
+                    source: destination.clone(),
+                    destination: key.clone(),
+                    from: *to,
+                    to: *from,
+                },
+            )?;
+        }
+        BlockingOp::ZPop(_) | BlockingOp::ZMPop { .. } => {
+            let sorted_set = match db.get_sorted_set_mut(key)? {
+                Some(sorted_set) => sorted_set,
+                None => {
+                    db.insert(key.clone(), Value::sorted_set(SortedSet::new()));
+                    db.get_sorted_set_mut(key)?.expect("inserted above")
+                }
+            };
+            let mut popped = popped.into_iter();
+            while let (Some(Element::BulkString(member)), Some(Element::Double(score))) =
+                (popped.next(), popped.next())
+            {
+                sorted_set.insert(member, score);
+            }
+            db.blocked().signal_ready(key);
+        }
+        // Reading streams doesn't remove anything
+        BlockingOp::XRead(_) => {}
+    }
+    Ok(())
+}
+
+fn flatten(element: Element, elements: &mut Vec<Element>) {
+    match element {
+        Element::Array(nested) => nested
+            .into_iter()
+            .for_each(|element| flatten(element, elements)),
+        element => elements.push(element),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{ListEnd, SortedSetEnd};
+
+    use super::*;
+
+    fn block_pop(db: &mut Keyspace) -> (u64, oneshot::Receiver<Element>) {
+        block(db, vec![Bytes::from("k")], BlockingOp::Pop(ListEnd::Left)).unwrap()
+    }
+
+    fn push(db: &mut Keyspace, key: &str, elements: &[&str]) {
+        let elements = elements
+            .iter()
+            .map(|element| Bytes::copy_from_slice(element.as_bytes()))
+            .collect();
+        lists::push(
+            db,
+            ListEnd::Right,
+            Bytes::from(key.to_string()),
+            elements,
+            true,
+        )
+        .unwrap();
+    }
+
+    fn list(db: &mut Keyspace, key: &str) -> Vec<Bytes> {
+        let list = db.get_list(key.as_bytes()).unwrap();
+        list.map_or(Vec::new(), |list| list.iter().cloned().collect())
+    }
+
+    fn served(receiver: &mut oneshot::Receiver<Element>) -> Option<String> {
+        receiver.try_recv().ok().map(|reply| format!("{reply:?}"))
+    }
+
+    #[test]
+    fn serves_clients_in_the_order_they_blocked() {
+        let mut db = Keyspace::default();
+        let mut clients: Vec<_> = (0..3).map(|_| block_pop(&mut db)).collect();
+        push(&mut db, "k", &["a", "b"]);
+        serve_blocked(&mut db);
+
+        let [first, second, third] = &mut clients[..] else {
+            unreachable!()
+        };
+        assert_eq!(
+            served(&mut first.1).unwrap(),
+            r#"Array([BulkString(b"k"), BulkString(b"a")])"#
+        );
+        assert_eq!(
+            served(&mut second.1).unwrap(),
+            r#"Array([BulkString(b"k"), BulkString(b"b")])"#
+        );
+        assert!(served(&mut third.1).is_none());
+        assert!(db.get(b"k").is_none());
+
+        // Only the client left in line is still blocked
+        assert!(!db.blocked().unblock(first.0));
+        assert!(db.blocked().unblock(third.0));
+    }
+
+    #[test]
+    fn skips_clients_that_hung_up() {
+        let mut db = Keyspace::default();
+        let (gone, receiver) = block_pop(&mut db);
+        drop(receiver);
+        let (_, mut receiver) = block_pop(&mut db);
+        push(&mut db, "k", &["a"]);
+        serve_blocked(&mut db);
+        assert_eq!(
+            served(&mut receiver).unwrap(),
+            r#"Array([BulkString(b"k"), BulkString(b"a")])"#
+        );
+        assert!(!db.blocked().unblock(gone));
+
+        // Nothing is popped for clients that are all gone
+        let (_, receiver) = block_pop(&mut db);
+        drop(receiver);
+        push(&mut db, "k", &["b"]);
+        serve_blocked(&mut db);
+        assert_eq!(list(&mut db, "k"), ["b"]);
+    }
+
+    #[test]
+    fn restores_what_was_served() {
+        let key = Bytes::from("k");
+        let operations = [
+            BlockingOp::Pop(ListEnd::Left),
+            BlockingOp::Pop(ListEnd::Right),
+            BlockingOp::LMPop {
+                end: ListEnd::Left,
+                count: 2,
+            },
+            BlockingOp::LMPop {
+                end: ListEnd::Right,
+                count: 5,
+            },
+            BlockingOp::LMove {
+                destination: Bytes::from("d"),
+                from: ListEnd::Left,
+                to: ListEnd::Right,
+            },
+            BlockingOp::LMove {
+                destination: Bytes::from("k"),
+                from: ListEnd::Right,
+                to: ListEnd::Left,
+            },
+        ];
+        for operation in operations {
+            let mut db = Keyspace::default();
+            push(&mut db, "k", &["a", "b", "c"]);
+            push(&mut db, "d", &["x"]);
+            let reply = serve(&mut db, std::slice::from_ref(&key), &operation)
+                .unwrap()
+                .unwrap();
+            restore(&mut db, &key, &operation, reply).unwrap();
+            assert_eq!(list(&mut db, "k"), ["a", "b", "c"], "{operation:?}");
+            assert_eq!(list(&mut db, "d"), ["x"], "{operation:?}");
+        }
+
+        let operations = [
+            BlockingOp::ZPop(SortedSetEnd::Min),
+            BlockingOp::ZMPop {
+                end: SortedSetEnd::Max,
+                count: 2,
+            },
+        ];
+        for operation in operations {
+            let mut db = Keyspace::default();
+            let mut sorted_set = SortedSet::new();
+            for (member, score) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+                sorted_set.insert(Bytes::from(member), score);
+            }
+            db.insert(key.clone(), Value::sorted_set(sorted_set));
+            let reply = serve(&mut db, std::slice::from_ref(&key), &operation)
+                .unwrap()
+                .unwrap();
+            restore(&mut db, &key, &operation, reply).unwrap();
+            let sorted_set = db.get_sorted_set(b"k").unwrap().unwrap();
+            let members: Vec<_> = sorted_set.iter().collect();
+            assert_eq!(
+                members,
+                [
+                    (&Bytes::from("a"), 1.0),
+                    (&Bytes::from("b"), 2.0),
+                    (&Bytes::from("c"), 3.0)
+                ],
+                "{operation:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,20 @@
+#[derive(Debug)]
+pub struct Config {
+    pub port: usize,
+    /// How many times per second background tasks, such as the active expire
+    /// cycle, run.
+    pub hz: u32,
+    /// From 1 to 10, how much CPU time the active expire cycle may spend to
+    /// reclaim memory from expired keys.
+    pub active_expire_effort: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: 6379,
+            hz: 10,
+            active_expire_effort: 1,
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{error::RedisError, protocol::Element, reader::ElementParser};
+
+/// A TCP stream paired with the bytes received from it that haven't been parsed
+/// into elements yet, so that elements split across several reads (or larger than
+/// a single read) can be reassembled.
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    buffer: BytesMut,
+    parser: ElementParser,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Self {
+        Connection {
+            stream,
+            buffer: BytesMut::with_capacity(4096),
+            parser: ElementParser::new(),
+        }
+    }
+
+    /// Reads the next element from the stream, waiting for more data as long as
+    /// the buffer only contains part of an element.
+    ///
+    /// Returns `Ok(None)` if the peer closed the connection cleanly.
+    pub async fn read_element(&mut self) -> Result<Option<Element>> {
+        loop {
+            if let Some(element) = self.parse_element()? {
+                return Ok(Some(element));
+            }
+
+            let n = self
+                .stream
+                .read_buf(&mut self.buffer)
+                .await
+                .context("read from stream")?;
+
+            if n == 0 {
+                if self.buffer.is_empty() && !self.parser.in_progress() {
+                    return Ok(None);
+                }
+                bail!("connection reset by peer with a partial element in the buffer");
+            }
+        }
+    }
+
+    /// Reads every complete element available, waiting until there is at least
+    /// one. Clients that pipeline commands get all of them parsed at once, in the
+    /// order they were sent.
+    ///
+    /// Returns an empty vector if the peer closed the connection cleanly.
+    pub async fn read_elements(&mut self) -> Result<Vec<Element>> {
+        let mut elements = Vec::new();
+        if let Some(element) = self.read_element().await? {
+            elements.push(element);
+            // A malformed element is reported by the next call, once the elements
+            // that preceded it have been handled
+            while let Ok(Some(element)) = self.parse_element() {
+                elements.push(element);
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Waits for more bytes from the peer and buffers them without parsing them,
+    /// for when elements can't be handled yet, e.g. while the client is blocked.
+    /// Cancelling it doesn't lose any data.
+    ///
+    /// Returns `false` if the peer closed the connection.
+    pub async fn read_more(&mut self) -> Result<bool> {
+        let n = self
+            .stream
+            .read_buf(&mut self.buffer)
+            .await
+            .context("read from stream")?;
+        Ok(n > 0)
+    }
+
+    fn parse_element(&mut self) -> Result<Option<Element>> {
+        let element = self
+            .parser
+            .parse(&mut self.buffer)
+            .map_err(|e| RedisError::Protocol(e.to_string()))?;
+        Ok(element)
+    }
+
+    pub async fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.stream
+            .write_all(bytes)
+            .await
+            .context("write to stream")
+    }
+}
@@ -1,35 +1,55 @@
-use std::{collections::HashMap, sync::Arc, time::Instant};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
-    sync::RwLock,
+    sync::Mutex,
 };
 
 use crate::{
-    protocol::{Command, Element, Psync, ReplOpt},
-    reader::ElementParser,
-    utils::decode_hex,
+    bitmaps, blocking,
+    config::Config,
+    connection::Connection,
+    error::RedisError,
+    hashes, hyperloglog,
+    keyspace::{Keyspace, Value},
+    lists,
+    protocol::{
+        Blocking, Command, Element, Expire, Hello, InfoSection, Protocol, Psync, ReplOpt, TimeUnit,
+    },
+    sets, sorted_sets, streams, strings,
+    utils::{decode_hex, from_unix_millis, glob_match, unix_millis},
     writer::{serialize_command, serialize_element},
 };
 
+/// Per-connection state.
 #[derive(Debug)]
-struct Value {
-    value: String,
-    expiration: Option<Instant>,
+struct Client {
+    id: u64,
+    protocol: Protocol,
+    name: Option<String>,
+    /// Commands queued since `MULTI`
+    transaction: Option<Transaction>,
 }
 
-impl Value {
-    fn is_expired(&self) -> bool {
-        match self.expiration {
-            None => false,
-            Some(expiration) => Instant::now() > expiration,
-        }
-    }
+#[derive(Debug, Default)]
+struct Transaction {
+    commands: Vec<Command>,
+    /// Whether a command failed to be queued, in which case `EXEC` discards the
+    /// transaction
+    failed: bool,
 }
 
 pub trait RoleInfo: std::fmt::Debug {
+    fn name(&self) -> &'static str;
+    fn is_read_only(&self) -> bool;
     fn as_info_section(&self) -> String;
     fn handle_psync(&self, psync: Psync) -> Result<Element>;
 }
@@ -42,10 +62,18 @@ pub struct MasterInfo {
 
 #[derive(Debug)]
 pub struct ReplicaInfo {
-    master: TcpStream,
+    master: Connection,
 }
 
 impl RoleInfo for MasterInfo {
+    fn name(&self) -> &'static str {
+        "master"
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
     fn as_info_section(&self) -> String {
         format!(
             "role:master
@@ -69,6 +97,14 @@ master_repl_offset:{}
 }
 
 impl RoleInfo for ReplicaInfo {
+    fn name(&self) -> &'static str {
+        "replica"
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
     fn as_info_section(&self) -> String {
         "role:slave".to_string()
     }
@@ -80,20 +116,24 @@ impl RoleInfo for ReplicaInfo {
 
 #[derive(Debug)]
 pub struct Database<W: Send> {
-    port: usize,
-    db: RwLock<HashMap<String, Value>>,
+    config: Config,
+    next_client_id: AtomicU64,
+    db: Mutex<Keyspace>,
     role: W,
 }
 
 impl<W: RoleInfo + Send + Sync + 'static> Database<W> {
     pub async fn listen(self) -> Result<()> {
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", self.port))
+        let listener = TcpListener::bind(format!("127.0.0.1:{}", self.config.port))
             .await
             .context("creating TCP server")?;
-        println!("Listening on port {}", self.port);
+        println!("Listening on port {}", self.config.port);
 
         let arc_self = Arc::new(self);
 
+        let s = arc_self.clone();
+        tokio::spawn(async move { s.cron().await });
+
         loop {
             match listener.accept().await {
                 Ok((stream, _addr)) => {
@@ -111,70 +151,531 @@ impl<W: RoleInfo + Send + Sync + 'static> Database<W> {
         }
     }
 
-    async fn handle_stream(&self, mut stream: TcpStream) -> Result<()> {
-        println!("Client connected");
+    /// Runs background tasks `hz` times per second.
+    async fn cron(&self) {
+        let period = Duration::from_secs(1) / self.config.hz;
+        // Like Redis, the active expire cycle may use up to 25% of each period
+        let expire_time_limit = period * (25 + 2 * (self.config.active_expire_effort - 1)) / 100;
+
+        let mut interval = tokio::time::interval(period);
         loop {
-            let mut buf = [0; 1024];
-            let n = stream
-                .read(&mut buf)
+            interval.tick().await;
+            self.db
+                .lock()
                 .await
-                .context("read command from client")?;
+                .active_expire_cycle(self.config.active_expire_effort, expire_time_limit);
+        }
+    }
+
+    async fn handle_stream(&self, stream: TcpStream) -> Result<()> {
+        println!("Client connected");
+        let mut connection = Connection::new(stream);
+        let mut client = Client {
+            id: self.next_client_id.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::Resp2,
+            name: None,
+            transaction: None,
+        };
+        loop {
+            let elements = match connection.read_elements().await {
+                Ok(elements) => elements,
+                Err(e) => {
+                    // Like Redis, reply to malformed input and then drop the client,
+                    // as there's no telling where the next element starts
+                    if let Some(error @ RedisError::Protocol(_)) = e.downcast_ref() {
+                        let reply = Element::Error(error.to_string());
+                        connection
+                            .write_bytes(&serialize_element(reply, client.protocol))
+                            .await?;
+                    }
+                    return Err(e.context("read commands from client"));
+                }
+            };
 
-            if n == 0 {
+            if elements.is_empty() {
                 println!("Client disconnected");
                 return Ok(());
             }
 
-            let command = ElementParser::new(&buf[..n]).parse()?.try_into()?;
-            let result = self.execute(command).await?;
-            stream.write_all(&serialize_element(result)).await?;
+            // Replies to pipelined commands are sent back in a single write
+            let mut replies = Vec::new();
+            for element in elements {
+                let result = match element.try_into() {
+                    Ok(Command::Blocking(blocking)) if client.transaction.is_none() => {
+                        // Don't hold back the replies to the commands before it
+                        // while the client is blocked
+                        connection.write_bytes(&replies).await?;
+                        replies.clear();
+                        self.block(&mut connection, blocking).await
+                    }
+                    Ok(command) => self.execute(&mut client, command).await,
+                    Err(e) => {
+                        // Like Redis, commands that can't be queued abort the
+                        // transaction
+                        if let Some(transaction) = &mut client.transaction {
+                            transaction.failed = true;
+                        }
+                        Err(e)
+                    }
+                };
+                let reply = result.unwrap_or_else(|e| {
+                    println!("Error: {e:?}");
+                    Element::Error(RedisError::reply_message(&e))
+                });
+                replies.extend_from_slice(&serialize_element(reply, client.protocol));
+            }
+            connection.write_bytes(&replies).await?;
         }
     }
 
-    async fn execute(&self, command: Command) -> Result<Element> {
+    async fn execute(&self, client: &mut Client, command: Command) -> Result<Element> {
         println!("Executing {command:?}");
 
+        if command.is_write() && self.role.is_read_only() {
+            if let Some(transaction) = &mut client.transaction {
+                transaction.failed = true;
+            }
+            bail!(RedisError::ReadOnly);
+        }
+
         let result = match command {
-            Command::Ping(message) => Ok(Element::SimpleString(
-                message.unwrap_or_else(|| "PONG".to_string()),
-            )),
-            Command::Echo(message) => Ok(Element::SimpleString(message)),
-            Command::Set(set) => {
-                let mut db = self.db.write().await;
-                db.insert(
-                    set.key,
-                    Value {
-                        value: set.value,
-                        expiration: set.expiration.map(|expiration| Instant::now() + expiration),
-                    },
-                );
+            Command::Multi if client.transaction.is_some() => {
+                bail!(RedisError::Err("MULTI calls can not be nested".to_string()))
+            }
+            Command::Multi => {
+                client.transaction = Some(Transaction::default());
                 Ok(Element::SimpleString("OK".to_string()))
             }
-            Command::Get(key) => {
-                let db = self.db.read().await;
-                match db.get(&key) {
-                    Some(value) if !value.is_expired() => {
-                        Ok(Element::BulkString(value.value.as_bytes().to_vec()))
+            Command::Exec => {
+                let Some(transaction) = client.transaction.take() else {
+                    bail!(RedisError::Err("EXEC without MULTI".to_string()));
+                };
+                if transaction.failed {
+                    bail!(RedisError::ExecAbort);
+                }
+                // The whole transaction runs under the same lock, so no other
+                // client can observe or interleave with part of it
+                let mut db = self.db.lock().await;
+                let replies = transaction
+                    .commands
+                    .into_iter()
+                    .map(|command| {
+                        self.apply(client, &mut db, command)
+                            .unwrap_or_else(|e| Element::Error(RedisError::reply_message(&e)))
+                    })
+                    .collect();
+                blocking::serve_blocked(&mut db);
+                Ok(Element::Array(replies))
+            }
+            Command::Discard => match client.transaction.take() {
+                Some(_) => Ok(Element::SimpleString("OK".to_string())),
+                None => bail!(RedisError::Err("DISCARD without MULTI".to_string())),
+            },
+            command => match &mut client.transaction {
+                Some(transaction) => {
+                    transaction.commands.push(command);
+                    Ok(Element::SimpleString("QUEUED".to_string()))
+                }
+                None => {
+                    let mut db = self.db.lock().await;
+                    let result = self.apply(client, &mut db, command);
+                    blocking::serve_blocked(&mut db);
+                    result
+                }
+            },
+        };
+
+        println!("Result: {result:?}");
+        result
+    }
+
+    /// Waits until one of the keys of a blocking command can serve it, the
+    /// timeout expires, or the client disconnects.
+    async fn block(&self, connection: &mut Connection, blocking: Blocking) -> Result<Element> {
+        println!("Executing {blocking:?}");
+
+        if blocking.operation.is_write() && self.role.is_read_only() {
+            bail!(RedisError::ReadOnly);
+        }
+
+        let (id, mut receiver) = {
+            let mut db = self.db.lock().await;
+            if let Some(reply) = blocking::serve(&mut db, &blocking.keys, &blocking.operation)? {
+                blocking::serve_blocked(&mut db);
+                return Ok(reply);
+            }
+            blocking::block(&mut db, blocking.keys, blocking.operation.clone())?
+        };
+
+        let timeout = async {
+            match blocking.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(timeout);
+        loop {
+            tokio::select! {
+                reply = &mut receiver => {
+                    return Ok(reply.expect("blocked clients are served before being dropped"));
+                }
+                _ = &mut timeout => break,
+                // Keep reading while blocked, to notice when the client hangs up.
+                // Read errors are reported by the next read, once unblocked
+                open = connection.read_more() => {
+                    if !matches!(open, Ok(true)) {
+                        break;
                     }
-                    _ => Ok(Element::NullBulkString),
                 }
             }
-            Command::Info(_section) => Ok(Element::BulkString(
-                self.role.as_info_section().as_bytes().to_vec(),
-            )),
+        }
+
+        // The client may have been served while the lock was being acquired
+        let mut db = self.db.lock().await;
+        if db.blocked().unblock(id) {
+            Ok(blocking.operation.timeout_reply())
+        } else {
+            Ok(receiver
+                .try_recv()
+                .expect("clients are unblocked by being sent a reply"))
+        }
+    }
+
+    /// Runs a command against the keyspace, with the lock already held.
+    fn apply(&self, client: &mut Client, db: &mut Keyspace, command: Command) -> Result<Element> {
+        match command {
+            Command::Ping(message) => Ok(match message {
+                Some(message) => Element::BulkString(message),
+                None => Element::SimpleString("PONG".to_string()),
+            }),
+            Command::Echo(message) => Ok(Element::BulkString(message)),
+            Command::Set(set) => strings::set(db, set),
+            Command::Get(key) => strings::get(db, &key),
+            Command::Info(sections) => self.info(db, sections),
             Command::ReplConf(_repl_conf) => Ok(Element::SimpleString("OK".to_string())),
             Command::Psync(psync) => self.role.handle_psync(psync),
+            Command::Hello(hello) => self.hello(client, hello),
+            Command::Expire(expire) => self.expire(db, expire),
+            Command::Ttl(key, unit) => match db.get(&key).map(Value::expiration) {
+                None => Ok(Element::Integer(-2)),
+                Some(None) => Ok(Element::Integer(-1)),
+                Some(Some(expiration)) => {
+                    let ttl = unix_millis(expiration) - unix_millis(SystemTime::now());
+                    Ok(Element::Integer(match unit {
+                        TimeUnit::Seconds => (ttl + 500) / 1000,
+                        TimeUnit::Milliseconds => ttl,
+                    }))
+                }
+            },
+            Command::ExpireTime(key, unit) => match db.get(&key).map(Value::expiration) {
+                None => Ok(Element::Integer(-2)),
+                Some(None) => Ok(Element::Integer(-1)),
+                Some(Some(expiration)) => Ok(Element::Integer(match unit {
+                    TimeUnit::Seconds => unix_millis(expiration) / 1000,
+                    TimeUnit::Milliseconds => unix_millis(expiration),
+                })),
+            },
+            Command::Persist(key) => match db.get(&key).map(Value::expiration) {
+                Some(Some(_)) => {
+                    db.set_expiration(&key, None);
+                    Ok(Element::Integer(1))
+                }
+                _ => Ok(Element::Integer(0)),
+            },
+            Command::Del(keys) | Command::Unlink(keys) => {
+                let deleted = keys.iter().filter(|key| db.remove(key).is_some()).count();
+                Ok(Element::Integer(deleted as i64))
+            }
+            Command::Exists(keys) | Command::Touch(keys) => {
+                let existing = keys.iter().filter(|key| db.get(key).is_some()).count();
+                Ok(Element::Integer(existing as i64))
+            }
+            Command::Type(key) => Ok(Element::SimpleString(
+                db.get(&key)
+                    .map_or("none", |value| value.value_type().name())
+                    .to_string(),
+            )),
+            Command::Rename(source, destination) => {
+                if db.get(&source).is_none() {
+                    bail!(RedisError::Err("no such key".to_string()));
+                }
+                if source != destination {
+                    let value = db.remove(&source).expect("checked above");
+                    db.insert(destination, value);
+                }
+                Ok(Element::SimpleString("OK".to_string()))
+            }
+            Command::RenameNx(source, destination) => {
+                if db.get(&source).is_none() {
+                    bail!(RedisError::Err("no such key".to_string()));
+                }
+                if db.get(&destination).is_some() {
+                    Ok(Element::Integer(0))
+                } else {
+                    let value = db.remove(&source).expect("checked above");
+                    db.insert(destination, value);
+                    Ok(Element::Integer(1))
+                }
+            }
+            Command::Copy(copy) => {
+                if copy.source == copy.destination {
+                    bail!(RedisError::Err(
+                        "source and destination objects are the same".to_string()
+                    ));
+                }
+                match db.get(&copy.source).cloned() {
+                    Some(_) if !copy.replace && db.get(&copy.destination).is_some() => {
+                        Ok(Element::Integer(0))
+                    }
+                    Some(value) => {
+                        db.insert(copy.destination, value);
+                        Ok(Element::Integer(1))
+                    }
+                    None => Ok(Element::Integer(0)),
+                }
+            }
+            Command::IncrBy(key, increment) => strings::incr_by(db, key, increment),
+            Command::IncrByFloat(key, increment) => strings::incr_by_float(db, key, increment),
+            Command::Append(key, value) => strings::append(db, key, value),
+            Command::Strlen(key) => strings::strlen(db, &key),
+            Command::GetRange(key, start, end) => strings::get_range(db, &key, start, end),
+            Command::SetRange(key, offset, value) => strings::set_range(db, key, offset, value),
+            Command::GetDel(key) => strings::get_del(db, &key),
+            Command::GetEx(get_ex) => strings::get_ex(db, get_ex),
+            Command::Lcs(lcs) => strings::lcs(db, lcs),
+            // Every key is written under the same lock, so no client can observe
+            // some of them set and the others not
+            Command::MGet(keys) => strings::mget(db, &keys),
+            Command::MSet(pairs) => strings::mset(db, pairs),
+            Command::MSetNx(pairs) => strings::msetnx(db, pairs),
+            Command::SetBit(key, offset, bit) => bitmaps::setbit(db, key, offset, bit),
+            Command::GetBit(key, offset) => bitmaps::getbit(db, &key, offset),
+            Command::BitCount(key, range) => bitmaps::bitcount(db, &key, range),
+            Command::BitPos(bit_pos) => bitmaps::bitpos(db, bit_pos),
+            Command::BitOp(operation, destination, keys) => {
+                bitmaps::bitop(db, operation, destination, keys)
+            }
+            Command::BitField(key, operations) | Command::BitFieldRo(key, operations) => {
+                bitmaps::bitfield(db, key, operations)
+            }
+            Command::PfAdd(key, elements) => hyperloglog::pfadd(db, key, elements),
+            Command::PfCount(keys) => hyperloglog::pfcount(db, &keys),
+            Command::PfMerge(destination, sources) => {
+                hyperloglog::pfmerge(db, destination, sources)
+            }
+            Command::PfDebug(subcommand, key) => hyperloglog::pfdebug(db, subcommand, &key),
+            Command::Push(end, key, elements) => lists::push(db, end, key, elements, true),
+            Command::PushX(end, key, elements) => lists::push(db, end, key, elements, false),
+            Command::Pop(end, key, count) => lists::pop(db, end, key, count),
+            Command::LLen(key) => lists::llen(db, &key),
+            Command::LRange(key, start, stop) => lists::lrange(db, &key, start, stop),
+            Command::LIndex(key, index) => lists::lindex(db, &key, index),
+            Command::LSet(key, index, element) => lists::lset(db, &key, index, element),
+            Command::LInsert(linsert) => lists::linsert(db, linsert),
+            Command::LRem(key, count, element) => lists::lrem(db, key, count, element),
+            Command::LTrim(key, start, stop) => lists::ltrim(db, key, start, stop),
+            Command::LPos(lpos) => lists::lpos(db, lpos),
+            Command::LMove(lmove) => lists::lmove(db, lmove),
+            Command::LMPop(lmpop) => lists::lmpop(db, lmpop),
+            Command::HSet(key, pairs) => hashes::hset(db, key, pairs),
+            Command::HSetNx(key, field, value) => hashes::hsetnx(db, key, field, value),
+            Command::HGet(key, field) => hashes::hget(db, &key, &field),
+            Command::HMGet(key, fields) => hashes::hmget(db, &key, &fields),
+            Command::HDel(key, fields) => hashes::hdel(db, &key, &fields),
+            Command::HExists(key, field) => hashes::hexists(db, &key, &field),
+            Command::HLen(key) => hashes::hlen(db, &key),
+            Command::HKeys(key) => hashes::hkeys(db, &key),
+            Command::HVals(key) => hashes::hvals(db, &key),
+            Command::HGetAll(key) => hashes::hgetall(db, &key),
+            Command::HIncrBy(key, field, increment) => hashes::hincrby(db, key, field, increment),
+            Command::HIncrByFloat(key, field, increment) => {
+                hashes::hincrbyfloat(db, key, field, increment)
+            }
+            Command::HStrlen(key, field) => hashes::hstrlen(db, &key, &field),
+            Command::HRandField(hrandfield) => hashes::hrandfield(db, hrandfield),
+            Command::HScan(scan) => hashes::hscan(db, scan),
+            Command::HExpire(hexpire) => hashes::hexpire(db, hexpire),
+            Command::HTtl(key, fields, unit) => hashes::httl(db, &key, &fields, unit),
+            Command::HExpireTime(key, fields, unit) => hashes::hexpiretime(db, &key, &fields, unit),
+            Command::HPersist(key, fields) => hashes::hpersist(db, &key, &fields),
+            Command::SAdd(key, members) => sets::sadd(db, key, members),
+            Command::SRem(key, members) => sets::srem(db, &key, &members),
+            Command::SIsMember(key, member) => sets::sismember(db, &key, &member),
+            Command::SMIsMember(key, members) => sets::smismember(db, &key, &members),
+            Command::SCard(key) => sets::scard(db, &key),
+            Command::SMembers(key) => sets::smembers(db, &key),
+            Command::SPop(key, count) => sets::spop(db, &key, count),
+            Command::SRandMember(key, count) => sets::srandmember(db, &key, count),
+            Command::SMove(source, destination, member) => {
+                sets::smove(db, &source, destination, member)
+            }
+            Command::SetOp(operation, keys) => sets::set_op(db, operation, &keys),
+            Command::SetOpStore(operation, destination, keys) => {
+                sets::set_op_store(db, operation, destination, &keys)
+            }
+            Command::SInterCard(keys, limit) => sets::sintercard(db, &keys, limit),
+            Command::SScan(scan) => sets::sscan(db, scan),
+            Command::ZAdd(zadd) => sorted_sets::zadd(db, zadd),
+            Command::ZRem(key, members) => sorted_sets::zrem(db, &key, &members),
+            Command::ZScore(key, member) => sorted_sets::zscore(db, &key, &member),
+            Command::ZMScore(key, members) => sorted_sets::zmscore(db, &key, &members),
+            Command::ZIncrBy(key, increment, member) => {
+                sorted_sets::zincrby(db, key, increment, member)
+            }
+            Command::ZCard(key) => sorted_sets::zcard(db, &key),
+            Command::ZCount(key, range) => sorted_sets::zcount(db, &key, &range),
+            Command::ZRank(zrank) => sorted_sets::zrank(db, zrank),
+            Command::ZRange(zrange) => sorted_sets::zrange(db, zrange),
+            Command::ZRangeStore(destination, zrange) => {
+                sorted_sets::zrangestore(db, destination, zrange)
+            }
+            Command::ZScan(scan) => sorted_sets::zscan(db, scan),
+            Command::ZSetOp(zset_op) => sorted_sets::zset_op(db, zset_op),
+            Command::ZSetOpStore(destination, zset_op) => {
+                sorted_sets::zset_op_store(db, destination, zset_op)
+            }
+            Command::ZInterCard(keys, limit) => sorted_sets::zintercard(db, keys, limit),
+            Command::ZRemRange(key, by) => sorted_sets::zremrange(db, &key, &by),
+            Command::ZPop(end, key, count) => sorted_sets::zpop(db, end, &key, count),
+            Command::ZMPop(zmpop) => sorted_sets::zmpop(db, zmpop),
+            Command::XAdd(xadd) => streams::xadd(db, xadd),
+            Command::XLen(key) => streams::xlen(db, &key),
+            Command::XRange(xrange) => streams::xrange(db, xrange),
+            Command::XDel(key, ids) => streams::xdel(db, &key, &ids),
+            Command::XTrim(key, trim) => streams::xtrim(db, &key, &trim),
+            Command::XSetId(xsetid) => streams::xsetid(db, xsetid),
+            Command::XRead(xread) => Ok(streams::xread(db, &xread)?.unwrap_or(Element::NullArray)),
+            Command::Keys(pattern) => Ok(Element::Array(
+                db.keys(&pattern)
+                    .into_iter()
+                    .map(Element::BulkString)
+                    .collect(),
+            )),
+            Command::Scan(scan) => {
+                let (cursor, keys) = db.scan(scan.cursor, scan.count);
+                let keys = keys
+                    .into_iter()
+                    .filter(|key| match db.get(key) {
+                        None => false,
+                        Some(value) => scan
+                            .value_type
+                            .is_none_or(|value_type| value_type == value.value_type()),
+                    })
+                    .filter(|key| {
+                        scan.pattern
+                            .as_ref()
+                            .is_none_or(|pattern| glob_match(pattern, key))
+                    })
+                    .map(Element::BulkString)
+                    .collect();
+                Ok(Element::Array(vec![
+                    Element::BulkString(cursor.to_string().into()),
+                    Element::Array(keys),
+                ]))
+            }
+            // Inside a transaction, blocking commands behave like their non
+            // blocking counterparts
+            Command::Blocking(blocking) => {
+                Ok(blocking::serve(db, &blocking.keys, &blocking.operation)?
+                    .unwrap_or_else(|| blocking.operation.timeout_reply()))
+            }
+            Command::Multi | Command::Exec | Command::Discard => {
+                unreachable!("transactions are handled by execute")
+            }
+        }
+    }
+
+    fn info(&self, db: &Keyspace, mut sections: Vec<InfoSection>) -> Result<Element> {
+        if sections.is_empty() {
+            sections = vec![
+                InfoSection::Replication,
+                InfoSection::Stats,
+                InfoSection::Keyspace,
+            ];
+        }
+
+        let mut info = Vec::new();
+        for section in sections {
+            info.push(match section {
+                InfoSection::Replication => {
+                    format!("# Replication\n{}", self.role.as_info_section())
+                }
+                InfoSection::Stats => format!(
+                    "# Stats
+expired_keys:{}
+expired_subkeys:{}
+expired_time_cap_reached_count:{}
+",
+                    db.stats().expired_keys,
+                    db.stats().expired_fields,
+                    db.stats().expired_time_cap_reached_count
+                ),
+                InfoSection::Keyspace if db.len() == 0 => "# Keyspace\n".to_string(),
+                InfoSection::Keyspace => format!(
+                    "# Keyspace\ndb0:keys={},expires={}\n",
+                    db.len(),
+                    db.volatile_len()
+                ),
+            });
+        }
+
+        Ok(Element::BulkString(info.join("\n").into()))
+    }
+
+    fn expire(&self, db: &mut Keyspace, expire: Expire) -> Result<Element> {
+        let Some(current) = db.get(&expire.key).map(Value::expiration) else {
+            return Ok(Element::Integer(0));
         };
 
-        println!("Result: {result:?}");
-        result
+        let current = current.map(unix_millis);
+        let allowed = expire
+            .conditions
+            .iter()
+            .all(|condition| condition.allows(current, expire.at));
+        if !allowed {
+            return Ok(Element::Integer(0));
+        }
+
+        if expire.at <= unix_millis(SystemTime::now()) {
+            db.remove(&expire.key);
+        } else {
+            db.set_expiration(&expire.key, Some(from_unix_millis(expire.at)));
+        }
+        Ok(Element::Integer(1))
+    }
+
+    fn hello(&self, client: &mut Client, hello: Hello) -> Result<Element> {
+        if let Some((username, _password)) = hello.auth {
+            // No passwords can be configured, so the default user accepts any
+            if username != "default" {
+                bail!(RedisError::WrongPass);
+            }
+        }
+        if let Some(protocol) = hello.protocol {
+            client.protocol = protocol;
+        }
+        if let Some(name) = hello.client_name {
+            client.name = Some(name);
+        }
+
+        let field = |name: &str| Element::BulkString(Bytes::copy_from_slice(name.as_bytes()));
+        Ok(Element::Map(vec![
+            (field("server"), field("redis")),
+            (field("version"), field("7.2.0")),
+            (field("proto"), Element::Integer(client.protocol.version())),
+            (field("id"), Element::Integer(client.id as i64)),
+            (field("mode"), field("standalone")),
+            (field("role"), field(self.role.name())),
+            (field("modules"), Element::Array(Vec::new())),
+        ]))
     }
 }
 
 impl Database<MasterInfo> {
-    pub fn new_master(port: usize) -> Self {
+    pub fn new_master(config: Config) -> Self {
         Database {
-            port,
+            config,
+            next_client_id: AtomicU64::new(1),
             db: Default::default(),
             role: MasterInfo {
                 replication_id: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string(),
@@ -185,12 +686,19 @@ impl Database<MasterInfo> {
 }
 
 impl Database<ReplicaInfo> {
-    pub async fn new_replica(port: usize, master_host: String, master_port: usize) -> Result<Self> {
+    pub async fn new_replica(
+        config: Config,
+        master_host: String,
+        master_port: usize,
+    ) -> Result<Self> {
         let mut database = Database {
-            port,
+            config,
+            next_client_id: AtomicU64::new(1),
             db: Default::default(),
             role: ReplicaInfo {
-                master: TcpStream::connect(format!("{master_host}:{master_port}")).await?,
+                master: Connection::new(
+                    TcpStream::connect(format!("{master_host}:{master_port}")).await?,
+                ),
             },
         };
 
@@ -203,7 +711,7 @@ impl Database<ReplicaInfo> {
         println!("Handshaking with master");
 
         self.send_command_to_master(Command::Ping(None)).await?;
-        self.send_command_to_master(Command::ReplConf(ReplOpt::ListeningPort(self.port)))
+        self.send_command_to_master(Command::ReplConf(ReplOpt::ListeningPort(self.config.port)))
             .await?;
         self.send_command_to_master(Command::ReplConf(ReplOpt::Capability))
             .await?;
@@ -220,11 +728,14 @@ impl Database<ReplicaInfo> {
         println!("Sending {command:?}");
         self.role
             .master
-            .write_all(&serialize_command(command))
+            .write_bytes(&serialize_command(command))
             .await?;
-        let mut response = [0; 1024];
-        let n = self.role.master.read(&mut response).await?;
-        let result = ElementParser::new(&response[..n]).parse();
+        let result = self
+            .role
+            .master
+            .read_element()
+            .await?
+            .ok_or(anyhow!("master closed the connection"));
 
         println!("Got {result:?}");
         result
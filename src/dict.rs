@@ -0,0 +1,400 @@
+use std::{
+    borrow::Borrow,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    mem,
+};
+
+use crate::utils::Rng;
+
+const MIN_BUCKETS: usize = 4;
+
+/// Most empty buckets visited by a single rehashing step, like in Redis.
+const MAX_EMPTY_VISITS: usize = 10;
+
+type Table<K, V> = Vec<Vec<(K, V)>>;
+
+/// Hash table with separate chaining, used instead of `HashMap` wherever we need
+/// access to its buckets: to sample random entries, and to iterate it with a
+/// stateless cursor.
+///
+/// The number of buckets is always a power of two. The table grows once it holds
+/// more entries than buckets, and shrinks once it's less than 1/8 full.
+///
+/// Like in Redis, resizing is incremental so that no single operation has to
+/// rehash a large table: a second table is allocated, and each insertion or
+/// removal moves a bucket of the first one to it. Until all of them have been
+/// moved, lookups search both tables, and new entries go to the second one.
+#[derive(Debug, Clone)]
+pub struct Dict<K, V> {
+    tables: [Table<K, V>; 2],
+    /// Index of the next bucket of the first table to move to the second one,
+    /// if a resize is in progress
+    rehash_index: Option<usize>,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<K, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        Dict {
+            tables: [empty_table(MIN_BUCKETS), Vec::new()],
+            rehash_index: None,
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+fn empty_table<K, V>(n_buckets: usize) -> Table<K, V> {
+    (0..n_buckets).map(|_| Vec::new()).collect()
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    /// The tables that may hold entries, the second one only while resizing.
+    fn tables(&self) -> &[Table<K, V>] {
+        match self.rehash_index {
+            Some(_) => &self.tables,
+            None => &self.tables[..1],
+        }
+    }
+
+    /// Finds the table and the position in its bucket of the entry for `key`.
+    fn locate<Q>(&self, key: &Q) -> Option<(usize, usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash(key);
+        self.tables()
+            .iter()
+            .enumerate()
+            .find_map(|(table, buckets)| {
+                let index = hash as usize & (buckets.len() - 1);
+                let position = buckets[index].iter().position(|(k, _)| k.borrow() == key)?;
+                Some((table, index, position))
+            })
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (table, index, position) = self.locate(key)?;
+        Some(&self.tables[table][index][position].1)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash_step();
+        let (table, index, position) = self.locate(key)?;
+        Some(&mut self.tables[table][index][position].1)
+    }
+
+    /// Inserts a value, returning the one previously stored for the key.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(existing) = self.get_mut(&key) {
+            return Some(mem::replace(existing, value));
+        }
+
+        // Entries are only added to the table being rehashed into
+        let table = usize::from(self.rehash_index.is_some());
+        let index = self.hash(&key) as usize & (self.tables[table].len() - 1);
+        self.tables[table][index].push((key, value));
+        self.len += 1;
+        if self.rehash_index.is_none() && self.len > self.tables[0].len() {
+            self.resize(self.tables[0].len() * 2);
+        }
+        None
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash_step();
+        let (table, index, position) = self.locate(key)?;
+        let entry = self.tables[table][index].swap_remove(position);
+        self.len -= 1;
+        self.shrink_if_sparse();
+        Some(entry)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.tables()
+            .iter()
+            .flatten()
+            .flat_map(|bucket| bucket.iter().map(|(k, v)| (k, v)))
+    }
+
+    /// Visits the entries of the bucket pointed to by `cursor`, and returns the
+    /// cursor to pass to the next call, or 0 once the whole table has been
+    /// visited. Iteration starts with a cursor of 0.
+    ///
+    /// Like Redis' `dictScan`, the cursor is incremented starting from its most
+    /// significant bit: since the table size is a power of two, after a resize
+    /// every bucket maps to a set of buckets that either all come before the
+    /// cursor or all after it. Every entry present for the whole iteration is
+    /// thus visited at least once, even if the table grows or shrinks between
+    /// calls, although some may be visited more than once.
+    ///
+    /// While resizing, the bucket of the smaller table is visited along with
+    /// every bucket of the larger one it maps to.
+    pub fn scan<'a>(&'a self, cursor: u64, mut visit: impl FnMut(&'a K, &'a V)) -> u64 {
+        let mut visit_bucket = |bucket: &'a Vec<(K, V)>| {
+            for (k, v) in bucket {
+                visit(k, v);
+            }
+        };
+
+        let (small, large) = match self.tables() {
+            [table] => (table, None),
+            [first, second] if first.len() <= second.len() => (first, Some(second)),
+            [first, second] => (second, Some(first)),
+            _ => unreachable!("there are one or two tables"),
+        };
+        let small_mask = (small.len() - 1) as u64;
+        visit_bucket(&small[(cursor & small_mask) as usize]);
+        let Some(large) = large else {
+            // Set the bits above the mask so that the increment carries over them
+            let cursor = (cursor | !small_mask).reverse_bits().wrapping_add(1);
+            return cursor.reverse_bits();
+        };
+
+        let large_mask = (large.len() - 1) as u64;
+        let mut cursor = cursor;
+        loop {
+            visit_bucket(&large[(cursor & large_mask) as usize]);
+            cursor = (cursor | !large_mask).reverse_bits().wrapping_add(1);
+            cursor = cursor.reverse_bits();
+            // Stop once the bits only in the larger mask have wrapped around, and
+            // carried over to the bits of the smaller mask
+            if cursor & (small_mask ^ large_mask) == 0 {
+                return cursor;
+            }
+        }
+    }
+
+    /// Visits buckets starting at `cursor`, until either `count` entries have
+    /// been collected or the whole table has been visited, and returns the cursor
+    /// to resume from along with the entries found. The work done in a sparse
+    /// table is bounded, so fewer entries may be returned before the end.
+    pub fn scan_entries(&self, mut cursor: u64, count: usize) -> (u64, Vec<(&K, &V)>) {
+        let mut entries = Vec::new();
+        let mut max_iterations = count.saturating_mul(10).max(1);
+        loop {
+            cursor = self.scan(cursor, |k, v| entries.push((k, v)));
+            max_iterations -= 1;
+            if cursor == 0 || max_iterations == 0 || entries.len() >= count {
+                return (cursor, entries);
+            }
+        }
+    }
+
+    /// Returns a random entry, or `None` if the table is empty.
+    pub fn random_entry(&self, rng: &mut Rng) -> Option<(&K, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        // The buckets of the first table that were already rehashed are empty
+        let start = self.rehash_index.unwrap_or(0);
+        let first_len = self.tables[0].len();
+        let n_buckets = self.tables().iter().map(Vec::len).sum::<usize>();
+        // The tables are never less than 1/8 full, so a non empty bucket turns up
+        // after a few attempts
+        loop {
+            let index = start + rng.below(n_buckets - start);
+            let bucket = match index.checked_sub(first_len) {
+                Some(index) => &self.tables[1][index],
+                None => &self.tables[0][index],
+            };
+            if !bucket.is_empty() {
+                let (k, v) = &bucket[rng.below(bucket.len())];
+                return Some((k, v));
+            }
+        }
+    }
+
+    fn shrink_if_sparse(&mut self) {
+        let n_buckets = self.tables[0].len();
+        if self.rehash_index.is_none() && n_buckets > MIN_BUCKETS && self.len * 8 < n_buckets {
+            self.resize((self.len.next_power_of_two()).max(MIN_BUCKETS));
+        }
+    }
+
+    /// Starts moving the entries to a table of `n_buckets` buckets.
+    fn resize(&mut self, n_buckets: usize) {
+        self.tables[1] = empty_table(n_buckets);
+        self.rehash_index = Some(0);
+        self.rehash_step();
+    }
+
+    /// Moves the entries of the next non empty bucket of the first table to the
+    /// second one, if a resize is in progress, visiting a bounded number of
+    /// empty buckets.
+    fn rehash_step(&mut self) {
+        let Some(mut index) = self.rehash_index else {
+            return;
+        };
+        let [old, new] = &mut self.tables;
+        let mut empty_visits = MAX_EMPTY_VISITS;
+        while index < old.len() && old[index].is_empty() && empty_visits > 0 {
+            index += 1;
+            empty_visits -= 1;
+        }
+        if index < old.len() && empty_visits > 0 {
+            let mask = new.len() - 1;
+            for (key, value) in mem::take(&mut old[index]) {
+                new[self.hasher.hash_one(&key) as usize & mask].push((key, value));
+            }
+            index += 1;
+        }
+
+        if index == old.len() {
+            self.tables[0] = mem::take(&mut self.tables[1]);
+            self.rehash_index = None;
+        } else {
+            self.rehash_index = Some(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+
+    #[test]
+    fn scan_with_a_count_of_zero_visits_a_bucket() {
+        let mut dict = Dict::new();
+        dict.insert(1, ());
+        let (cursor, _) = dict.scan_entries(0, 0);
+        assert_ne!(cursor, 0);
+    }
+
+    #[test]
+    fn scan_returns_every_key_present_while_resizing() {
+        let mut dict = Dict::new();
+        for key in 0..100 {
+            dict.insert(key, ());
+        }
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        let mut round = 0;
+        loop {
+            let (next, entries) = dict.scan_entries(cursor, 5);
+            seen.extend(entries.into_iter().map(|(&k, _)| k));
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+
+            // Alternately grow the table with temporary keys, and shrink it
+            // back by removing them
+            round += 1;
+            if round % 2 == 1 {
+                for key in 1000..1000 + 1000 * round {
+                    dict.insert(key, ());
+                }
+            } else {
+                let temporary: Vec<_> = dict
+                    .iter()
+                    .map(|(&k, _)| k)
+                    .filter(|&k| k >= 1000)
+                    .collect();
+                for key in temporary {
+                    dict.remove(&key);
+                }
+            }
+        }
+
+        assert!(round > 2, "the table was resized between calls");
+        assert!((0..100).all(|key| seen.contains(&key)));
+    }
+
+    #[test]
+    fn matches_a_hash_map_while_rehashing() {
+        let mut rng = Rng::new();
+        let mut dict = Dict::new();
+        let mut model = HashMap::new();
+        let mut rehashed = 0;
+        for i in 0..20_000 {
+            // Grow up to a few thousand keys, then shrink back
+            let key = rng.below(4000);
+            if (i / 5000) % 2 == 0 {
+                assert_eq!(dict.insert(key, i), model.insert(key, i));
+            } else {
+                assert_eq!(dict.remove(&key), model.remove(&key));
+            }
+            rehashed += usize::from(dict.rehash_index.is_some());
+
+            assert_eq!(dict.len(), model.len());
+            let probe = rng.below(4000);
+            assert_eq!(dict.get(&probe), model.get(&probe));
+            if let Some((k, v)) = dict.random_entry(&mut rng) {
+                assert_eq!(model.get(k), Some(v));
+            }
+        }
+        assert!(rehashed > 0, "some operations happened while rehashing");
+
+        let mut entries: Vec<_> = dict.iter().map(|(&k, &v)| (k, v)).collect();
+        let mut expected: Vec<_> = model.into_iter().collect();
+        entries.sort();
+        expected.sort();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn scan_visits_both_tables_while_rehashing() {
+        let mut dict = Dict::new();
+        for key in 0..1024 {
+            dict.insert(key, ());
+        }
+        // The next insertion starts growing the table
+        dict.insert(1024, ());
+        assert!(dict.rehash_index.is_some());
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            cursor = dict.scan(cursor, |&k, _| {
+                seen.insert(k);
+            });
+            if cursor == 0 {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 1025);
+    }
+}
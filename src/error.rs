@@ -0,0 +1,54 @@
+use thiserror::Error;
+
+/// Errors that are reported back to the client as an error reply instead of
+/// closing the connection. The message of each variant starts with the error
+/// prefix Redis uses for it, so that clients can tell them apart.
+///
+/// Any other error raised while parsing or executing a command is reported as a
+/// generic `ERR`.
+#[derive(Debug, Error)]
+pub enum RedisError {
+    #[error("ERR {0}")]
+    Err(String),
+    #[error("ERR Protocol error: {0}")]
+    Protocol(String),
+    #[error("ERR unknown command '{name}', with args beginning with: {args}")]
+    UnknownCommand { name: String, args: String },
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(&'static str),
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("WRONGTYPE Key is not a valid HyperLogLog string value.")]
+    NotHyperLogLog,
+    #[error("INVALIDOBJ Corrupted HLL object detected")]
+    CorruptedHyperLogLog,
+    #[error("NOPROTO unsupported protocol version")]
+    NoProto,
+    #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+    WrongPass,
+    // Authentication can't be configured yet
+    #[allow(dead_code)]
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+    #[error("READONLY You can't write against a read only replica.")]
+    ReadOnly,
+    #[error("EXECABORT Transaction discarded because of previous errors.")]
+    ExecAbort,
+}
+
+impl RedisError {
+    /// Message to send back to the client for any error raised while handling a
+    /// command.
+    pub fn reply_message(error: &anyhow::Error) -> String {
+        let message = match error.downcast_ref::<RedisError>() {
+            Some(error) => error.to_string(),
+            None => RedisError::Err(error.to_string()).to_string(),
+        };
+        // Error replies are single line
+        message.replace(['\r', '\n'], " ")
+    }
+}
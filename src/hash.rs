@@ -0,0 +1,130 @@
+use std::{collections::BTreeSet, time::SystemTime};
+
+use bytes::Bytes;
+
+use crate::{dict::Dict, utils::Rng};
+
+/// The fields of a hash, each of which may have its own expiration.
+///
+/// Expired fields aren't removed on their own: [`Hash::remove_expired`] must be
+/// called before accessing the hash, which the keyspace does, as expirations
+/// are kept sorted so that finding the expired fields doesn't require visiting
+/// every field.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: Dict<Bytes, Field>,
+    /// Fields with an expiration, sorted by it
+    expirations: BTreeSet<(SystemTime, Bytes)>,
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    value: Bytes,
+    expiration: Option<SystemTime>,
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Hash::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        self.fields.get(field).map(|field| &field.value)
+    }
+
+    /// Mutable access to the value of a field, which keeps its expiration.
+    pub fn get_mut(&mut self, field: &[u8]) -> Option<&mut Bytes> {
+        self.fields.get_mut(field).map(|field| &mut field.value)
+    }
+
+    /// Sets the value of a field, replacing the previous one and its
+    /// expiration.
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> Option<Bytes> {
+        let previous = self.remove(&field);
+        self.fields.insert(
+            field,
+            Field {
+                value,
+                expiration: None,
+            },
+        );
+        previous
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        let (field, Field { value, expiration }) = self.fields.remove_entry(field)?;
+        if let Some(expiration) = expiration {
+            self.expirations.remove(&(expiration, field));
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.fields.iter().map(|(field, f)| (field, &f.value))
+    }
+
+    pub fn random_entry(&self, rng: &mut Rng) -> Option<(&Bytes, &Bytes)> {
+        self.fields
+            .random_entry(rng)
+            .map(|(field, f)| (field, &f.value))
+    }
+
+    /// See [`Dict::scan_entries`].
+    pub fn scan_entries(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &Bytes)>) {
+        let (cursor, entries) = self.fields.scan_entries(cursor, count);
+        let entries = entries
+            .into_iter()
+            .map(|(field, f)| (field, &f.value))
+            .collect();
+        (cursor, entries)
+    }
+
+    /// The expiration of a field, or `None` if the field doesn't exist.
+    pub fn expiration(&self, field: &[u8]) -> Option<Option<SystemTime>> {
+        self.fields.get(field).map(|field| field.expiration)
+    }
+
+    /// Sets or clears the expiration of a field, returning whether the field
+    /// exists.
+    pub fn set_expiration(&mut self, field: &[u8], expiration: Option<SystemTime>) -> bool {
+        let Some(f) = self.fields.get_mut(field) else {
+            return false;
+        };
+        let name = Bytes::copy_from_slice(field);
+        if let Some(previous) = f.expiration {
+            self.expirations.remove(&(previous, name.clone()));
+        }
+        f.expiration = expiration;
+        if let Some(expiration) = expiration {
+            self.expirations.insert((expiration, name));
+        }
+        true
+    }
+
+    /// The earliest expiration of any field.
+    pub fn next_expiration(&self) -> Option<SystemTime> {
+        self.expirations.first().map(|(expiration, _)| *expiration)
+    }
+
+    /// Removes the fields that expired by `now`, returning how many there were.
+    pub fn remove_expired(&mut self, now: SystemTime) -> usize {
+        let mut removed = 0;
+        while let Some((expiration, field)) = self.expirations.first() {
+            if *expiration > now {
+                break;
+            }
+            let field = field.clone();
+            self.remove(&field);
+            removed += 1;
+        }
+        removed
+    }
+}
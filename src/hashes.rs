@@ -0,0 +1,543 @@
+//! Commands operating on hash values. Hashes are never left empty: the key is
+//! removed along with the last field.
+
+use std::time::SystemTime;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    error::RedisError,
+    hash::Hash,
+    keyspace::{Keyspace, Value},
+    long_double::LongDouble,
+    protocol::{Element, ElementScan, HExpire, HRandField, TimeUnit},
+    utils::{from_unix_millis, glob_match, parse_i64, unix_millis, Rng},
+};
+
+/// Sets the fields, replying with how many of them were added rather than
+/// updated.
+pub fn hset(db: &mut Keyspace, key: Bytes, pairs: Vec<(Bytes, Bytes)>) -> Result<Element> {
+    let hash = hash_for_write(db, key.clone())?;
+    let added = pairs
+        .into_iter()
+        .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+        .count();
+    // Setting a field clears its expiration
+    db.update_volatile_hash(&key);
+    Ok(Element::Integer(added as i64))
+}
+
+pub fn hsetnx(db: &mut Keyspace, key: Bytes, field: Bytes, value: Bytes) -> Result<Element> {
+    if let Some(hash) = db.get_hash(&key)? {
+        if hash.get(&field).is_some() {
+            return Ok(Element::Integer(0));
+        }
+    }
+    hash_for_write(db, key)?.insert(field, value);
+    Ok(Element::Integer(1))
+}
+
+pub fn hget(db: &mut Keyspace, key: &[u8], field: &[u8]) -> Result<Element> {
+    Ok(match db.get_hash(key)?.and_then(|hash| hash.get(field)) {
+        Some(value) => Element::BulkString(value.clone()),
+        None => Element::Null,
+    })
+}
+
+pub fn hmget(db: &mut Keyspace, key: &[u8], fields: &[Bytes]) -> Result<Element> {
+    let hash = db.get_hash(key)?;
+    Ok(Element::Array(
+        fields
+            .iter()
+            .map(|field| match hash.and_then(|hash| hash.get(field)) {
+                Some(value) => Element::BulkString(value.clone()),
+                None => Element::Null,
+            })
+            .collect(),
+    ))
+}
+
+pub fn hdel(db: &mut Keyspace, key: &[u8], fields: &[Bytes]) -> Result<Element> {
+    let Some(hash) = db.get_hash_mut(key)? else {
+        return Ok(Element::Integer(0));
+    };
+    let deleted = fields
+        .iter()
+        .filter(|field| hash.remove(field).is_some())
+        .count();
+    if hash.is_empty() {
+        db.remove(key);
+    } else {
+        db.update_volatile_hash(key);
+    }
+    Ok(Element::Integer(deleted as i64))
+}
+
+pub fn hexists(db: &mut Keyspace, key: &[u8], field: &[u8]) -> Result<Element> {
+    let exists = db.get_hash(key)?.and_then(|hash| hash.get(field)).is_some();
+    Ok(Element::Integer(exists as i64))
+}
+
+pub fn hlen(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let len = db.get_hash(key)?.map_or(0, Hash::len);
+    Ok(Element::Integer(len as i64))
+}
+
+pub fn hkeys(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let fields = db.get_hash(key)?.map_or_else(Vec::new, |hash| {
+        hash.iter()
+            .map(|(field, _)| Element::BulkString(field.clone()))
+            .collect()
+    });
+    Ok(Element::Array(fields))
+}
+
+pub fn hvals(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let values = db.get_hash(key)?.map_or_else(Vec::new, |hash| {
+        hash.iter()
+            .map(|(_, value)| Element::BulkString(value.clone()))
+            .collect()
+    });
+    Ok(Element::Array(values))
+}
+
+pub fn hgetall(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let pairs = db.get_hash(key)?.map_or_else(Vec::new, |hash| {
+        hash.iter()
+            .map(|(field, value)| {
+                (
+                    Element::BulkString(field.clone()),
+                    Element::BulkString(value.clone()),
+                )
+            })
+            .collect()
+    });
+    Ok(Element::Map(pairs))
+}
+
+pub fn hincrby(db: &mut Keyspace, key: Bytes, field: Bytes, increment: i64) -> Result<Element> {
+    let current = match db.get_hash(&key)?.and_then(|hash| hash.get(&field)) {
+        Some(value) => {
+            parse_i64(value).ok_or(RedisError::Err("hash value is not an integer".to_string()))?
+        }
+        None => 0,
+    };
+    let result = current.checked_add(increment).ok_or(RedisError::Err(
+        "increment or decrement would overflow".to_string(),
+    ))?;
+    update_field(db, key, field, result.to_string().into())?;
+    Ok(Element::Integer(result))
+}
+
+pub fn hincrbyfloat(
+    db: &mut Keyspace,
+    key: Bytes,
+    field: Bytes,
+    increment: LongDouble,
+) -> Result<Element> {
+    let current = match db.get_hash(&key)?.and_then(|hash| hash.get(&field)) {
+        Some(value) => LongDouble::parse(value)
+            .ok_or(RedisError::Err("hash value is not a float".to_string()))?,
+        None => LongDouble::ZERO,
+    };
+    let result = current + increment;
+    if !result.is_finite() {
+        bail!(RedisError::Err(
+            "increment would produce NaN or Infinity".to_string()
+        ));
+    }
+    let result = Bytes::from(result.to_string());
+    update_field(db, key, field, result.clone())?;
+    Ok(Element::BulkString(result))
+}
+
+pub fn hstrlen(db: &mut Keyspace, key: &[u8], field: &[u8]) -> Result<Element> {
+    let len = db
+        .get_hash(key)?
+        .and_then(|hash| hash.get(field))
+        .map_or(0, Bytes::len);
+    Ok(Element::Integer(len as i64))
+}
+
+/// Replies with random fields, see [`HRandField`].
+pub fn hrandfield(db: &mut Keyspace, hrandfield: HRandField) -> Result<Element> {
+    let hash = db.get_hash(&hrandfield.key)?;
+    let mut rng = Rng::new();
+    let Some(count) = hrandfield.count else {
+        return Ok(match hash.and_then(|hash| hash.random_entry(&mut rng)) {
+            Some((field, _)) => Element::BulkString(field.clone()),
+            None => Element::Null,
+        });
+    };
+    let Some(hash) = hash else {
+        return Ok(Element::Array(Vec::new()));
+    };
+
+    let entries: Vec<(&Bytes, &Bytes)> = if count < 0 {
+        (0..count.unsigned_abs())
+            .map_while(|_| hash.random_entry(&mut rng))
+            .collect()
+    } else {
+        // Partial Fisher-Yates shuffle, for distinct fields
+        let mut entries: Vec<_> = hash.iter().collect();
+        let count = (count as usize).min(entries.len());
+        for i in 0..count {
+            let j = i + rng.below(entries.len() - i);
+            entries.swap(i, j);
+        }
+        entries.truncate(count);
+        entries
+    };
+
+    let field = |field: &Bytes| Element::BulkString(field.clone());
+    Ok(if hrandfield.with_values {
+        Element::Pairs(
+            entries
+                .into_iter()
+                .map(|(f, value)| (field(f), field(value)))
+                .collect(),
+        )
+    } else {
+        Element::Array(entries.into_iter().map(|(f, _)| field(f)).collect())
+    })
+}
+
+/// Iterates the fields of a hash, see [`crate::dict::Dict::scan`] for the
+/// guarantees this provides.
+pub fn hscan(db: &mut Keyspace, scan: ElementScan) -> Result<Element> {
+    let (cursor, elements) = match db.get_hash(&scan.key)? {
+        Some(hash) => {
+            let (cursor, entries) = hash.scan_entries(scan.cursor, scan.count);
+            let mut elements = Vec::new();
+            for (field, value) in entries {
+                if let Some(pattern) = &scan.pattern {
+                    if !glob_match(pattern, field) {
+                        continue;
+                    }
+                }
+                elements.push(Element::BulkString(field.clone()));
+                if !scan.no_values {
+                    elements.push(Element::BulkString(value.clone()));
+                }
+            }
+            (cursor, elements)
+        }
+        None => (0, Vec::new()),
+    };
+    Ok(Element::Array(vec![
+        Element::BulkString(cursor.to_string().into()),
+        Element::Array(elements),
+    ]))
+}
+
+/// Sets the expiration of fields, replying for each of them with -2 if it
+/// doesn't exist, 0 if the condition isn't met, 1 if the expiration was set, or
+/// 2 if the field was deleted because the time is in the past.
+pub fn hexpire(db: &mut Keyspace, hexpire: HExpire) -> Result<Element> {
+    let Some(hash) = db.get_hash_mut(&hexpire.key)? else {
+        return Ok(per_field(&hexpire.fields, |_| -2));
+    };
+
+    let now = unix_millis(SystemTime::now());
+    let reply = per_field(&hexpire.fields, |field| {
+        let Some(current) = hash.expiration(field) else {
+            return -2;
+        };
+        let current = current.map(unix_millis);
+        if let Some(condition) = hexpire.condition {
+            if !condition.allows(current, hexpire.at) {
+                return 0;
+            }
+        }
+        if hexpire.at <= now {
+            hash.remove(field);
+            2
+        } else {
+            hash.set_expiration(field, Some(from_unix_millis(hexpire.at)));
+            1
+        }
+    });
+
+    if hash.is_empty() {
+        db.remove(&hexpire.key);
+    } else {
+        db.update_volatile_hash(&hexpire.key);
+    }
+    Ok(reply)
+}
+
+/// Replies for each field with its remaining time to live, -1 if it has no
+/// expiration, or -2 if it doesn't exist.
+pub fn httl(db: &mut Keyspace, key: &[u8], fields: &[Bytes], unit: TimeUnit) -> Result<Element> {
+    let hash = db.get_hash(key)?;
+    let now = unix_millis(SystemTime::now());
+    Ok(per_field(fields, |field| {
+        match hash.and_then(|hash| hash.expiration(field)) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(expiration)) => {
+                let ttl = unix_millis(expiration) - now;
+                match unit {
+                    TimeUnit::Seconds => (ttl + 999) / 1000,
+                    TimeUnit::Milliseconds => ttl,
+                }
+            }
+        }
+    }))
+}
+
+/// Replies for each field with the unix time at which it expires, -1 if it has
+/// no expiration, or -2 if it doesn't exist.
+pub fn hexpiretime(
+    db: &mut Keyspace,
+    key: &[u8],
+    fields: &[Bytes],
+    unit: TimeUnit,
+) -> Result<Element> {
+    let hash = db.get_hash(key)?;
+    Ok(per_field(fields, |field| {
+        match hash.and_then(|hash| hash.expiration(field)) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(expiration)) => match unit {
+                TimeUnit::Seconds => unix_millis(expiration) / 1000,
+                TimeUnit::Milliseconds => unix_millis(expiration),
+            },
+        }
+    }))
+}
+
+/// Removes the expiration of fields, replying for each of them with 1 if it
+/// had one, -1 if it didn't, or -2 if it doesn't exist.
+pub fn hpersist(db: &mut Keyspace, key: &[u8], fields: &[Bytes]) -> Result<Element> {
+    let mut hash = db.get_hash_mut(key)?;
+    let reply = per_field(fields, |field| {
+        let Some(hash) = hash.as_deref_mut() else {
+            return -2;
+        };
+        match hash.expiration(field) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(_)) => {
+                hash.set_expiration(field, None);
+                1
+            }
+        }
+    });
+    db.update_volatile_hash(key);
+    Ok(reply)
+}
+
+fn per_field(fields: &[Bytes], mut reply: impl FnMut(&Bytes) -> i64) -> Element {
+    Element::Array(
+        fields
+            .iter()
+            .map(|field| Element::Integer(reply(field)))
+            .collect(),
+    )
+}
+
+/// The hash stored at `key`, created empty if it doesn't exist. Callers must
+/// add fields to it, as hashes are never left empty.
+fn hash_for_write(db: &mut Keyspace, key: Bytes) -> Result<&mut Hash> {
+    if db.get_hash(&key)?.is_none() {
+        db.insert(key.clone(), Value::hash(Hash::new()));
+    }
+    Ok(db.get_hash_mut(&key)?.expect("inserted above"))
+}
+
+/// Sets the value of a field, keeping its expiration if it already exists.
+fn update_field(db: &mut Keyspace, key: Bytes, field: Bytes, value: Bytes) -> Result<()> {
+    match db.get_hash_mut(&key)?.and_then(|hash| hash.get_mut(&field)) {
+        Some(current) => *current = value,
+        None => {
+            hash_for_write(db, key)?.insert(field, value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::protocol::{Command, ExpireCondition};
+
+    use super::*;
+
+    fn parse_hexpire(args: &[&str]) -> Result<HExpire> {
+        let args = args
+            .iter()
+            .map(|arg| Element::BulkString(Bytes::copy_from_slice(arg.as_bytes())));
+        match Element::Array(args.collect()).try_into()? {
+            Command::HExpire(hexpire) => Ok(hexpire),
+            other => panic!("parsed {other:?}"),
+        }
+    }
+
+    fn integers(reply: Element) -> Vec<i64> {
+        let Element::Array(elements) = reply else {
+            panic!("replied {reply:?}");
+        };
+        elements
+            .into_iter()
+            .map(|element| match element {
+                Element::Integer(i) => i,
+                other => panic!("replied {other:?}"),
+            })
+            .collect()
+    }
+
+    /// A hash with a field `a` without expiration, and a field `b` expiring at
+    /// `at`.
+    fn volatile_hash(at: i64) -> Keyspace {
+        let mut db = Keyspace::default();
+        let value = Bytes::from_static(b"1");
+        let pairs = vec![(Bytes::from("a"), value.clone()), (Bytes::from("b"), value)];
+        hset(&mut db, Bytes::from("h"), pairs).unwrap();
+        let expire = HExpire {
+            key: Bytes::from("h"),
+            at,
+            condition: None,
+            fields: vec![Bytes::from("b")],
+        };
+        assert_eq!(integers(hexpire(&mut db, expire).unwrap()), [1]);
+        db
+    }
+
+    #[test]
+    fn hexpire_validates_its_arguments() {
+        let max = (1i64 << 48).to_string();
+        for (args, error) in [
+            (
+                &["hexpire", "h", "-1", "fields", "1", "f"][..],
+                "ERR invalid expire time, must be >= 0",
+            ),
+            (
+                &["hpexpireat", "h", "-1", "fields", "1", "f"],
+                "ERR invalid expire time, must be >= 0",
+            ),
+            (
+                &["hpexpireat", "h", &max, "fields", "1", "f"],
+                "ERR invalid expire time in 'hpexpireat' command",
+            ),
+            (
+                &["hexpire", "h", "9223372036854775807", "fields", "1", "f"],
+                "ERR invalid expire time in 'hexpire' command",
+            ),
+            (
+                &["hexpire", "h", "10", "fields", "0", "f"],
+                "ERR Parameter `numFields` should be greater than 0",
+            ),
+            (
+                &["hexpire", "h", "10", "fields", "2", "f"],
+                "ERR The `numfields` parameter must match the number of arguments",
+            ),
+            (
+                &["hexpire", "h", "10", "nx", "xx", "fields", "1", "f"],
+                "ERR Mandatory argument FIELDS is missing or not at the right position",
+            ),
+            (
+                &["hexpire", "h", "10", "1", "f"],
+                "ERR wrong number of arguments for 'hexpire' command",
+            ),
+        ] {
+            let parsed = parse_hexpire(args);
+            assert_eq!(parsed.unwrap_err().to_string(), error, "{args:?}");
+        }
+
+        let expire = parse_hexpire(&["hexpire", "h", "0", "gt", "fields", "1", "f"]).unwrap();
+        assert_eq!(expire.condition, Some(ExpireCondition::Gt));
+        assert_eq!(expire.fields, ["f"]);
+    }
+
+    #[test]
+    fn hexpire_checks_conditions() {
+        let at = unix_millis(SystemTime::now()) + 100_000;
+        let (later, sooner) = (at + 1000, at - 1000);
+        for (condition, new, replies) in [
+            (None, sooner, [1, 1, -2]),
+            (Some(ExpireCondition::Nx), later, [1, 0, -2]),
+            (Some(ExpireCondition::Xx), later, [0, 1, -2]),
+            // Fields without expiration never expire, which is later than any time
+            (Some(ExpireCondition::Gt), later, [0, 1, -2]),
+            (Some(ExpireCondition::Gt), sooner, [0, 0, -2]),
+            (Some(ExpireCondition::Lt), later, [1, 0, -2]),
+            (Some(ExpireCondition::Lt), sooner, [1, 1, -2]),
+        ] {
+            let mut db = volatile_hash(at);
+            let expire = HExpire {
+                key: Bytes::from("h"),
+                at: new,
+                condition,
+                fields: vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")],
+            };
+            let reply = integers(hexpire(&mut db, expire).unwrap());
+            assert_eq!(reply, replies, "{condition:?}");
+
+            let fields = [Bytes::from("a"), Bytes::from("b")];
+            let times = hexpiretime(&mut db, b"h", &fields, TimeUnit::Milliseconds).unwrap();
+            let expected = replies[..2].iter().zip([-1, at]);
+            let expected: Vec<_> = expected
+                .map(|(&reply, old)| if reply == 1 { new } else { old })
+                .collect();
+            assert_eq!(integers(times), expected, "{condition:?}");
+        }
+
+        // Hashes that don't exist have no fields
+        let expire = parse_hexpire(&["hexpire", "x", "10", "fields", "1", "f"]).unwrap();
+        let mut db = Keyspace::default();
+        assert_eq!(integers(hexpire(&mut db, expire).unwrap()), [-2]);
+    }
+
+    #[test]
+    fn deletes_the_key_once_its_last_field_expires() {
+        let now = unix_millis(SystemTime::now());
+        let mut db = volatile_hash(now + 100_000);
+        let expire_now = |fields: &[&str]| HExpire {
+            key: Bytes::from("h"),
+            at: now,
+            condition: None,
+            fields: fields
+                .iter()
+                .map(|&field| Bytes::from(field.to_string()))
+                .collect(),
+        };
+        assert_eq!(integers(hexpire(&mut db, expire_now(&["a"])).unwrap()), [2]);
+        assert_eq!(db.get_hash(b"h").unwrap().unwrap().len(), 1);
+        assert_eq!(integers(hexpire(&mut db, expire_now(&["b"])).unwrap()), [2]);
+        assert!(db.get(b"h").is_none());
+
+        // Fields expiring on their own delete the key as well
+        let soon = unix_millis(SystemTime::now()) + 100;
+        let mut db = volatile_hash(soon);
+        let expire = HExpire {
+            key: Bytes::from("h"),
+            at: soon,
+            condition: None,
+            fields: vec![Bytes::from("a")],
+        };
+        assert_eq!(integers(hexpire(&mut db, expire).unwrap()), [1]);
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(db.get(b"h").is_none());
+        assert_eq!(db.volatile_len(), 0);
+    }
+
+    #[test]
+    fn hincrbyfloat_rounds_like_redis() {
+        let mut db = Keyspace::default();
+        let (key, field) = (Bytes::from_static(b"h"), Bytes::from_static(b"f"));
+        hset(
+            &mut db,
+            key.clone(),
+            vec![(field.clone(), Bytes::from_static(b"0.1"))],
+        )
+        .unwrap();
+        let increment = LongDouble::parse(b"0.2").unwrap();
+        let reply = hincrbyfloat(&mut db, key.clone(), field.clone(), increment).unwrap();
+        assert!(matches!(reply, Element::BulkString(result) if result == "0.3"));
+        let hash = db.get_hash(&key).unwrap().unwrap();
+        assert_eq!(hash.get(&field).unwrap(), "0.3");
+    }
+}
@@ -0,0 +1,789 @@
+//! HyperLogLog commands, estimating the number of distinct elements added to a
+//! set in a fixed amount of memory.
+//!
+//! HyperLogLogs are stored as strings, in the exact same format Redis uses, so
+//! that their values can be moved between the two. The string starts with a
+//! header:
+//!
+//! ```text
+//! +------+---+-----+----------+
+//! | HYLL | E | N/U | Cardin.  |
+//! +------+---+-----+----------+
+//! ```
+//!
+//! The magic `HYLL`, the encoding (0 for dense, 1 for sparse), 3 unused bytes,
+//! and the last cardinality computed as a little endian integer, which is
+//! invalidated by setting the most significant bit of its last byte.
+//!
+//! The dense encoding follows with the 16384 registers, 6 bits each, starting
+//! from the least significant bits of each byte. The sparse encoding follows
+//! with runs of registers, each encoded by one of these opcodes:
+//!
+//! - `00xxxxxx`: `ZERO`, `xxxxxx + 1` registers set to zero.
+//! - `01xxxxxx yyyyyyyy`: `XZERO`, `xxxxxxyyyyyyyy + 1` registers set to zero.
+//! - `1vvvvvxx`: `VAL`, `xx + 1` registers set to `vvvvv + 1`.
+//!
+//! Registers above 32 can't be represented by the sparse encoding, in which
+//! case, or once the sparse representation grows too large, it's converted to
+//! dense.
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    error::RedisError,
+    keyspace::{Keyspace, Value},
+    protocol::{Element, PfDebug},
+    strings::edit,
+};
+
+/// Bits of the hash used to select a register.
+const P: u32 = 14;
+/// Bits of the hash used to count the length of the run of zeros.
+const Q: u32 = 64 - P;
+const REGISTERS: usize = 1 << P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+/// Sparse representations larger than this are converted to dense, matching
+/// Redis' default `hll-sparse-max-bytes`.
+const SPARSE_MAX_BYTES: usize = 3000;
+
+/// Adds elements, replying with 1 if the estimated cardinality may have changed.
+pub fn pfadd(db: &mut Keyspace, key: Bytes, elements: Vec<Bytes>) -> Result<Element> {
+    let mut updated = false;
+    if db.get(&key).is_none() {
+        db.insert(key.clone(), Value::string(create().into()));
+        updated = true;
+    }
+    let hll = db.get_mut(&key).expect("inserted above").as_string_mut()?;
+    validate(hll)?;
+
+    edit(hll, |hll| -> Result<()> {
+        for element in &elements {
+            let (index, count) = pattern_len(element);
+            updated |= set_register(hll, index, count)?;
+        }
+        if updated {
+            invalidate_cache(hll);
+        }
+        Ok(())
+    })?;
+
+    Ok(Element::Integer(updated as i64))
+}
+
+/// Replies with the estimated cardinality of the union of the HyperLogLogs.
+///
+/// With a single key the cardinality is cached in the header, so that it's
+/// only computed again after the HyperLogLog is modified.
+pub fn pfcount(db: &mut Keyspace, keys: &[Bytes]) -> Result<Element> {
+    if let [key] = keys {
+        let Some(value) = db.get_mut(key) else {
+            return Ok(Element::Integer(0));
+        };
+        let hll = value.as_string_mut()?;
+        validate(hll)?;
+
+        if hll[15] & 0x80 == 0 {
+            let cached = u64::from_le_bytes(hll[8..16].try_into().expect("8 bytes"));
+            return Ok(Element::Integer(cached as i64));
+        }
+        let cardinality = estimate(&histogram(hll)?);
+        edit(hll, |hll| {
+            hll[8..16].copy_from_slice(&cardinality.to_le_bytes());
+        });
+        return Ok(Element::Integer(cardinality as i64));
+    }
+
+    let mut max = vec![0; REGISTERS];
+    for key in keys {
+        if let Some(hll) = db.get_string(key)? {
+            validate(hll)?;
+            merge_registers(&mut max, hll)?;
+        }
+    }
+    let mut histogram = [0; 64];
+    for register in max {
+        histogram[register as usize] += 1;
+    }
+    Ok(Element::Integer(estimate(&histogram) as i64))
+}
+
+/// Stores the union of the HyperLogLogs, including the destination itself, in
+/// `destination`.
+pub fn pfmerge(db: &mut Keyspace, destination: Bytes, sources: Vec<Bytes>) -> Result<Element> {
+    let mut max = vec![0; REGISTERS];
+    // Start with a dense destination right away if any of the inputs is dense
+    let mut use_dense = false;
+    for key in std::iter::once(&destination).chain(&sources) {
+        if let Some(hll) = db.get_string(key)? {
+            validate(hll)?;
+            use_dense |= hll[4] == DENSE;
+            merge_registers(&mut max, hll)?;
+        }
+    }
+
+    if db.get(&destination).is_none() {
+        db.insert(destination.clone(), Value::string(create().into()));
+    }
+    let hll = db
+        .get_mut(&destination)
+        .expect("inserted above")
+        .as_string_mut()?;
+
+    edit(hll, |hll| -> Result<()> {
+        if use_dense {
+            to_dense(hll)?;
+        }
+        for (index, &count) in max.iter().enumerate() {
+            if count != 0 {
+                set_register(hll, index, count)?;
+            }
+        }
+        invalidate_cache(hll);
+        Ok(())
+    })?;
+
+    Ok(Element::SimpleString("OK".to_string()))
+}
+
+pub fn pfdebug(db: &mut Keyspace, subcommand: PfDebug, key: &[u8]) -> Result<Element> {
+    let Some(value) = db.get_mut(key) else {
+        bail!(RedisError::Err(
+            "The specified key does not exist".to_string()
+        ));
+    };
+    let hll = value.as_string_mut()?;
+    validate(hll)?;
+
+    match subcommand {
+        PfDebug::GetReg => {
+            edit(hll, to_dense)?;
+            Ok(Element::Array(
+                (0..REGISTERS)
+                    .map(|index| Element::Integer(get_dense(&hll[HEADER_LEN..], index) as i64))
+                    .collect(),
+            ))
+        }
+        PfDebug::Decode => {
+            if hll[4] != SPARSE {
+                bail!(RedisError::Err("HLL encoding is not sparse".to_string()));
+            }
+            let decoded: Vec<String> = decode_sparse(&hll[HEADER_LEN..])?
+                .into_iter()
+                .map(|opcode| match opcode {
+                    Opcode::Zero(len) => format!("z:{len}"),
+                    Opcode::XZero(len) => format!("Z:{len}"),
+                    Opcode::Val(value, len) => format!("v:{value},{len}"),
+                })
+                .collect();
+            Ok(Element::SimpleString(decoded.join(" ")))
+        }
+        PfDebug::Encoding => Ok(Element::SimpleString(
+            if hll[4] == DENSE { "dense" } else { "sparse" }.to_string(),
+        )),
+        PfDebug::ToDense => {
+            let converted = hll[4] == SPARSE;
+            edit(hll, to_dense)?;
+            Ok(Element::Integer(converted as i64))
+        }
+    }
+}
+
+/// An empty HyperLogLog, with the sparse encoding.
+fn create() -> Vec<u8> {
+    let mut hll = Vec::with_capacity(HEADER_LEN + 2);
+    hll.extend_from_slice(b"HYLL");
+    hll.push(SPARSE);
+    hll.extend_from_slice(&[0; 11]);
+    let mut remaining = REGISTERS;
+    while remaining > 0 {
+        let len = remaining.min(SPARSE_XZERO_MAX_LEN);
+        hll.extend_from_slice(&xzero(len));
+        remaining -= len;
+    }
+    hll
+}
+
+/// Checks that a string holds a HyperLogLog.
+fn validate(hll: &[u8]) -> Result<()> {
+    let valid = hll.len() >= HEADER_LEN
+        && hll.starts_with(b"HYLL")
+        && (hll[4] == SPARSE || (hll[4] == DENSE && hll.len() == DENSE_LEN));
+    if !valid {
+        bail!(RedisError::NotHyperLogLog);
+    }
+    Ok(())
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[15] |= 0x80;
+}
+
+/// The register an element falls into, and the length of the run of zeros in
+/// the rest of its hash, plus one.
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, 0xadc83b19);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // Make sure the count is at most Q + 1
+    let hash = (hash >> P) | (1 << Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+/// MurmurHash2, 64 bit version, reading blocks as little endian like Redis.
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut blocks = data.chunks_exact(8);
+    for block in &mut blocks {
+        let mut k = u64::from_le_bytes(block.try_into().expect("8 byte block"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Raises a register to `count`, returning whether it was lower.
+fn set_register(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool> {
+    if hll[4] == DENSE {
+        return Ok(set_dense(&mut hll[HEADER_LEN..], index, count));
+    }
+    set_sparse(hll, index, count)
+}
+
+fn get_dense(registers: &[u8], index: usize) -> u8 {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let low = registers[byte] as u16 >> shift;
+    let high = registers
+        .get(byte + 1)
+        .map_or(0, |&high| (high as u16) << (8 - shift));
+    ((low | high) & REGISTER_MAX as u16) as u8
+}
+
+/// Raises a register of the dense encoding, returning whether it was lower.
+fn set_dense(registers: &mut [u8], index: usize, count: u8) -> bool {
+    if get_dense(registers, index) >= count {
+        return false;
+    }
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    registers[byte] &= !((REGISTER_MAX as u16) << shift) as u8;
+    registers[byte] |= ((count as u16) << shift) as u8;
+    if shift + REGISTER_BITS > 8 {
+        registers[byte + 1] &= !(REGISTER_MAX >> (8 - shift));
+        registers[byte + 1] |= count >> (8 - shift);
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Opcode {
+    Zero(usize),
+    XZero(usize),
+    Val(u8, usize),
+}
+
+impl Opcode {
+    fn read(sparse: &[u8]) -> Result<Opcode> {
+        match sparse {
+            [op, ..] if op & 0x80 != 0 => {
+                Ok(Opcode::Val(((op >> 2) & 0x1f) + 1, (op & 0x3) as usize + 1))
+            }
+            [op, ..] if op & 0x40 == 0 => Ok(Opcode::Zero((op & 0x3f) as usize + 1)),
+            [op, next, ..] => Ok(Opcode::XZero(
+                (((op & 0x3f) as usize) << 8 | *next as usize) + 1,
+            )),
+            _ => bail!(RedisError::CorruptedHyperLogLog),
+        }
+    }
+
+    /// Number of registers covered.
+    fn len(&self) -> usize {
+        match *self {
+            Opcode::Zero(len) | Opcode::XZero(len) | Opcode::Val(_, len) => len,
+        }
+    }
+
+    /// Number of bytes taken by the opcode.
+    fn size(&self) -> usize {
+        match self {
+            Opcode::XZero(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+fn val(value: u8, len: usize) -> u8 {
+    0x80 | (value - 1) << 2 | (len - 1) as u8
+}
+
+fn xzero(len: usize) -> [u8; 2] {
+    let len = len - 1;
+    [0x40 | (len >> 8) as u8, (len & 0xff) as u8]
+}
+
+/// Opcodes encoding a run of `len` zero registers.
+fn zeros(len: usize) -> Vec<u8> {
+    if len > SPARSE_ZERO_MAX_LEN {
+        xzero(len).to_vec()
+    } else {
+        vec![(len - 1) as u8]
+    }
+}
+
+/// Decodes a sparse representation, checking that it covers every register.
+fn decode_sparse(mut sparse: &[u8]) -> Result<Vec<Opcode>> {
+    let mut opcodes = Vec::new();
+    let mut registers = 0;
+    while !sparse.is_empty() {
+        let opcode = Opcode::read(sparse)?;
+        registers += opcode.len();
+        sparse = &sparse[opcode.size()..];
+        opcodes.push(opcode);
+    }
+    if registers != REGISTERS {
+        bail!(RedisError::CorruptedHyperLogLog);
+    }
+    Ok(opcodes)
+}
+
+/// Raises a register of the sparse encoding, returning whether it was lower.
+/// The run containing the register is split in up to 3 runs, merging them with
+/// their neighbors when possible.
+///
+/// The HyperLogLog is converted to dense if the value is too large for the
+/// sparse encoding or if it would grow beyond [`SPARSE_MAX_BYTES`].
+fn set_sparse(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool> {
+    if count > SPARSE_VAL_MAX_VALUE {
+        return promote(hll, index, count);
+    }
+
+    // Locate the opcode covering the register
+    let mut position = HEADER_LEN;
+    let mut first = 0;
+    let mut previous = None;
+    let opcode = loop {
+        if position >= hll.len() {
+            bail!(RedisError::CorruptedHyperLogLog);
+        }
+        let opcode = Opcode::read(&hll[position..])?;
+        if index < first + opcode.len() {
+            break opcode;
+        }
+        previous = Some(position);
+        position += opcode.size();
+        first += opcode.len();
+    };
+    let last = first + opcode.len() - 1;
+
+    let mut sequence = Vec::with_capacity(5);
+    match opcode {
+        Opcode::Val(current, _) if current >= count => return Ok(false),
+        Opcode::Val(_, 1) | Opcode::Zero(1) => sequence.push(val(count, 1)),
+        Opcode::Zero(_) | Opcode::XZero(_) => {
+            if index != first {
+                sequence.extend_from_slice(&zeros(index - first));
+            }
+            sequence.push(val(count, 1));
+            if index != last {
+                sequence.extend_from_slice(&zeros(last - index));
+            }
+        }
+        Opcode::Val(current, _) => {
+            if index != first {
+                sequence.push(val(current, index - first));
+            }
+            sequence.push(val(count, 1));
+            if index != last {
+                sequence.push(val(current, last - index));
+            }
+        }
+    }
+
+    if sequence.len() > opcode.size()
+        && hll.len() + sequence.len() - opcode.size() > SPARSE_MAX_BYTES
+    {
+        return promote(hll, index, count);
+    }
+    hll.splice(position..position + opcode.size(), sequence);
+
+    // Merge adjacent runs of the same value, scanning up to 5 opcodes starting
+    // from the one before the update
+    let mut position = previous.unwrap_or(HEADER_LEN);
+    let mut scan = 5;
+    while position < hll.len() && scan > 0 {
+        scan -= 1;
+        match Opcode::read(&hll[position..])? {
+            Opcode::Val(value, len) => {
+                match hll.get(position + 1).map(|&next| Opcode::read(&[next])) {
+                    Some(Ok(Opcode::Val(next_value, next_len)))
+                        if next_value == value && len + next_len <= SPARSE_VAL_MAX_LEN =>
+                    {
+                        hll[position + 1] = val(value, len + next_len);
+                        hll.remove(position);
+                        // Try to merge the new run with the one on its right as well
+                    }
+                    _ => position += 1,
+                }
+            }
+            opcode => position += opcode.size(),
+        }
+    }
+    Ok(true)
+}
+
+/// Converts to dense to raise a register beyond what the sparse encoding allows.
+fn promote(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool> {
+    to_dense(hll)?;
+    set_dense(&mut hll[HEADER_LEN..], index, count);
+    Ok(true)
+}
+
+fn to_dense(hll: &mut Vec<u8>) -> Result<()> {
+    if hll[4] == DENSE {
+        return Ok(());
+    }
+
+    let mut dense = vec![0; DENSE_LEN];
+    dense[..HEADER_LEN].copy_from_slice(&hll[..HEADER_LEN]);
+    dense[4] = DENSE;
+    let mut index = 0;
+    for opcode in decode_sparse(&hll[HEADER_LEN..])? {
+        if let Opcode::Val(value, len) = opcode {
+            for index in index..index + len {
+                set_dense(&mut dense[HEADER_LEN..], index, value);
+            }
+        }
+        index += opcode.len();
+    }
+    *hll = dense;
+    Ok(())
+}
+
+/// Raises each of `max` to the value of the same register in the HyperLogLog.
+fn merge_registers(max: &mut [u8], hll: &[u8]) -> Result<()> {
+    if hll[4] == DENSE {
+        for (index, max) in max.iter_mut().enumerate() {
+            *max = (*max).max(get_dense(&hll[HEADER_LEN..], index));
+        }
+        return Ok(());
+    }
+
+    let mut index = 0;
+    for opcode in decode_sparse(&hll[HEADER_LEN..])? {
+        if let Opcode::Val(value, len) = opcode {
+            for max in &mut max[index..index + len] {
+                *max = (*max).max(value);
+            }
+        }
+        index += opcode.len();
+    }
+    Ok(())
+}
+
+/// Number of registers holding each value.
+fn histogram(hll: &[u8]) -> Result<[u32; 64]> {
+    let mut histogram = [0; 64];
+    if hll[4] == DENSE {
+        for index in 0..REGISTERS {
+            histogram[get_dense(&hll[HEADER_LEN..], index) as usize] += 1;
+        }
+    } else {
+        for opcode in decode_sparse(&hll[HEADER_LEN..])? {
+            match opcode {
+                Opcode::Zero(len) | Opcode::XZero(len) => histogram[0] += len as u32,
+                Opcode::Val(value, len) => histogram[value as usize] += len as u32,
+            }
+        }
+    }
+    Ok(histogram)
+}
+
+/// Estimates the cardinality from the histogram of the registers, using the
+/// estimator from "New cardinality estimation algorithms for HyperLogLog
+/// sketches" by Otmar Ertl, like Redis does.
+fn estimate(histogram: &[u32; 64]) -> u64 {
+    const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+    let m = REGISTERS as f64;
+
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for j in (1..=Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty HyperLogLog as created by Redis: sparse, with a cached
+    /// cardinality of 0, and a single `XZERO` covering every register.
+    const EMPTY: &[u8] = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x7f\xff";
+
+    fn set(db: &mut Keyspace, key: &'static [u8], value: Vec<u8>) {
+        db.insert(Bytes::from_static(key), Value::string(value.into()));
+    }
+
+    fn add(db: &mut Keyspace, key: &'static [u8], elements: impl Iterator<Item = String>) -> bool {
+        let elements = elements.map(Bytes::from).collect();
+        let reply = pfadd(db, Bytes::from_static(key), elements).unwrap();
+        matches!(reply, Element::Integer(1))
+    }
+
+    fn count(db: &mut Keyspace, keys: &[&'static [u8]]) -> Result<i64> {
+        let keys: Vec<Bytes> = keys.iter().map(|&key| Bytes::from_static(key)).collect();
+        match pfcount(db, &keys)? {
+            Element::Integer(count) => Ok(count),
+            other => panic!("unexpected reply {other:?}"),
+        }
+    }
+
+    fn debug(db: &mut Keyspace, subcommand: PfDebug, key: &[u8]) -> String {
+        match pfdebug(db, subcommand, key).unwrap() {
+            Element::SimpleString(reply) => reply,
+            other => panic!("unexpected reply {other:?}"),
+        }
+    }
+
+    /// The registers, read without converting the HyperLogLog to dense.
+    fn registers(db: &mut Keyspace, key: &[u8]) -> Vec<u8> {
+        let mut registers = vec![0; REGISTERS];
+        merge_registers(&mut registers, db.get_string(key).unwrap().unwrap()).unwrap();
+        registers
+    }
+
+    /// The registers of a HyperLogLog holding `elements`, computed directly.
+    fn expected_registers(elements: impl Iterator<Item = String>) -> Vec<u8> {
+        let mut registers = vec![0; REGISTERS];
+        for element in elements {
+            let (index, count) = pattern_len(element.as_bytes());
+            registers[index] = registers[index].max(count);
+        }
+        registers
+    }
+
+    fn elements(range: std::ops::Range<usize>) -> impl Iterator<Item = String> {
+        range.map(|i| format!("element:{i}"))
+    }
+
+    #[test]
+    fn creates_the_same_empty_hyperloglog_as_redis() {
+        assert_eq!(create(), EMPTY);
+
+        // Adding to a new key invalidates the cached cardinality
+        let mut db = Keyspace::default();
+        assert!(add(&mut db, b"hll", elements(0..0)));
+        let mut invalidated = EMPTY.to_vec();
+        invalidated[15] = 0x80;
+        assert_eq!(db.get_string(b"hll").unwrap().unwrap()[..], invalidated);
+        assert!(!add(&mut db, b"hll", elements(0..0)));
+    }
+
+    #[test]
+    fn decodes_redis_blobs() {
+        let mut db = Keyspace::default();
+        set(&mut db, b"empty", EMPTY.to_vec());
+        assert_eq!(count(&mut db, &[b"empty"]).unwrap(), 0);
+        assert_eq!(debug(&mut db, PfDebug::Decode, b"empty"), "Z:16384");
+
+        // Register 0 set to 3 and registers 1000 to 1002 set to 1, with the
+        // cached cardinality invalidated
+        let mut sparse = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80".to_vec();
+        sparse.extend_from_slice(b"\x88\x43\xe6\x82\x7c\x14");
+        let mut dense = b"HYLL\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80".to_vec();
+        dense.resize(DENSE_LEN, 0);
+        dense[HEADER_LEN] = 0x03;
+        dense[HEADER_LEN + 750] = 0x41;
+        dense[HEADER_LEN + 751] = 0x10;
+        set(&mut db, b"sparse", sparse);
+        set(&mut db, b"dense", dense);
+
+        let mut expected = vec![0; REGISTERS];
+        expected[0] = 3;
+        expected[1000..1003].fill(1);
+        assert_eq!(registers(&mut db, b"sparse"), expected);
+        assert_eq!(registers(&mut db, b"dense"), expected);
+        assert_eq!(
+            debug(&mut db, PfDebug::Decode, b"sparse"),
+            "v:3,1 Z:999 v:1,3 Z:15381"
+        );
+
+        assert_eq!(count(&mut db, &[b"sparse"]).unwrap(), 4);
+        assert_eq!(count(&mut db, &[b"dense"]).unwrap(), 4);
+        assert_eq!(count(&mut db, &[b"sparse", b"dense", b"empty"]).unwrap(), 4);
+        // The cardinality is now cached
+        let sparse = db.get_string(b"sparse").unwrap().unwrap();
+        assert_eq!(sparse[8..16], [4, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn caches_the_cardinality_like_redis() {
+        let mut db = Keyspace::default();
+        let abc = || ["a", "b", "c"].into_iter().map(String::from);
+        add(&mut db, b"hll", abc());
+        count(&mut db, &[b"hll"]).unwrap();
+        assert_eq!(db.get_string(b"hll").unwrap().unwrap()[15], 0);
+        assert!(!add(&mut db, b"hll", abc()));
+        assert_eq!(db.get_string(b"hll").unwrap().unwrap()[15], 0);
+        assert!(add(&mut db, b"hll", elements(0..3)));
+        assert_eq!(db.get_string(b"hll").unwrap().unwrap()[15], 0x80);
+    }
+
+    #[test]
+    fn detects_corrupted_blobs() {
+        let mut db = Keyspace::default();
+
+        // From Redis' test suite: enough `XZERO` opcodes for their lengths to
+        // overflow a 32 bit counter, followed by a `VAL` out of bounds
+        let mut overflow = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        for _ in 0..131072 + 1000 {
+            overflow.extend_from_slice(b"\x7f\xff");
+        }
+        overflow.push(0xff);
+        set(&mut db, b"overflow", overflow);
+        // Like Redis, a single key replies with the cached cardinality
+        assert_eq!(count(&mut db, &[b"overflow"]).unwrap(), 0);
+        assert!(count(&mut db, &[b"overflow", b"overflow"]).is_err());
+        assert!(pfdebug(&mut db, PfDebug::GetReg, b"overflow").is_err());
+
+        add(&mut db, b"tail", elements(0..3));
+        let tail = db.get_mut(b"tail").unwrap().as_string_mut().unwrap();
+        edit(tail, |tail| tail.extend_from_slice(b"hello"));
+        assert!(count(&mut db, &[b"tail"]).is_err());
+
+        let mut magic = EMPTY.to_vec();
+        magic[..4].copy_from_slice(b"0123");
+        set(&mut db, b"magic", magic);
+        assert!(count(&mut db, &[b"magic"]).is_err());
+
+        // A sparse HyperLogLog claiming to be dense
+        let mut encoding = EMPTY.to_vec();
+        encoding[4] = DENSE;
+        set(&mut db, b"encoding", encoding);
+        assert!(count(&mut db, &[b"encoding"]).is_err());
+    }
+
+    #[test]
+    fn promotes_from_sparse_to_dense() {
+        let mut db = Keyspace::default();
+        let mut n = 0;
+        while n < 20000 {
+            add(&mut db, b"hll", elements(n..n + 100));
+            n += 100;
+
+            let count = count(&mut db, &[b"hll"]).unwrap() as f64;
+            assert!(
+                (count - n as f64).abs() < count / 100.0 * 5.0,
+                "{count} for {n}"
+            );
+            let encoding = debug(&mut db, PfDebug::Encoding, b"hll");
+            if n < 1000 {
+                assert_eq!(encoding, "sparse");
+            } else if n > 10000 {
+                assert_eq!(encoding, "dense");
+            }
+            if n % 1000 == 0 {
+                assert_eq!(
+                    registers(&mut db, b"hll"),
+                    expected_registers(elements(0..n))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn merges_sparse_and_dense_hyperloglogs() {
+        let mut db = Keyspace::default();
+        add(&mut db, b"small", elements(0..100));
+        add(&mut db, b"other", elements(50..300));
+        add(&mut db, b"large", elements(200..20200));
+        assert_eq!(debug(&mut db, PfDebug::Encoding, b"small"), "sparse");
+        assert_eq!(debug(&mut db, PfDebug::Encoding, b"large"), "dense");
+
+        let sources = vec![Bytes::from_static(b"small"), Bytes::from_static(b"other")];
+        pfmerge(&mut db, Bytes::from_static(b"sparse"), sources).unwrap();
+        assert_eq!(debug(&mut db, PfDebug::Encoding, b"sparse"), "sparse");
+        assert_eq!(
+            registers(&mut db, b"sparse"),
+            expected_registers(elements(0..300))
+        );
+        assert_eq!(
+            count(&mut db, &[b"sparse"]).unwrap(),
+            count(&mut db, &[b"small", b"other"]).unwrap()
+        );
+
+        // The destination is merged too
+        let sources = vec![Bytes::from_static(b"large")];
+        pfmerge(&mut db, Bytes::from_static(b"sparse"), sources).unwrap();
+        assert_eq!(debug(&mut db, PfDebug::Encoding, b"sparse"), "dense");
+        assert_eq!(
+            registers(&mut db, b"sparse"),
+            expected_registers(elements(0..20200))
+        );
+        let count = count(&mut db, &[b"sparse"]).unwrap() as f64;
+        assert!((count - 20200.0).abs() < count / 100.0 * 5.0);
+    }
+}
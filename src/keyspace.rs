@@ -0,0 +1,517 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    blocking::Blocked,
+    dict::Dict,
+    error::RedisError,
+    hash::Hash,
+    protocol::ValueType,
+    quicklist::QuickList,
+    set::Set,
+    sorted_set::SortedSet,
+    stream::Stream,
+    utils::{glob_match, Rng},
+};
+
+/// A value stored in the keyspace: data of one of the supported types, and the
+/// time at which it expires.
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub data: Data,
+    expiration: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Data {
+    String(Bytes),
+    List(QuickList),
+    Hash(Hash),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    pub fn new(data: Data) -> Self {
+        Value {
+            data,
+            expiration: None,
+        }
+    }
+
+    pub fn string(string: Bytes) -> Self {
+        Value::new(Data::String(string))
+    }
+
+    pub fn list(list: QuickList) -> Self {
+        Value::new(Data::List(list))
+    }
+
+    pub fn hash(hash: Hash) -> Self {
+        Value::new(Data::Hash(hash))
+    }
+
+    pub fn set(set: Set) -> Self {
+        Value::new(Data::Set(set))
+    }
+
+    pub fn sorted_set(sorted_set: SortedSet) -> Self {
+        Value::new(Data::SortedSet(sorted_set))
+    }
+
+    pub fn stream(stream: Stream) -> Self {
+        Value::new(Data::Stream(stream))
+    }
+
+    pub fn with_expiration(self, expiration: Option<SystemTime>) -> Self {
+        Value { expiration, ..self }
+    }
+
+    pub fn expiration(&self) -> Option<SystemTime> {
+        self.expiration
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self.data {
+            Data::String(_) => ValueType::String,
+            Data::List(_) => ValueType::List,
+            Data::Hash(_) => ValueType::Hash,
+            Data::Set(_) => ValueType::Set,
+            Data::SortedSet(_) => ValueType::SortedSet,
+            Data::Stream(_) => ValueType::Stream,
+        }
+    }
+
+    /// The string held by the value, failing with `WRONGTYPE` for any other type.
+    pub fn as_string(&self) -> Result<&Bytes> {
+        match &self.data {
+            Data::String(string) => Ok(string),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    pub fn as_string_mut(&mut self) -> Result<&mut Bytes> {
+        match &mut self.data {
+            Data::String(string) => Ok(string),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    /// The list held by the value, failing with `WRONGTYPE` for any other type.
+    pub fn as_list(&self) -> Result<&QuickList> {
+        match &self.data {
+            Data::List(list) => Ok(list),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Result<&mut QuickList> {
+        match &mut self.data {
+            Data::List(list) => Ok(list),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    /// The hash held by the value, failing with `WRONGTYPE` for any other type.
+    pub fn as_hash(&self) -> Result<&Hash> {
+        match &self.data {
+            Data::Hash(hash) => Ok(hash),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    pub fn as_hash_mut(&mut self) -> Result<&mut Hash> {
+        match &mut self.data {
+            Data::Hash(hash) => Ok(hash),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    /// The set held by the value, failing with `WRONGTYPE` for any other type.
+    pub fn as_set(&self) -> Result<&Set> {
+        match &self.data {
+            Data::Set(set) => Ok(set),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    pub fn as_set_mut(&mut self) -> Result<&mut Set> {
+        match &mut self.data {
+            Data::Set(set) => Ok(set),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    /// The sorted set held by the value, failing with `WRONGTYPE` for any other
+    /// type.
+    pub fn as_sorted_set(&self) -> Result<&SortedSet> {
+        match &self.data {
+            Data::SortedSet(sorted_set) => Ok(sorted_set),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    pub fn as_sorted_set_mut(&mut self) -> Result<&mut SortedSet> {
+        match &mut self.data {
+            Data::SortedSet(sorted_set) => Ok(sorted_set),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    /// The stream held by the value, failing with `WRONGTYPE` for any other type.
+    pub fn as_stream(&self) -> Result<&Stream> {
+        match &self.data {
+            Data::Stream(stream) => Ok(stream),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    pub fn as_stream_mut(&mut self) -> Result<&mut Stream> {
+        match &mut self.data {
+            Data::Stream(stream) => Ok(stream),
+            _ => bail!(RedisError::WrongType),
+        }
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        match self.expiration {
+            None => false,
+            Some(expiration) => now > expiration,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub expired_keys: u64,
+    /// Hash fields removed because they expired
+    pub expired_fields: u64,
+    pub expired_time_cap_reached_count: u64,
+}
+
+/// All the keys stored by the server.
+///
+/// Expired keys are removed lazily, when they are accessed, and by
+/// [`Keyspace::active_expire_cycle`], which samples the keys that have an
+/// expiration so that the ones that are never accessed again don't pile up.
+#[derive(Debug)]
+pub struct Keyspace {
+    entries: Dict<Bytes, Value>,
+    /// Keys with an expiration, for the active expire cycle to sample from
+    volatile: Dict<Bytes, ()>,
+    /// Hashes with fields that have an expiration, for the active expire cycle
+    /// to sample from
+    volatile_hashes: Dict<Bytes, ()>,
+    rng: Rng,
+    stats: Stats,
+    blocked: Blocked,
+}
+
+impl Default for Keyspace {
+    fn default() -> Self {
+        Keyspace {
+            entries: Dict::new(),
+            volatile: Dict::new(),
+            volatile_hashes: Dict::new(),
+            rng: Rng::new(),
+            stats: Stats::default(),
+            blocked: Blocked::default(),
+        }
+    }
+}
+
+impl Keyspace {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Clients blocked on keys of this keyspace.
+    pub fn blocked(&mut self) -> &mut Blocked {
+        &mut self.blocked
+    }
+
+    /// Removes the key if it has expired, returning whether it did. Expired hash
+    /// fields are removed as well, along with the key if none are left.
+    fn expire_if_needed(&mut self, key: &[u8], now: SystemTime) -> bool {
+        match self.entries.get(key) {
+            Some(value) if value.is_expired(now) => {
+                self.remove(key);
+                self.stats.expired_keys += 1;
+                true
+            }
+            Some(Value {
+                data: Data::Hash(_),
+                ..
+            }) => {
+                self.expire_fields_if_needed(key, now);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the expired fields of a hash, returning whether there were any.
+    fn expire_fields_if_needed(&mut self, key: &[u8], now: SystemTime) -> bool {
+        let Some(Value {
+            data: Data::Hash(hash),
+            ..
+        }) = self.entries.get_mut(key)
+        else {
+            self.volatile_hashes.remove(key);
+            return false;
+        };
+        match hash.next_expiration() {
+            None => {
+                self.volatile_hashes.remove(key);
+                return false;
+            }
+            Some(expiration) if expiration > now => return false,
+            Some(_) => {}
+        }
+
+        self.stats.expired_fields += hash.remove_expired(now) as u64;
+        if hash.is_empty() {
+            self.remove(key);
+        } else if hash.next_expiration().is_none() {
+            self.volatile_hashes.remove(key);
+        }
+        true
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Option<&Value> {
+        self.expire_if_needed(key, SystemTime::now());
+        self.entries.get(key)
+    }
+
+    /// The string stored at `key`, failing with `WRONGTYPE` if the key holds
+    /// another type.
+    pub fn get_string(&mut self, key: &[u8]) -> Result<Option<&Bytes>> {
+        self.get(key).map(Value::as_string).transpose()
+    }
+
+    /// The list stored at `key`, failing with `WRONGTYPE` if the key holds
+    /// another type.
+    pub fn get_list(&mut self, key: &[u8]) -> Result<Option<&QuickList>> {
+        self.get(key).map(Value::as_list).transpose()
+    }
+
+    pub fn get_list_mut(&mut self, key: &[u8]) -> Result<Option<&mut QuickList>> {
+        self.get_mut(key).map(Value::as_list_mut).transpose()
+    }
+
+    /// The hash stored at `key`, failing with `WRONGTYPE` if the key holds
+    /// another type.
+    pub fn get_hash(&mut self, key: &[u8]) -> Result<Option<&Hash>> {
+        self.get(key).map(Value::as_hash).transpose()
+    }
+
+    pub fn get_hash_mut(&mut self, key: &[u8]) -> Result<Option<&mut Hash>> {
+        self.get_mut(key).map(Value::as_hash_mut).transpose()
+    }
+
+    /// The set stored at `key`, failing with `WRONGTYPE` if the key holds
+    /// another type.
+    pub fn get_set(&mut self, key: &[u8]) -> Result<Option<&Set>> {
+        self.get(key).map(Value::as_set).transpose()
+    }
+
+    pub fn get_set_mut(&mut self, key: &[u8]) -> Result<Option<&mut Set>> {
+        self.get_mut(key).map(Value::as_set_mut).transpose()
+    }
+
+    /// The sorted set stored at `key`, failing with `WRONGTYPE` if the key holds
+    /// another type.
+    pub fn get_sorted_set(&mut self, key: &[u8]) -> Result<Option<&SortedSet>> {
+        self.get(key).map(Value::as_sorted_set).transpose()
+    }
+
+    pub fn get_sorted_set_mut(&mut self, key: &[u8]) -> Result<Option<&mut SortedSet>> {
+        self.get_mut(key).map(Value::as_sorted_set_mut).transpose()
+    }
+
+    /// The stream stored at `key`, failing with `WRONGTYPE` if the key holds
+    /// another type.
+    pub fn get_stream(&mut self, key: &[u8]) -> Result<Option<&Stream>> {
+        self.get(key).map(Value::as_stream).transpose()
+    }
+
+    pub fn get_stream_mut(&mut self, key: &[u8]) -> Result<Option<&mut Stream>> {
+        self.get_mut(key).map(Value::as_stream_mut).transpose()
+    }
+
+    /// The values stored at each of `keys`, to be accessed all at once.
+    pub fn get_many(&mut self, keys: &[Bytes]) -> Vec<Option<&Value>> {
+        let now = SystemTime::now();
+        for key in keys {
+            self.expire_if_needed(key, now);
+        }
+        keys.iter().map(|key| self.entries.get(key)).collect()
+    }
+
+    /// The sets stored at each of `keys`, to be accessed all at once, failing
+    /// with `WRONGTYPE` if any of them holds another type.
+    pub fn get_sets(&mut self, keys: &[Bytes]) -> Result<Vec<Option<&Set>>> {
+        self.get_many(keys)
+            .into_iter()
+            .map(|value| value.map(Value::as_set).transpose())
+            .collect()
+    }
+
+    /// Mutable access to a value. Its expiration can only be changed through
+    /// [`Keyspace::set_expiration`].
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut Value> {
+        self.expire_if_needed(key, SystemTime::now());
+        self.entries.get_mut(key)
+    }
+
+    /// All the keys that haven't expired and match `pattern`.
+    pub fn keys(&mut self, pattern: &[u8]) -> Vec<Bytes> {
+        let now = SystemTime::now();
+        self.entries
+            .iter()
+            .filter(|(key, value)| !value.is_expired(now) && glob_match(pattern, key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Visits buckets of the table starting at `cursor`, until either `count` keys
+    /// have been collected or the whole table has been visited, and returns the
+    /// cursor to resume from along with the keys found. See [`Dict::scan`] for the
+    /// guarantees this provides.
+    ///
+    /// The keys returned may have expired, and may not be there anymore by the
+    /// time they're accessed.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        let (cursor, entries) = self.entries.scan_entries(cursor, count);
+        (
+            cursor,
+            entries.into_iter().map(|(key, _)| key.clone()).collect(),
+        )
+    }
+
+    /// Stores a value, replacing the previous one and its expiration.
+    pub fn insert(&mut self, key: Bytes, value: Value) -> Option<Value> {
+        if value.expiration.is_some() {
+            self.volatile.insert(key.clone(), ());
+        } else {
+            self.volatile.remove(&key);
+        }
+        match &value.data {
+            Data::Hash(hash) if hash.next_expiration().is_some() => {
+                self.volatile_hashes.insert(key.clone(), ());
+            }
+            _ => {
+                self.volatile_hashes.remove(&key);
+            }
+        }
+        self.blocked.signal_ready(&key);
+        self.entries.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<Value> {
+        self.volatile.remove(key);
+        self.volatile_hashes.remove(key);
+        self.entries.remove(key)
+    }
+
+    /// Sets or clears the expiration of a key, returning whether the key exists.
+    pub fn set_expiration(&mut self, key: &[u8], expiration: Option<SystemTime>) -> bool {
+        let Some(value) = self.get_mut(key) else {
+            return false;
+        };
+        value.expiration = expiration;
+        if expiration.is_some() {
+            self.volatile.insert(Bytes::copy_from_slice(key), ());
+        } else {
+            self.volatile.remove(key);
+        }
+        true
+    }
+
+    /// Registers a hash whose fields had their expiration changed, so that the
+    /// active expire cycle samples it only as long as some fields expire.
+    pub fn update_volatile_hash(&mut self, key: &[u8]) {
+        match self.entries.get(key) {
+            Some(Value {
+                data: Data::Hash(hash),
+                ..
+            }) if hash.next_expiration().is_some() => {
+                self.volatile_hashes.insert(Bytes::copy_from_slice(key), ());
+            }
+            _ => {
+                self.volatile_hashes.remove(key);
+            }
+        }
+    }
+
+    /// Removes expired keys by sampling the keys that have an expiration, the
+    /// same way Redis' active expire cycle does: as long as a large enough share
+    /// of the sampled keys turns out to be expired, there are likely many more,
+    /// so keep sampling until `time_limit` runs out. Hashes with fields that have
+    /// an expiration are then sampled the same way, to remove their expired
+    /// fields.
+    ///
+    /// `effort` goes from 1 to 10, and trades CPU for memory: higher efforts
+    /// sample more keys per iteration and tolerate fewer expired keys.
+    pub fn active_expire_cycle(&mut self, effort: u32, time_limit: Duration) {
+        let effort = effort.clamp(1, 10) as usize - 1;
+        let keys_per_loop = 20 + 20 / 4 * effort;
+        let acceptable_stale_percent = 10 - effort;
+        let start = Instant::now();
+
+        for fields in [false, true] {
+            loop {
+                let now = SystemTime::now();
+                let index = if fields {
+                    &self.volatile_hashes
+                } else {
+                    &self.volatile
+                };
+                let to_sample = keys_per_loop.min(index.len());
+                if to_sample == 0 {
+                    break;
+                }
+
+                let mut expired = 0;
+                for _ in 0..to_sample {
+                    let index = if fields {
+                        &self.volatile_hashes
+                    } else {
+                        &self.volatile
+                    };
+                    let Some((key, _)) = index.random_entry(&mut self.rng) else {
+                        break;
+                    };
+                    let key = key.clone();
+                    let reclaimed = if fields {
+                        self.expire_fields_if_needed(&key, now)
+                    } else {
+                        self.expire_if_needed(&key, now)
+                    };
+                    if reclaimed {
+                        expired += 1;
+                    }
+                }
+
+                if expired * 100 <= to_sample * acceptable_stale_percent {
+                    break;
+                }
+                if start.elapsed() > time_limit {
+                    self.stats.expired_time_cap_reached_count += 1;
+                    return;
+                }
+            }
+        }
+    }
+}
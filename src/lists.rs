@@ -0,0 +1,310 @@
+//! Commands operating on list values. Lists are never left empty: the key is
+//! removed along with the last element.
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    error::RedisError,
+    keyspace::{Keyspace, Value},
+    protocol::{Element, LInsert, LMPop, LMove, LPos, ListEnd},
+    quicklist::QuickList,
+};
+
+/// Pushes elements one after the other, so that pushing to the left leaves
+/// them in reverse order. Unless `create` is set, nothing is pushed to keys
+/// that don't exist.
+pub fn push(
+    db: &mut Keyspace,
+    end: ListEnd,
+    key: Bytes,
+    elements: Vec<Bytes>,
+    create: bool,
+) -> Result<Element> {
+    let list = match db.get_list_mut(&key)? {
+        Some(list) => list,
+        None if !create => return Ok(Element::Integer(0)),
+        None => {
+            db.insert(key.clone(), Value::list(QuickList::new()));
+            db.get_list_mut(&key)?.expect("inserted above")
+        }
+    };
+
+    for element in elements {
+        match end {
+            ListEnd::Left => list.push_front(element),
+            ListEnd::Right => list.push_back(element),
+        }
+    }
+    let len = list.len();
+    db.blocked().signal_ready(&key);
+    Ok(Element::Integer(len as i64))
+}
+
+/// Replies with a single element, or with an array of up to `count` elements
+/// if given.
+pub fn pop(db: &mut Keyspace, end: ListEnd, key: Bytes, count: Option<usize>) -> Result<Element> {
+    if db.get_list(&key)?.is_none() {
+        return Ok(match count {
+            Some(_) => Element::NullArray,
+            None => Element::Null,
+        });
+    }
+
+    let mut elements = pop_elements(db, &key, end, count.unwrap_or(1))?;
+    Ok(match count {
+        Some(_) => Element::Array(elements.into_iter().map(Element::BulkString).collect()),
+        None => Element::BulkString(elements.pop().expect("lists are never empty")),
+    })
+}
+
+pub fn llen(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let len = db.get_list(key)?.map_or(0, QuickList::len);
+    Ok(Element::Integer(len as i64))
+}
+
+/// Replies with the elements from `start` to `stop`, both inclusive. Negative
+/// indexes count from the tail of the list.
+pub fn lrange(db: &mut Keyspace, key: &[u8], start: i64, stop: i64) -> Result<Element> {
+    let Some(list) = db.get_list(key)? else {
+        return Ok(Element::Array(Vec::new()));
+    };
+    let elements = match resolve_range(list.len(), start, stop) {
+        Some((start, stop)) => list
+            .iter_from(start)
+            .take(stop - start + 1)
+            .cloned()
+            .map(Element::BulkString)
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(Element::Array(elements))
+}
+
+pub fn lindex(db: &mut Keyspace, key: &[u8], index: i64) -> Result<Element> {
+    let element = db
+        .get_list(key)?
+        .and_then(|list| list.get(resolve_index(list.len(), index)?));
+    Ok(match element {
+        Some(element) => Element::BulkString(element.clone()),
+        None => Element::Null,
+    })
+}
+
+pub fn lset(db: &mut Keyspace, key: &[u8], index: i64, element: Bytes) -> Result<Element> {
+    let Some(list) = db.get_list_mut(key)? else {
+        bail!(RedisError::Err("no such key".to_string()));
+    };
+    let Some(current) = resolve_index(list.len(), index).and_then(|index| list.get_mut(index))
+    else {
+        bail!(RedisError::Err("index out of range".to_string()));
+    };
+    *current = element;
+    Ok(Element::SimpleString("OK".to_string()))
+}
+
+/// Inserts an element next to the first occurrence of the pivot, replying with
+/// the length of the list, or -1 if the pivot wasn't found.
+pub fn linsert(db: &mut Keyspace, linsert: LInsert) -> Result<Element> {
+    let Some(list) = db.get_list_mut(&linsert.key)? else {
+        return Ok(Element::Integer(0));
+    };
+    let Some(position) = list.iter().position(|element| *element == linsert.pivot) else {
+        return Ok(Element::Integer(-1));
+    };
+
+    let index = if linsert.before {
+        position
+    } else {
+        position + 1
+    };
+    list.insert(index, linsert.element);
+    Ok(Element::Integer(list.len() as i64))
+}
+
+/// Removes up to `count` occurrences of an element starting from the head, or
+/// from the tail if `count` is negative. All of them are removed if `count` is
+/// 0.
+pub fn lrem(db: &mut Keyspace, key: Bytes, count: i64, element: Bytes) -> Result<Element> {
+    let Some(list) = db.get_list_mut(&key)? else {
+        return Ok(Element::Integer(0));
+    };
+
+    let limit = match count {
+        0 => usize::MAX,
+        count => count.unsigned_abs() as usize,
+    };
+    let mut removed = 0;
+    let keep = |candidate: &&Bytes| {
+        if removed < limit && **candidate == element {
+            removed += 1;
+            false
+        } else {
+            true
+        }
+    };
+    if count >= 0 {
+        *list = list.iter().filter(keep).cloned().collect();
+    } else {
+        let mut kept: Vec<Bytes> = list.iter().rev().filter(keep).cloned().collect();
+        kept.reverse();
+        *list = kept.into_iter().collect();
+    }
+
+    remove_if_empty(db, &key);
+    Ok(Element::Integer(removed as i64))
+}
+
+/// Keeps only the elements from `start` to `stop`, both inclusive.
+pub fn ltrim(db: &mut Keyspace, key: Bytes, start: i64, stop: i64) -> Result<Element> {
+    let Some(list) = db.get_list_mut(&key)? else {
+        return Ok(Element::SimpleString("OK".to_string()));
+    };
+
+    match resolve_range(list.len(), start, stop) {
+        Some((start, stop)) => list.trim(start, stop),
+        None => *list = QuickList::new(),
+    }
+    remove_if_empty(db, &key);
+    Ok(Element::SimpleString("OK".to_string()))
+}
+
+/// Replies with the index of the matching elements, see [`LPos`].
+pub fn lpos(db: &mut Keyspace, lpos: LPos) -> Result<Element> {
+    let Some(list) = db.get_list(&lpos.key)? else {
+        return Ok(match lpos.count {
+            Some(_) => Element::Array(Vec::new()),
+            None => Element::Null,
+        });
+    };
+
+    let len = list.len();
+    let max_len = if lpos.max_len == 0 { len } else { lpos.max_len };
+    let limit = match lpos.count {
+        None => 1,
+        Some(0) => usize::MAX,
+        Some(count) => count,
+    };
+    let skip = lpos.rank.unsigned_abs() as usize - 1;
+
+    let elements: Box<dyn Iterator<Item = (usize, &Bytes)>> = if lpos.rank > 0 {
+        Box::new(list.iter().enumerate())
+    } else {
+        Box::new(list.iter().rev().enumerate().map(|(i, e)| (len - 1 - i, e)))
+    };
+    let positions: Vec<usize> = elements
+        .take(max_len)
+        .filter(|(_, element)| **element == lpos.element)
+        .skip(skip)
+        .take(limit)
+        .map(|(index, _)| index)
+        .collect();
+
+    Ok(match lpos.count {
+        Some(_) => Element::Array(
+            positions
+                .into_iter()
+                .map(|index| Element::Integer(index as i64))
+                .collect(),
+        ),
+        None => match positions.first() {
+            Some(&index) => Element::Integer(index as i64),
+            None => Element::Null,
+        },
+    })
+}
+
+/// Atomically pops an element from one list and pushes it to another, which
+/// may be the same one, replying with the element.
+pub fn lmove(db: &mut Keyspace, lmove: LMove) -> Result<Element> {
+    let Some(list) = db.get_list_mut(&lmove.source)? else {
+        return Ok(Element::Null);
+    };
+    if lmove.source == lmove.destination {
+        // Rotate the list in place, which also keeps its expiration
+        let element = match lmove.from {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        }
+        .expect("lists are never empty");
+        match lmove.to {
+            ListEnd::Left => list.push_front(element.clone()),
+            ListEnd::Right => list.push_back(element.clone()),
+        }
+        return Ok(Element::BulkString(element));
+    }
+    // Fail before popping anything if the element can't be pushed
+    db.get_list(&lmove.destination)?;
+
+    let element = pop_elements(db, &lmove.source, lmove.from, 1)?
+        .pop()
+        .expect("lists are never empty");
+    push(db, lmove.to, lmove.destination, vec![element.clone()], true)?;
+    Ok(Element::BulkString(element))
+}
+
+/// Pops up to `count` elements from the first non empty list, replying with
+/// its key and the elements.
+pub fn lmpop(db: &mut Keyspace, lmpop: LMPop) -> Result<Element> {
+    for key in lmpop.keys {
+        if db.get_list(&key)?.is_some() {
+            let elements = pop_elements(db, &key, lmpop.end, lmpop.count)?;
+            return Ok(Element::Array(vec![
+                Element::BulkString(key),
+                Element::Array(elements.into_iter().map(Element::BulkString).collect()),
+            ]));
+        }
+    }
+    Ok(Element::NullArray)
+}
+
+/// Pops up to `count` elements from a list, removing the key if it's left
+/// empty.
+pub fn pop_elements(
+    db: &mut Keyspace,
+    key: &[u8],
+    end: ListEnd,
+    count: usize,
+) -> Result<Vec<Bytes>> {
+    let Some(list) = db.get_list_mut(key)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut elements = Vec::with_capacity(count.min(list.len()));
+    while elements.len() < count {
+        let element = match end {
+            ListEnd::Left => list.pop_front(),
+            ListEnd::Right => list.pop_back(),
+        };
+        match element {
+            Some(element) => elements.push(element),
+            None => break,
+        }
+    }
+
+    remove_if_empty(db, key);
+    Ok(elements)
+}
+
+fn remove_if_empty(db: &mut Keyspace, key: &[u8]) {
+    if let Ok(Some(list)) = db.get_list(key) {
+        if list.is_empty() {
+            db.remove(key);
+        }
+    }
+}
+
+/// Resolves an index that may count from the tail of the list.
+fn resolve_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// Resolves a range of indexes that may count from the tail of the list,
+/// clamping it to the list, or `None` if it's empty.
+pub fn resolve_range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
@@ -0,0 +1,608 @@
+//! Software implementation of the x87 80 bit extended precision floating point
+//! numbers that Redis uses as `long double` to compute `INCRBYFLOAT`, so that
+//! results are rounded, and formatted, exactly like Redis does on x86.
+
+use std::{fmt, ops::Add};
+
+/// Longest string Redis parses as a long double, its `MAX_LONG_DOUBLE_CHARS`.
+const MAX_LEN: usize = 5 * 1024;
+
+/// Exponents of the least significant bit of the mantissa: the smallest one,
+/// shared by subnormal numbers, and the largest one of finite numbers.
+const MIN_EXPONENT: i64 = -16382 - 63;
+const MAX_EXPONENT: i64 = 16383 - 63;
+
+/// Decimal exponent beyond which any non zero number overflows, or underflows
+/// to zero, whatever its digits.
+const MAX_DECIMAL_EXPONENT: i64 = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LongDouble {
+    negative: bool,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    /// `mantissa * 2^exponent`, where the mantissa has its most significant bit
+    /// set unless the exponent is the smallest one
+    Finite {
+        mantissa: u64,
+        exponent: i64,
+    },
+    Infinite,
+    Nan,
+}
+
+impl LongDouble {
+    pub const ZERO: LongDouble = LongDouble {
+        negative: false,
+        kind: Kind::Finite {
+            mantissa: 0,
+            exponent: MIN_EXPONENT,
+        },
+    };
+
+    /// Parses a number the way Redis' `string2ld` does, with `strtold`: decimal
+    /// or hexadecimal, possibly infinite, but never NaN, surrounded by
+    /// whitespace, or out of range.
+    pub fn parse(raw: &[u8]) -> Option<LongDouble> {
+        if raw.is_empty() || raw.len() >= MAX_LEN {
+            return None;
+        }
+        let (negative, unsigned) = match raw[0] {
+            b'-' => (true, &raw[1..]),
+            b'+' => (false, &raw[1..]),
+            _ => (false, raw),
+        };
+        let kind = if unsigned.eq_ignore_ascii_case(b"inf")
+            || unsigned.eq_ignore_ascii_case(b"infinity")
+        {
+            Kind::Infinite
+        } else if let Some(hex) = unsigned
+            .strip_prefix(b"0x")
+            .or_else(|| unsigned.strip_prefix(b"0X"))
+        {
+            parse_hex(hex)?
+        } else {
+            parse_decimal(unsigned)?
+        };
+        Some(LongDouble { negative, kind })
+    }
+
+    pub fn is_finite(&self) -> bool {
+        matches!(self.kind, Kind::Finite { .. })
+    }
+
+    fn infinite(negative: bool) -> LongDouble {
+        LongDouble {
+            negative,
+            kind: Kind::Infinite,
+        }
+    }
+}
+
+impl Add for LongDouble {
+    type Output = LongDouble;
+
+    fn add(self, other: LongDouble) -> LongDouble {
+        let (a, b) = match (self.kind, other.kind) {
+            (Kind::Nan, _) | (_, Kind::Nan) => {
+                return LongDouble {
+                    kind: Kind::Nan,
+                    ..self
+                }
+            }
+            (Kind::Infinite, Kind::Infinite) if self.negative != other.negative => {
+                return LongDouble {
+                    negative: false,
+                    kind: Kind::Nan,
+                }
+            }
+            (Kind::Infinite, _) => return self,
+            (_, Kind::Infinite) => return other,
+            (
+                Kind::Finite {
+                    mantissa: a,
+                    exponent: a_exponent,
+                },
+                Kind::Finite {
+                    mantissa: b,
+                    exponent: b_exponent,
+                },
+            ) => ((a, a_exponent), (b, b_exponent)),
+        };
+        if b.0 == 0 {
+            // Only the sum of two negative zeros is negative
+            let negative = if a.0 == 0 {
+                self.negative && other.negative
+            } else {
+                self.negative
+            };
+            return LongDouble { negative, ..self };
+        }
+        if a.0 == 0 {
+            return other;
+        }
+
+        // Align both mantissas on an exponent leaving 63 bits below the largest
+        // one, so that the sum fits and the bits of the smaller one that don't
+        // fit are too far down to matter but for rounding
+        let exponent = a.1.max(b.1) - 63;
+        let mut sticky = false;
+        let mut align = |(mantissa, mantissa_exponent): (u64, i64)| {
+            let shift = mantissa_exponent - exponent;
+            if shift >= 0 {
+                u128::from(mantissa) << shift
+            } else if shift > -128 {
+                let shift = -shift;
+                sticky |= u128::from(mantissa) & ((1 << shift) - 1) != 0;
+                u128::from(mantissa) >> shift
+            } else {
+                sticky = true;
+                0
+            }
+        };
+        let (a, b) = (align(a), align(b));
+
+        if self.negative == other.negative {
+            return round(self.negative, a + b, exponent, sticky);
+        }
+        // The bits of the smaller operand that didn't fit are subtracted from
+        // the larger one by borrowing a unit that they don't make up for
+        let (negative, larger, smaller) = if a >= b {
+            (self.negative, a, b)
+        } else {
+            (other.negative, b, a)
+        };
+        let difference = larger - smaller - u128::from(sticky);
+        if difference == 0 && !sticky {
+            return LongDouble::ZERO;
+        }
+        round(negative, difference, exponent, sticky)
+    }
+}
+
+/// Formats the number like Redis' `ld2string` in its human mode: with `%.17Lf`,
+/// without the trailing zeros after the decimal point, nor the sign of zero.
+impl fmt::Display for LongDouble {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (mantissa, exponent) = match self.kind {
+            Kind::Finite { mantissa, exponent } => (mantissa, exponent),
+            Kind::Infinite if self.negative => return f.write_str("-inf"),
+            Kind::Infinite => return f.write_str("inf"),
+            Kind::Nan => return f.write_str("nan"),
+        };
+
+        // The digits of the number times 10^17, rounded to the nearest integer,
+        // or to the even one on ties
+        let mut digits = BigUint::from(mantissa);
+        digits.mul_add_small(1_000_000_000, 0);
+        digits.mul_add_small(100_000_000, 0);
+        if exponent >= 0 {
+            digits.shl(exponent as u64);
+        } else {
+            let shift = exponent.unsigned_abs();
+            let below_half = digits.shr(shift - 1);
+            let half = digits.bit(0);
+            digits.shr(1);
+            if half && (below_half || digits.bit(0)) {
+                digits.mul_add_small(1, 1);
+            }
+        }
+
+        let digits = format!("{:0>18}", digits.to_decimal());
+        let (integer, fraction) = digits.split_at(digits.len() - 17);
+        let fraction = fraction.trim_end_matches('0');
+        if integer == "0" && fraction.is_empty() {
+            return f.write_str("0");
+        }
+        if self.negative {
+            f.write_str("-")?;
+        }
+        f.write_str(integer)?;
+        if !fraction.is_empty() {
+            write!(f, ".{fraction}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the digits and exponent of a decimal number, rounding it to the
+/// nearest long double.
+fn parse_decimal(raw: &[u8]) -> Option<Kind> {
+    let (significand, exponent) = match raw.iter().position(|&b| b == b'e' || b == b'E') {
+        Some(position) => (&raw[..position], Some(&raw[position + 1..])),
+        None => (raw, None),
+    };
+    let (integer, fraction) = match significand.iter().position(|&b| b == b'.') {
+        Some(position) => (&significand[..position], &significand[position + 1..]),
+        None => (significand, &b""[..]),
+    };
+    if integer.is_empty() && fraction.is_empty()
+        || !integer.iter().chain(fraction).all(u8::is_ascii_digit)
+    {
+        return None;
+    }
+    let exponent = match exponent {
+        Some(exponent) => parse_exponent(exponent)?,
+        None => 0,
+    };
+
+    let digits: Vec<u8> = integer
+        .iter()
+        .chain(fraction)
+        .copied()
+        .skip_while(|&b| b == b'0')
+        .collect();
+    if digits.is_empty() {
+        return Some(Kind::Finite {
+            mantissa: 0,
+            exponent: MIN_EXPONENT,
+        });
+    }
+    // The number is the digits times 10^exponent
+    let exponent = exponent - fraction.len() as i64;
+    let magnitude = digits.len() as i64 + exponent;
+    if !(-MAX_DECIMAL_EXPONENT..=MAX_DECIMAL_EXPONENT).contains(&magnitude) {
+        return None;
+    }
+
+    let mut value = BigUint::default();
+    for chunk in digits.chunks(9) {
+        let chunk_value = chunk
+            .iter()
+            .fold(0, |value, &b| value * 10 + u32::from(b - b'0'));
+        value.mul_add_small(10u32.pow(chunk.len() as u32), chunk_value);
+    }
+
+    let kind = if exponent >= 0 {
+        for _ in 0..exponent {
+            value.mul_add_small(10, 0);
+        }
+        round_big(false, value, 0, false).kind
+    } else {
+        // Dividing by 10^n is dividing by 5^n and 2^n, the digits are first
+        // shifted so that the quotient keeps enough bits to be rounded
+        let n = exponent.unsigned_abs();
+        let shift = (66 + n * 2323 / 1000 + 2).saturating_sub(value.bit_len());
+        value.shl(shift);
+        let mut sticky = false;
+        for _ in 0..n / 13 {
+            sticky |= value.div_rem_small(5u32.pow(13)) != 0;
+        }
+        sticky |= value.div_rem_small(5u32.pow((n % 13) as u32)) != 0;
+        round_big(false, value, -(shift as i64) - n as i64, sticky).kind
+    };
+    // Like `strtold`, report numbers out of range, that `string2ld` rejects
+    match kind {
+        Kind::Finite { mantissa: 0, .. } | Kind::Infinite => None,
+        kind => Some(kind),
+    }
+}
+
+/// Parses the hexadecimal digits and binary exponent following `0x`.
+fn parse_hex(raw: &[u8]) -> Option<Kind> {
+    let (significand, exponent) = match raw.iter().position(|&b| b == b'p' || b == b'P') {
+        Some(position) => (&raw[..position], Some(&raw[position + 1..])),
+        None => (raw, None),
+    };
+    let (integer, fraction) = match significand.iter().position(|&b| b == b'.') {
+        Some(position) => (&significand[..position], &significand[position + 1..]),
+        None => (significand, &b""[..]),
+    };
+    if integer.is_empty() && fraction.is_empty()
+        || !integer.iter().chain(fraction).all(u8::is_ascii_hexdigit)
+    {
+        return None;
+    }
+    let exponent = match exponent {
+        Some(exponent) => parse_exponent(exponent)?,
+        None => 0,
+    };
+
+    let mut value = BigUint::default();
+    for &b in integer.iter().chain(fraction) {
+        let digit = (b as char).to_digit(16).expect("checked above");
+        value.mul_add_small(16, digit);
+    }
+    if value.is_zero() {
+        return Some(Kind::Finite {
+            mantissa: 0,
+            exponent: MIN_EXPONENT,
+        });
+    }
+    let exponent = exponent - 4 * fraction.len() as i64;
+    if exponent.abs() > 4 * MAX_DECIMAL_EXPONENT + 4 * MAX_LEN as i64 {
+        return None;
+    }
+    match round_big(false, value, exponent, false).kind {
+        Kind::Finite { mantissa: 0, .. } | Kind::Infinite => None,
+        kind => Some(kind),
+    }
+}
+
+/// Parses an exponent, saturating it well past the point where the number
+/// overflows, or underflows.
+fn parse_exponent(raw: &[u8]) -> Option<i64> {
+    let (negative, digits) = match raw.first() {
+        Some(b'-') => (true, &raw[1..]),
+        Some(b'+') => (false, &raw[1..]),
+        _ => (false, raw),
+    };
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let exponent = digits.iter().fold(0i64, |exponent, &b| {
+        (exponent * 10 + i64::from(b - b'0')).min(1_000_000)
+    });
+    Some(if negative { -exponent } else { exponent })
+}
+
+/// Rounds `value * 2^exponent` to the nearest long double, or to the one with
+/// an even mantissa on ties. `sticky` tells whether the value was truncated,
+/// and is only set when it has more bits than the mantissa.
+fn round_big(
+    negative: bool,
+    mut value: BigUint,
+    mut exponent: i64,
+    mut sticky: bool,
+) -> LongDouble {
+    let len = value.bit_len();
+    if len > 128 {
+        sticky |= value.shr(len - 128);
+        exponent += (len - 128) as i64;
+    }
+    round(negative, value.low_u128(), exponent, sticky)
+}
+
+fn round(negative: bool, value: u128, exponent: i64, sticky: bool) -> LongDouble {
+    if value == 0 {
+        return LongDouble {
+            negative,
+            ..LongDouble::ZERO
+        };
+    }
+    let len = i64::from(128 - value.leading_zeros());
+    let shift = (len - 64).max(MIN_EXPONENT - exponent);
+
+    let (mantissa, exponent) = if shift <= 0 {
+        // Exact, normalized as far as the smallest exponent allows
+        let shift = (64 - len).min(exponent - MIN_EXPONENT);
+        ((value << shift) as u64, exponent - shift)
+    } else {
+        let (kept, half, below_half) = match shift {
+            1..=127 => (
+                value >> shift,
+                value >> (shift - 1) & 1 == 1,
+                value & ((1 << (shift - 1)) - 1) != 0,
+            ),
+            128 => (0, value >> 127 == 1, value & (u128::MAX >> 1) != 0),
+            _ => (0, false, true),
+        };
+        let mut mantissa = kept;
+        let mut exponent = exponent + shift;
+        if half && (below_half || sticky || mantissa & 1 == 1) {
+            mantissa += 1;
+            if mantissa == 1 << 64 {
+                mantissa >>= 1;
+                exponent += 1;
+            }
+        }
+        (mantissa as u64, exponent)
+    };
+    if exponent > MAX_EXPONENT {
+        return LongDouble::infinite(negative);
+    }
+    LongDouble {
+        negative,
+        kind: Kind::Finite { mantissa, exponent },
+    }
+}
+
+/// Unsigned integer of arbitrary size, stored as 32 bit limbs starting from the
+/// least significant one, and only as large as needed for exact conversions
+/// between decimal strings and long doubles.
+#[derive(Debug, Clone, Default)]
+struct BigUint(Vec<u32>);
+
+impl From<u64> for BigUint {
+    fn from(n: u64) -> Self {
+        let mut big = BigUint(vec![n as u32, (n >> 32) as u32]);
+        big.trim();
+        big
+    }
+}
+
+impl BigUint {
+    fn trim(&mut self) {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bit_len(&self) -> u64 {
+        match self.0.last() {
+            Some(last) => 32 * self.0.len() as u64 - u64::from(last.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    fn bit(&self, i: u64) -> bool {
+        self.0
+            .get((i / 32) as usize)
+            .is_some_and(|limb| limb >> (i % 32) & 1 == 1)
+    }
+
+    /// The 128 least significant bits.
+    fn low_u128(&self) -> u128 {
+        self.0
+            .iter()
+            .take(4)
+            .rev()
+            .fold(0, |value, &limb| value << 32 | u128::from(limb))
+    }
+
+    /// Sets the number to `self * mul + add`.
+    fn mul_add_small(&mut self, mul: u32, add: u32) {
+        let mut carry = u64::from(add);
+        for limb in &mut self.0 {
+            let product = u64::from(*limb) * u64::from(mul) + carry;
+            *limb = product as u32;
+            carry = product >> 32;
+        }
+        if carry > 0 {
+            self.0.push(carry as u32);
+        }
+        self.trim();
+    }
+
+    /// Divides the number, returning the remainder.
+    fn div_rem_small(&mut self, div: u32) -> u32 {
+        let mut remainder = 0u64;
+        for limb in self.0.iter_mut().rev() {
+            let dividend = remainder << 32 | u64::from(*limb);
+            *limb = (dividend / u64::from(div)) as u32;
+            remainder = dividend % u64::from(div);
+        }
+        self.trim();
+        remainder as u32
+    }
+
+    fn shl(&mut self, bits: u64) {
+        if self.is_zero() {
+            return;
+        }
+        let limbs = (bits / 32) as usize;
+        let bits = bits % 32;
+        if bits > 0 {
+            let mut carry = 0;
+            for limb in &mut self.0 {
+                let shifted = u64::from(*limb) << bits | carry;
+                *limb = shifted as u32;
+                carry = shifted >> 32;
+            }
+            if carry > 0 {
+                self.0.push(carry as u32);
+            }
+        }
+        self.0.splice(0..0, std::iter::repeat_n(0, limbs));
+    }
+
+    /// Shifts the number right, returning whether any of the bits shifted out
+    /// was set.
+    fn shr(&mut self, bits: u64) -> bool {
+        let limbs = ((bits / 32) as usize).min(self.0.len());
+        let bits = bits % 32;
+        let mut lost = self.0.drain(..limbs).any(|limb| limb != 0);
+        if bits > 0 {
+            let mut carry = 0;
+            for limb in self.0.iter_mut().rev() {
+                let shifted = u64::from(*limb) << (32 - bits);
+                *limb = (shifted >> 32) as u32 | carry;
+                carry = shifted as u32;
+            }
+            lost |= carry != 0;
+        }
+        self.trim();
+        lost
+    }
+
+    fn to_decimal(&self) -> String {
+        let mut n = self.clone();
+        let mut chunks = Vec::new();
+        while !n.is_zero() {
+            chunks.push(n.div_rem_small(1_000_000_000));
+        }
+        let mut decimal = chunks.pop().unwrap_or(0).to_string();
+        for chunk in chunks.iter().rev() {
+            decimal.push_str(&format!("{chunk:09}"));
+        }
+        decimal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn incr(current: &str, increment: &str) -> String {
+        let current = LongDouble::parse(current.as_bytes()).unwrap();
+        let increment = LongDouble::parse(increment.as_bytes()).unwrap();
+        (current + increment).to_string()
+    }
+
+    #[test]
+    fn formats_sums_like_redis() {
+        assert_eq!(incr("0.1", "0.2"), "0.3");
+        assert_eq!(incr("10.50", "0.1"), "10.6");
+        assert_eq!(incr("10.6", "-5"), "5.6");
+        assert_eq!(incr("5.0e3", "2.0e2"), "5200");
+        assert_eq!(incr("0", "1"), "1");
+        assert_eq!(incr("1", "0.25"), "1.25");
+        assert_eq!(incr("1.1", "2.2"), "3.3");
+        assert_eq!(incr("-1.5", "1.5"), "0");
+        assert_eq!(incr("-0", "-0"), "0");
+        assert_eq!(incr("3", "-5.25"), "-2.25");
+    }
+
+    #[test]
+    fn rounds_like_x87() {
+        // Digits beyond the 17th decimal place are rounded, after the number
+        // itself was rounded to 64 bits
+        assert_eq!(incr("0", "0.123456789012345685"), "0.12345678901234568");
+        assert_eq!(incr("0", "0.123456789012345675"), "0.12345678901234567");
+        assert_eq!(incr("0", "1e-18"), "0");
+        assert_eq!(incr("0", "-1e-18"), "0");
+        // Integers are exact up to 2^64
+        assert_eq!(incr("18446744073709551615", "0"), "18446744073709551615");
+        assert_eq!(incr("18446744073709551615", "1"), "18446744073709551616");
+        assert_eq!(incr("18446744073709551617", "0"), "18446744073709551616");
+        assert_eq!(incr("18446744073709551619", "0"), "18446744073709551620");
+        assert_eq!(incr("1e30", "0"), "1000000000000000000024696061952");
+        assert_eq!(incr("1e25", "1"), "10000000000000000000000000");
+        assert_eq!(incr("0x1p-2", "0X.8"), "0.75");
+    }
+
+    #[test]
+    fn parses_like_string2ld() {
+        for valid in [
+            "1",
+            "-1.",
+            ".5",
+            "+0",
+            "1E3",
+            "1e-3",
+            "inf",
+            "-Infinity",
+            "0x1P3",
+        ] {
+            assert!(LongDouble::parse(valid.as_bytes()).is_some(), "{valid}");
+        }
+        for invalid in [
+            "", " 1", "1 ", ".", "e3", "1e", "1e+", "nan", "0x", "1f", "--1",
+        ] {
+            assert!(LongDouble::parse(invalid.as_bytes()).is_none(), "{invalid}");
+        }
+        // Out of range
+        assert!(LongDouble::parse(b"1e5000").is_none());
+        assert!(LongDouble::parse(b"1e-5000").is_none());
+        assert!(LongDouble::parse(b"0e5000").is_some());
+        assert!(LongDouble::parse(b"1.2e4932").is_none());
+        assert!(LongDouble::parse(b"1.1e4932").is_some());
+        assert!(LongDouble::parse(b"1e-4950").is_some());
+        assert!(LongDouble::parse("1".repeat(MAX_LEN).as_bytes()).is_none());
+    }
+
+    #[test]
+    fn overflows_to_infinity() {
+        let large = LongDouble::parse(b"1e4932").unwrap();
+        assert!(!(large + large).is_finite());
+        assert!(!(LongDouble::parse(b"-inf").unwrap() + large).is_finite());
+        assert!((large + LongDouble::parse(b"-1e4932").unwrap()).is_finite());
+    }
+}
@@ -1,17 +1,39 @@
+mod bitmaps;
+mod blocking;
+mod config;
+mod connection;
 mod database;
+mod dict;
+mod error;
+mod hash;
+mod hashes;
+mod hyperloglog;
+mod keyspace;
+mod lists;
+mod long_double;
 mod protocol;
+mod quicklist;
 mod reader;
+mod set;
+mod sets;
+mod skiplist;
+mod sorted_set;
+mod sorted_sets;
+mod stream;
+mod streams;
+mod strings;
 mod utils;
 mod writer;
 
 use std::env;
 
 use anyhow::{anyhow, bail, Result};
+use config::Config;
 use database::Database;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let mut port = 6379;
+    let mut config = Config::default();
 
     let mut is_replica = false;
     let mut master_host = None;
@@ -21,11 +43,27 @@ async fn main() -> Result<()> {
     loop {
         match args.next().as_deref() {
             Some("--port") => {
-                port = args
+                config.port = args
                     .next()
                     .ok_or(anyhow!("--port requires an argument"))?
                     .parse()?
             }
+            Some("--hz") => {
+                config.hz = args
+                    .next()
+                    .ok_or(anyhow!("--hz requires an argument"))?
+                    .parse::<u32>()?
+                    .clamp(1, 500)
+            }
+            Some("--active-expire-effort") => {
+                config.active_expire_effort = args
+                    .next()
+                    .ok_or(anyhow!("--active-expire-effort requires an argument"))?
+                    .parse()?;
+                if !(1..=10).contains(&config.active_expire_effort) {
+                    bail!("--active-expire-effort must be between 1 and 10");
+                }
+            }
             Some("--replicaof") => {
                 master_host = Some(
                     args.next()
@@ -45,7 +83,7 @@ async fn main() -> Result<()> {
 
     if is_replica {
         Database::new_replica(
-            port,
+            config,
             master_host.expect("if we are dealing with a replica, master_host must be set"),
             master_port.expect("if we are dealing with a replica, master_port must be set"),
         )
@@ -53,7 +91,7 @@ async fn main() -> Result<()> {
         .listen()
         .await?;
     } else {
-        Database::new_master(port).listen().await?;
+        Database::new_master(config).listen().await?;
     }
 
     Ok(())
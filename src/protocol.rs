@@ -1,37 +1,786 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+
+use crate::long_double::LongDouble;
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Element {
     SimpleString(String),
-    BulkString(Vec<u8>),
-    NullBulkString,
+    Error(String),
+    BulkString(Bytes),
     Array(Vec<Element>),
+    Integer(i64),
+    Null,
+    /// Null reply of commands that otherwise reply with an array, which RESP2
+    /// encodes differently from a null bulk string.
+    NullArray,
+    /// Pairs of elements, such as fields and their values, replied as a flat
+    /// array in RESP2 and as an array of two element arrays in RESP3.
+    Pairs(Vec<(Element, Element)>),
+    /// Pairs of elements replied as a map in RESP3 and as an array of two
+    /// element arrays in RESP2, such as streams and their entries.
+    PairsMap(Vec<(Element, Element)>),
+    Boolean(bool),
+    Double(f64),
+    BigNumber(String),
+    Map(Vec<(Element, Element)>),
+    Set(Vec<Element>),
+    VerbatimString {
+        format: String,
+        data: Bytes,
+    },
+    /// Out-of-band information attached to the element that follows it.
+    Attribute {
+        attributes: Vec<(Element, Element)>,
+        element: Box<Element>,
+    },
+    Push(Vec<Element>),
     RdbFile(Vec<u8>),
     MultiInternal(Vec<Element>),
 }
 
+/// Version of the serialization protocol spoken on a connection, negotiated
+/// through `HELLO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(&self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Command {
-    Ping(Option<String>),
-    Echo(String),
+    Ping(Option<Bytes>),
+    Echo(Bytes),
     Set(Set),
-    Get(String),
+    Get(Bytes),
     Info(Vec<InfoSection>),
     ReplConf(ReplOpt),
     Psync(Psync),
+    Hello(Hello),
+    Expire(Expire),
+    Ttl(Bytes, TimeUnit),
+    ExpireTime(Bytes, TimeUnit),
+    Persist(Bytes),
+    Del(Vec<Bytes>),
+    Unlink(Vec<Bytes>),
+    Exists(Vec<Bytes>),
+    Type(Bytes),
+    Rename(Bytes, Bytes),
+    RenameNx(Bytes, Bytes),
+    Copy(Copy),
+    Touch(Vec<Bytes>),
+    IncrBy(Bytes, i64),
+    IncrByFloat(Bytes, LongDouble),
+    Keys(Bytes),
+    Scan(Scan),
+    Append(Bytes, Bytes),
+    Strlen(Bytes),
+    GetRange(Bytes, i64, i64),
+    SetRange(Bytes, i64, Bytes),
+    GetDel(Bytes),
+    GetEx(GetEx),
+    Lcs(Lcs),
+    MGet(Vec<Bytes>),
+    MSet(Vec<(Bytes, Bytes)>),
+    MSetNx(Vec<(Bytes, Bytes)>),
+    SetBit(Bytes, u64, bool),
+    GetBit(Bytes, u64),
+    BitCount(Bytes, Option<BitRange>),
+    BitPos(BitPos),
+    BitOp(BitOperation, Bytes, Vec<Bytes>),
+    BitField(Bytes, Vec<BitFieldOp>),
+    BitFieldRo(Bytes, Vec<BitFieldOp>),
+    PfAdd(Bytes, Vec<Bytes>),
+    PfCount(Vec<Bytes>),
+    PfMerge(Bytes, Vec<Bytes>),
+    PfDebug(PfDebug, Bytes),
+    Push(ListEnd, Bytes, Vec<Bytes>),
+    PushX(ListEnd, Bytes, Vec<Bytes>),
+    Pop(ListEnd, Bytes, Option<usize>),
+    LLen(Bytes),
+    LRange(Bytes, i64, i64),
+    LIndex(Bytes, i64),
+    LSet(Bytes, i64, Bytes),
+    LInsert(LInsert),
+    LRem(Bytes, i64, Bytes),
+    LTrim(Bytes, i64, i64),
+    LPos(LPos),
+    LMove(LMove),
+    LMPop(LMPop),
+    Blocking(Blocking),
+    HSet(Bytes, Vec<(Bytes, Bytes)>),
+    HSetNx(Bytes, Bytes, Bytes),
+    HGet(Bytes, Bytes),
+    HMGet(Bytes, Vec<Bytes>),
+    HDel(Bytes, Vec<Bytes>),
+    HExists(Bytes, Bytes),
+    HLen(Bytes),
+    HKeys(Bytes),
+    HVals(Bytes),
+    HGetAll(Bytes),
+    HIncrBy(Bytes, Bytes, i64),
+    HIncrByFloat(Bytes, Bytes, LongDouble),
+    HStrlen(Bytes, Bytes),
+    HRandField(HRandField),
+    HScan(ElementScan),
+    HExpire(HExpire),
+    HTtl(Bytes, Vec<Bytes>, TimeUnit),
+    HExpireTime(Bytes, Vec<Bytes>, TimeUnit),
+    HPersist(Bytes, Vec<Bytes>),
+    SAdd(Bytes, Vec<Bytes>),
+    SRem(Bytes, Vec<Bytes>),
+    SIsMember(Bytes, Bytes),
+    SMIsMember(Bytes, Vec<Bytes>),
+    SCard(Bytes),
+    SMembers(Bytes),
+    SPop(Bytes, Option<usize>),
+    SRandMember(Bytes, Option<i64>),
+    SMove(Bytes, Bytes, Bytes),
+    SetOp(SetOperation, Vec<Bytes>),
+    SetOpStore(SetOperation, Bytes, Vec<Bytes>),
+    SInterCard(Vec<Bytes>, usize),
+    SScan(ElementScan),
+    ZAdd(ZAdd),
+    ZRem(Bytes, Vec<Bytes>),
+    ZScore(Bytes, Bytes),
+    ZMScore(Bytes, Vec<Bytes>),
+    ZIncrBy(Bytes, f64, Bytes),
+    ZCard(Bytes),
+    ZCount(Bytes, ScoreRange),
+    ZRank(ZRank),
+    ZRange(ZRange),
+    ZRangeStore(Bytes, ZRange),
+    ZScan(ElementScan),
+    ZSetOp(ZSetOp),
+    ZSetOpStore(Bytes, ZSetOp),
+    ZInterCard(Vec<Bytes>, usize),
+    ZRemRange(Bytes, ZRangeBy),
+    ZPop(SortedSetEnd, Bytes, Option<usize>),
+    ZMPop(ZMPop),
+    XAdd(XAdd),
+    XLen(Bytes),
+    XRange(XRange),
+    XDel(Bytes, Vec<StreamId>),
+    XTrim(Bytes, StreamTrim),
+    XSetId(XSetId),
+    XRead(XRead),
+    Multi,
+    Exec,
+    Discard,
+}
+
+impl Command {
+    /// Whether the command modifies the keyspace, and must therefore be rejected
+    /// by read only replicas.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::Expire(_)
+                | Command::Persist(_)
+                | Command::Del(_)
+                | Command::Unlink(_)
+                | Command::Rename(..)
+                | Command::RenameNx(..)
+                | Command::Copy(_)
+                | Command::IncrBy(..)
+                | Command::IncrByFloat(..)
+                | Command::Append(..)
+                | Command::SetRange(..)
+                | Command::GetDel(_)
+                | Command::GetEx(_)
+                | Command::MSet(_)
+                | Command::MSetNx(_)
+                | Command::SetBit(..)
+                | Command::BitOp(..)
+                | Command::BitField(..)
+                | Command::PfAdd(..)
+                | Command::PfMerge(..)
+                | Command::PfDebug(..)
+                | Command::Push(..)
+                | Command::PushX(..)
+                | Command::Pop(..)
+                | Command::LSet(..)
+                | Command::LInsert(_)
+                | Command::LRem(..)
+                | Command::LTrim(..)
+                | Command::LMove(_)
+                | Command::LMPop(_)
+                | Command::HSet(..)
+                | Command::HSetNx(..)
+                | Command::HDel(..)
+                | Command::HIncrBy(..)
+                | Command::HIncrByFloat(..)
+                | Command::HExpire(_)
+                | Command::HPersist(..)
+                | Command::SAdd(..)
+                | Command::SRem(..)
+                | Command::SPop(..)
+                | Command::SMove(..)
+                | Command::SetOpStore(..)
+                | Command::ZAdd(_)
+                | Command::ZRem(..)
+                | Command::ZIncrBy(..)
+                | Command::ZRangeStore(..)
+                | Command::ZSetOpStore(..)
+                | Command::ZRemRange(..)
+                | Command::ZPop(..)
+                | Command::ZMPop(_)
+                | Command::XAdd(_)
+                | Command::XDel(..)
+                | Command::XTrim(..)
+                | Command::XSetId(_)
+        ) || matches!(self, Command::Blocking(blocking) if blocking.operation.is_write())
+    }
 }
 
 #[derive(Debug)]
 pub struct Set {
-    pub key: String,
-    pub value: String,
-    pub expiration: Option<Duration>,
+    pub key: Bytes,
+    pub value: Bytes,
+    pub expiration: Option<Expiration>,
+    pub keep_ttl: bool,
+    pub condition: Option<SetCondition>,
+    pub get: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Expiration {
+    /// Relative to the moment the command is executed (`EX`, `PX`)
+    After(Duration),
+    /// Absolute unix time (`EXAT`, `PXAT`)
+    At(SystemTime),
+}
+
+/// Only perform the write if the key, or the element of a sorted set, already
+/// exists (`XX`) or doesn't exist (`NX`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetCondition {
+    Nx,
+    Xx,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct GetEx {
+    pub key: Bytes,
+    pub expiration: Option<Expiration>,
+    pub persist: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lcs {
+    pub key1: Bytes,
+    pub key2: Bytes,
+    /// Only reply with the length of the match (`LEN`)
+    pub len: bool,
+    /// Reply with the ranges of every match (`IDX`)
+    pub idx: bool,
+    /// Ranges shorter than this are left out of `IDX` replies (`MINMATCHLEN`)
+    pub min_match_len: usize,
+    /// Include the length of each range in `IDX` replies (`WITHMATCHLEN`)
+    pub with_match_len: bool,
+}
+
+/// Range of a string, in bytes or bits, with negative offsets counting from the
+/// end of the string.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BitRange {
+    pub start: i64,
+    /// Defaults to the end of the string
+    pub end: Option<i64>,
+    pub unit: BitUnit,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BitPos {
+    pub key: Bytes,
+    pub bit: bool,
+    pub range: Option<BitRange>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// One of the operations of a `BITFIELD` command, applied in order. Offsets are
+/// in bits.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BitFieldOp {
+    Get(BitFieldType, u64),
+    Set(BitFieldType, u64, i64),
+    IncrBy(BitFieldType, u64, i64),
+    /// Sets the overflow behavior of the `SET` and `INCRBY` operations that
+    /// follow it
+    Overflow(BitFieldOverflow),
+}
+
+/// Signed integers of 1 to 64 bits, or unsigned integers of 1 to 63 bits.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BitFieldOverflow {
+    #[default]
+    Wrap,
+    /// Saturate to the minimum or maximum value
+    Sat,
+    /// Leave the value unchanged and reply with nil
+    Fail,
+}
+
+/// Subcommands of `PFDEBUG`, to inspect the internals of a HyperLogLog.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PfDebug {
+    /// Reply with the value of every register
+    GetReg,
+    /// Reply with the opcodes of a sparse representation
+    Decode,
+    Encoding,
+    /// Convert a sparse representation to dense
+    ToDense,
+}
+
+/// End of a list, `LEFT` being the head.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct LInsert {
+    pub key: Bytes,
+    /// Insert before the pivot (`BEFORE`) rather than after it (`AFTER`)
+    pub before: bool,
+    pub pivot: Bytes,
+    pub element: Bytes,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct LPos {
+    pub key: Bytes,
+    pub element: Bytes,
+    /// Skip the first `rank - 1` matches, scanning from the tail if negative
+    pub rank: i64,
+    /// Reply with up to this many matches, or all of them if 0, instead of
+    /// just the first one
+    pub count: Option<usize>,
+    /// Only compare this many elements, or all of them if 0
+    pub max_len: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct LMove {
+    pub source: Bytes,
+    pub destination: Bytes,
+    pub from: ListEnd,
+    pub to: ListEnd,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct LMPop {
+    pub keys: Vec<Bytes>,
+    pub end: ListEnd,
+    pub count: usize,
+}
+
+/// A command that waits until one of its keys can serve it, such as `BLPOP`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Blocking {
+    pub keys: Vec<Bytes>,
+    pub operation: BlockingOp,
+    /// How long to wait for, or forever if `None`
+    pub timeout: Option<Duration>,
+}
+
+/// What a blocking command does with the first of its keys that can serve it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BlockingOp {
+    /// Pop an element (`BLPOP`, `BRPOP`)
+    Pop(ListEnd),
+    LMove {
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    },
+    LMPop {
+        end: ListEnd,
+        count: usize,
+    },
+    /// Pop a member (`BZPOPMIN`, `BZPOPMAX`)
+    ZPop(SortedSetEnd),
+    ZMPop {
+        end: SortedSetEnd,
+        count: usize,
+    },
+    /// Read the entries added to a stream (`XREAD`)
+    XRead(XRead),
+}
+
+impl BlockingOp {
+    /// Reply sent when the timeout expires before any key could serve the
+    /// command.
+    pub fn timeout_reply(&self) -> Element {
+        match self {
+            BlockingOp::LMove { .. } => Element::Null,
+            BlockingOp::Pop(_)
+            | BlockingOp::LMPop { .. }
+            | BlockingOp::ZPop(_)
+            | BlockingOp::ZMPop { .. }
+            | BlockingOp::XRead(_) => Element::NullArray,
+        }
+    }
+
+    /// Whether serving the command modifies the keyspace, which all but
+    /// `XREAD` do.
+    pub fn is_write(&self) -> bool {
+        !matches!(self, BlockingOp::XRead(_))
+    }
+
+    /// Type of the values that can serve the command.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            BlockingOp::Pop(_) | BlockingOp::LMove { .. } | BlockingOp::LMPop { .. } => {
+                ValueType::List
+            }
+            BlockingOp::ZPop(_) | BlockingOp::ZMPop { .. } => ValueType::SortedSet,
+            BlockingOp::XRead(_) => ValueType::Stream,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct HRandField {
+    pub key: Bytes,
+    /// Reply with this many distinct fields, or with this many fields that may
+    /// repeat if negative, instead of a single one
+    pub count: Option<i64>,
+    pub with_values: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct HExpire {
+    pub key: Bytes,
+    /// Unix time in milliseconds, possibly in the past
+    pub at: i64,
+    pub condition: Option<ExpireCondition>,
+    pub fields: Vec<Bytes>,
+}
+
+/// Iteration over the elements of a value with a cursor, like `SCAN` does for
+/// keys (`HSCAN`, `SSCAN`, `ZSCAN`).
+#[derive(Debug, PartialEq, Eq)]
+pub struct ElementScan {
+    pub key: Bytes,
+    pub cursor: u64,
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    /// Only reply with the fields of hashes (`NOVALUES`)
+    pub no_values: bool,
+}
+
+/// Operations combining several sets (`SINTER`, `SUNION`, `SDIFF`), missing keys
+/// being considered empty sets.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    /// Members of the first set that aren't in any of the others
+    Diff,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ZAdd {
+    pub key: Bytes,
+    pub condition: Option<SetCondition>,
+    /// Only update the score of existing members if the new one is greater
+    /// (`GT`) or less (`LT`), which doesn't prevent adding new members
+    pub comparison: Option<ScoreComparison>,
+    /// Reply with the number of members added or updated, rather than just
+    /// added (`CH`)
+    pub changed: bool,
+    /// Increment the score of a single member, like `ZINCRBY` (`INCR`)
+    pub incr: bool,
+    pub elements: Vec<(f64, Bytes)>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ScoreComparison {
+    Gt,
+    Lt,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ZRank {
+    pub key: Bytes,
+    pub member: Bytes,
+    /// Rank from the highest score rather than the lowest (`ZREVRANK`)
+    pub rev: bool,
+    pub with_score: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ZRange {
+    pub key: Bytes,
+    pub by: ZRangeBy,
+    /// Order from the highest score to the lowest (`REV`)
+    pub rev: bool,
+    /// Skip this many elements of the range (`LIMIT`)
+    pub offset: i64,
+    /// Reply with at most this many elements, or all of them if negative
+    /// (`LIMIT`)
+    pub count: i64,
+    pub with_scores: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ZRangeBy {
+    /// Indexes that may count from the end, in the order of the range
+    Rank(i64, i64),
+    Score(ScoreRange),
+    Lex(LexRange),
+}
+
+/// Range of scores, each end of which is excluded if prefixed by `(`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub min_exclusive: bool,
+    pub max: f64,
+    pub max_exclusive: bool,
+}
+
+impl ScoreRange {
+    pub fn is_below(&self, score: f64) -> bool {
+        score < self.min || (self.min_exclusive && score == self.min)
+    }
+
+    pub fn is_above(&self, score: f64) -> bool {
+        score > self.max || (self.max_exclusive && score == self.max)
+    }
+}
+
+/// Range of members of a sorted set, compared byte by byte, which is only
+/// meaningful if they all have the same score.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LexBound {
+    /// Before every member (`-`)
+    Min,
+    /// After every member (`+`)
+    Max,
+    /// Member prefixed by `[`
+    Inclusive(Bytes),
+    /// Member prefixed by `(`
+    Exclusive(Bytes),
+}
+
+impl LexRange {
+    pub fn is_below(&self, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(min) => member < &min[..],
+            LexBound::Exclusive(min) => member <= &min[..],
+        }
+    }
+
+    pub fn is_above(&self, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(max) => member > &max[..],
+            LexBound::Exclusive(max) => member >= &max[..],
+        }
+    }
+}
+
+/// Operations combining several sorted sets, or sets whose members are given
+/// a score of 1 (`ZUNION`, `ZINTER`, `ZDIFF`), missing keys being considered
+/// empty.
+#[derive(Debug, PartialEq)]
+pub struct ZSetOp {
+    pub operation: SetOperation,
+    pub keys: Vec<Bytes>,
+    /// Factor by which the scores of each input are multiplied, 1 by default
+    /// (`WEIGHTS`)
+    pub weights: Vec<f64>,
+    pub aggregate: Aggregate,
+    pub with_scores: bool,
+}
+
+/// How the scores of a member in several inputs are combined (`AGGREGATE`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+/// End of a sorted set, `MIN` being the lowest score.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SortedSetEnd {
+    Min,
+    Max,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ZMPop {
+    pub keys: Vec<Bytes>,
+    pub end: SortedSetEnd,
+    pub count: usize,
+}
+
+/// ID of a stream entry: the Unix time in milliseconds at which it was added,
+/// followed by a sequence number for the entries added during the same
+/// millisecond.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// The smallest ID greater than this one, if there is any.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The greatest ID smaller than this one, if there is any.
+    pub fn previous(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+}
+
+impl std::fmt::Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// How the ID of an entry added with `XADD` is chosen.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum XAddId {
+    /// From the current time (`*`)
+    Auto,
+    /// With the given time and the next sequence number for it (`<ms>-*`)
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct XAdd {
+    pub key: Bytes,
+    /// Don't create the stream if it doesn't exist (`NOMKSTREAM`)
+    pub no_mkstream: bool,
+    pub trim: Option<StreamTrim>,
+    pub id: XAddId,
+    pub fields: Vec<(Bytes, Bytes)>,
+}
+
+/// Removal of the oldest entries of a stream (`XTRIM`, or the trimming options
+/// of `XADD`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    /// Only remove whole blocks of entries, which may leave some that should
+    /// have been removed (`~`)
+    pub approximate: bool,
+    /// Largest number of entries to remove, with 0 meaning no limit. When
+    /// trimming approximately, defaults to 100 blocks worth of entries
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TrimStrategy {
+    /// Keep at most this many entries (`MAXLEN`)
+    MaxLen(usize),
+    /// Remove the entries with a smaller ID (`MINID`)
+    MinId(StreamId),
+}
+
+/// The entries of a stream between two IDs, both included (`XRANGE`,
+/// `XREVRANGE`).
+#[derive(Debug, PartialEq, Eq)]
+pub struct XRange {
+    pub key: Bytes,
+    pub start: StreamId,
+    pub end: StreamId,
+    /// Reply from the greatest ID to the smallest
+    pub rev: bool,
+    pub count: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct XSetId {
+    pub key: Bytes,
+    pub last_id: StreamId,
+    /// Only checked against the length of the stream, as nothing reports it
+    pub entries_added: Option<u64>,
+    pub max_deleted_id: Option<StreamId>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct XRead {
+    /// Streams to read, each with the ID after which to read their entries, or
+    /// `None` to read the ones added after the command runs (`$`)
+    pub streams: Vec<(Bytes, Option<StreamId>)>,
+    /// Largest number of entries to read from each stream, if any
+    pub count: Option<usize>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum InfoSection {
     Replication,
+    Stats,
+    Keyspace,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -45,3 +794,91 @@ pub struct Psync {
     pub replication_id: Option<String>,
     pub replication_offset: Option<u128>,
 }
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hello {
+    pub protocol: Option<Protocol>,
+    pub auth: Option<(String, String)>,
+    pub client_name: Option<String>,
+}
+
+/// Unit of the times taken or returned by a command, e.g. `TTL` versus `PTTL`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Expire {
+    pub key: Bytes,
+    /// Unix time in milliseconds, possibly in the past
+    pub at: i64,
+    pub conditions: Vec<ExpireCondition>,
+}
+
+/// Only set the expiration if the key has none (`NX`), has one (`XX`), or if the
+/// new one is greater (`GT`) or less (`LT`) than the current one. A key without
+/// an expiration is considered to have an infinite TTL.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExpireCondition {
+    Nx,
+    Xx,
+    Gt,
+    Lt,
+}
+
+impl ExpireCondition {
+    /// Whether an expiration can be replaced by `new`, both in unix
+    /// milliseconds.
+    pub fn allows(&self, current: Option<i64>, new: i64) -> bool {
+        match (self, current) {
+            (ExpireCondition::Nx, current) => current.is_none(),
+            (ExpireCondition::Xx, current) => current.is_some(),
+            (ExpireCondition::Gt, Some(current)) => new > current,
+            (ExpireCondition::Gt, None) => false,
+            (ExpireCondition::Lt, Some(current)) => new < current,
+            (ExpireCondition::Lt, None) => true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Copy {
+    pub source: Bytes,
+    pub destination: Bytes,
+    pub replace: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Scan {
+    pub cursor: u64,
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    pub value_type: Option<ValueType>,
+}
+
+/// The types of values that can be stored in the keyspace.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ValueType {
+    String,
+    List,
+    Hash,
+    Set,
+    SortedSet,
+    Stream,
+}
+
+impl ValueType {
+    /// Name of the type, as reported by `TYPE`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::String => "string",
+            ValueType::List => "list",
+            ValueType::Hash => "hash",
+            ValueType::Set => "set",
+            ValueType::SortedSet => "zset",
+            ValueType::Stream => "stream",
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+/// Largest number of elements stored by each node.
+const NODE_CAPACITY: usize = 128;
+
+/// A list stored as a sequence of small nodes, in the spirit of Redis'
+/// quicklist: pushing and popping at either end is O(1), and accessing an
+/// element by index only walks the nodes, not every element before it.
+#[derive(Debug, Clone, Default)]
+pub struct QuickList {
+    nodes: VecDeque<VecDeque<Bytes>>,
+    len: usize,
+}
+
+impl QuickList {
+    pub fn new() -> Self {
+        QuickList::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, element: Bytes) {
+        match self.nodes.front_mut() {
+            Some(node) if node.len() < NODE_CAPACITY => node.push_front(element),
+            _ => self.nodes.push_front(VecDeque::from([element])),
+        }
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, element: Bytes) {
+        match self.nodes.back_mut() {
+            Some(node) if node.len() < NODE_CAPACITY => node.push_back(element),
+            _ => self.nodes.push_back(VecDeque::from([element])),
+        }
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        let node = self.nodes.front_mut()?;
+        let element = node.pop_front();
+        if node.is_empty() {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        element
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        let node = self.nodes.back_mut()?;
+        let element = node.pop_back();
+        if node.is_empty() {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        element
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Bytes> {
+        let (node, offset) = self.locate(index)?;
+        self.nodes[node].get(offset)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Bytes> {
+        let (node, offset) = self.locate(index)?;
+        self.nodes[node].get_mut(offset)
+    }
+
+    /// Inserts an element at `index`, shifting the ones after it. Panics if
+    /// `index` is greater than the length of the list.
+    pub fn insert(&mut self, index: usize, element: Bytes) {
+        assert!(index <= self.len, "index out of bounds");
+        if index == self.len {
+            return self.push_back(element);
+        }
+
+        let (mut node, mut offset) = self.locate(index).expect("checked above");
+        if self.nodes[node].len() >= NODE_CAPACITY {
+            // Split full nodes in half, so that inserting in the middle of the
+            // list doesn't create tiny nodes
+            let tail = self.nodes[node].split_off(NODE_CAPACITY / 2);
+            self.nodes.insert(node + 1, tail);
+            if offset >= NODE_CAPACITY / 2 {
+                node += 1;
+                offset -= NODE_CAPACITY / 2;
+            }
+        }
+        self.nodes[node].insert(offset, element);
+        self.len += 1;
+    }
+
+    /// Keeps only the elements from `start` to `end`, both inclusive.
+    pub fn trim(&mut self, start: usize, end: usize) {
+        let keep = (end + 1)
+            .saturating_sub(start)
+            .min(self.len.saturating_sub(start));
+        for _ in 0..start.min(self.len) {
+            self.pop_front();
+        }
+        while self.len > keep {
+            self.pop_back();
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Bytes> {
+        self.nodes.iter().flatten()
+    }
+
+    /// Iterates the elements starting at `index`, skipping whole nodes to reach
+    /// it like [`QuickList::get`] does.
+    pub fn iter_from(&self, index: usize) -> impl Iterator<Item = &Bytes> {
+        let (node, offset) = self.locate(index).unwrap_or((self.nodes.len(), 0));
+        self.nodes.range(node..).flatten().skip(offset)
+    }
+
+    /// The node holding the element at `index`, and its offset within the node,
+    /// walking the nodes from whichever end of the list is closer.
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+
+        if index < self.len / 2 {
+            let mut offset = index;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if offset < node.len() {
+                    return Some((i, offset));
+                }
+                offset -= node.len();
+            }
+        } else {
+            let mut offset = self.len - 1 - index;
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                if offset < node.len() {
+                    return Some((i, node.len() - 1 - offset));
+                }
+                offset -= node.len();
+            }
+        }
+        unreachable!("the length of the list matches its nodes")
+    }
+}
+
+impl FromIterator<Bytes> for QuickList {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        let mut list = QuickList::new();
+        for element in iter {
+            list.push_back(element);
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iterates_from_any_index() {
+        // Nodes of uneven lengths, as left by insertions in the middle
+        let mut list: QuickList = (0..300).map(|i| Bytes::from(i.to_string())).collect();
+        for i in (0..300).step_by(7) {
+            list.insert(i, Bytes::from(format!("x{i}")));
+        }
+        let elements: Vec<_> = list.iter().collect();
+        for index in 0..=list.len() {
+            assert!(list.iter_from(index).eq(elements[index..].iter().copied()));
+        }
+        assert_eq!(list.iter_from(list.len() + 1).count(), 0);
+    }
+}
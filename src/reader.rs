@@ -1,29 +1,310 @@
-use std::{io::Cursor, ops::Deref, time::Duration};
+use std::{
+    io::Cursor,
+    ops::Deref,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{anyhow, bail, Context, Result};
-use bytes::Buf;
+use bytes::{Buf, Bytes, BytesMut};
 
-use crate::protocol::{Command, Element, InfoSection, Psync, ReplOpt, Set};
+use crate::error::RedisError;
+use crate::long_double::LongDouble;
+use crate::protocol::{
+    Aggregate, BitFieldOp, BitFieldOverflow, BitFieldType, BitOperation, BitPos, BitRange, BitUnit,
+    Blocking, BlockingOp, Command, Copy, Element, ElementScan, Expiration, Expire, ExpireCondition,
+    GetEx, HExpire, HRandField, Hello, InfoSection, LInsert, LMPop, LMove, LPos, Lcs, LexBound,
+    LexRange, ListEnd, PfDebug, Protocol, Psync, ReplOpt, Scan, ScoreComparison, ScoreRange, Set,
+    SetCondition, SetOperation, SortedSetEnd, StreamId, StreamTrim, TimeUnit, TrimStrategy,
+    ValueType, XAdd, XAddId, XRange, XRead, XSetId, ZAdd, ZMPop, ZRange, ZRangeBy, ZRank, ZSetOp,
+};
+use crate::utils::{parse_f64, parse_i64, unix_millis};
 
-pub struct ElementParser<'a> {
+/// Signals that the buffer ends in the middle of an element, and that parsing
+/// should be retried once more bytes have been received.
+#[derive(Debug, thiserror::Error)]
+#[error("buffer terminated before the element was complete")]
+struct Incomplete;
+
+/// Largest bulk string a client is allowed to send, matching Redis' default
+/// `proto-max-bulk-len`. Strings built by commands are held to the same limit.
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Largest number of elements a client is allowed to send in a single array.
+const MAX_ARRAY_LEN: usize = 1024 * 1024;
+
+/// Largest line a client is allowed to send as an inline command, or as the
+/// header of an element.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Deepest nesting of aggregates a peer is allowed to send. Commands are flat
+/// arrays and replies barely nest, while dropping or serializing an element
+/// recurses into the elements it holds.
+const MAX_NESTING: usize = 32;
+
+/// First byte of every element type, anything else starts an inline command.
+const ELEMENT_PREFIXES: &[u8] = b"+-$*:_#,(%~=|>";
+
+/// Parses elements out of the bytes received from a peer, consuming them from
+/// the buffer as they are parsed.
+///
+/// Aggregates are parsed without recursing: the ones still waiting for some of
+/// their elements are kept across calls, so that parsing resumes where it
+/// stopped once more bytes have been received.
+#[derive(Debug, Default)]
+pub struct ElementParser {
+    partials: Vec<Partial>,
+}
+
+/// An aggregate whose header has been parsed, but not all of its elements.
+#[derive(Debug)]
+struct Partial {
+    kind: AggregateKind,
+    remaining: usize,
+    elements: Vec<Element>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AggregateKind {
+    Array,
+    Map,
+    Set,
+    Push,
+    Attribute,
+}
+
+/// The start of an element, up to the first byte that isn't part of its header.
+enum Header {
+    /// An element with nothing after the header
+    Complete(Element),
+    /// A bulk string of the given length, verbatim if it starts with a format
+    String { len: usize, verbatim: bool },
+    /// An aggregate of the given number of elements
+    Aggregate(AggregateKind, usize),
+}
+
+impl ElementParser {
+    pub fn new() -> ElementParser {
+        ElementParser::default()
+    }
+
+    /// Whether an aggregate has been partially parsed, which is then lost if
+    /// the peer closes the connection.
+    pub fn in_progress(&self) -> bool {
+        !self.partials.is_empty()
+    }
+
+    /// Parses the next element in the buffer. Lines that don't start like any
+    /// element are inline commands, as sent by telnet or netcat sessions, and are
+    /// parsed as an array of bulk strings.
+    ///
+    /// Returns `Ok(None)` if the buffer doesn't contain a complete element yet, in
+    /// which case the elements parsed so far are kept, and parsing can be resumed
+    /// once more data has been appended to the buffer.
+    pub fn parse(&mut self, buffer: &mut BytesMut) -> Result<Option<Element>> {
+        loop {
+            match buffer.first() {
+                None => return Ok(None),
+                Some(b) if self.partials.is_empty() && !ELEMENT_PREFIXES.contains(b) => {
+                    match read_inline_command(buffer) {
+                        Ok(Some(command)) => return Ok(Some(command)),
+                        Ok(None) => continue,
+                        Err(e) if e.is::<Incomplete>() => return Ok(None),
+                        Err(e) => return Err(e),
+                    }
+                }
+                Some(_) => {}
+            }
+
+            let mut reader = HeaderReader::new(buffer);
+            let header = match reader.read_header() {
+                Ok(header) => header,
+                Err(e) if e.is::<Incomplete>() => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let header_len = reader.position();
+
+            let mut element = match header {
+                Header::Complete(element) => {
+                    buffer.advance(header_len);
+                    element
+                }
+                Header::String { len, verbatim } => {
+                    let Some(data) = read_string_data(buffer, header_len, len, verbatim)? else {
+                        return Ok(None);
+                    };
+                    data
+                }
+                Header::Aggregate(kind, len) => {
+                    if self.partials.len() == MAX_NESTING {
+                        bail!("too many nested aggregates");
+                    }
+                    buffer.advance(header_len);
+                    let remaining = match kind {
+                        AggregateKind::Map => 2 * len,
+                        AggregateKind::Attribute => 2 * len + 1,
+                        _ => len,
+                    };
+                    // Elements are only allocated as they arrive, the length may
+                    // be a lie
+                    self.partials.push(Partial {
+                        kind,
+                        remaining,
+                        elements: Vec::new(),
+                    });
+                    if remaining > 0 {
+                        continue;
+                    }
+                    self.partials.pop().expect("pushed above").finish()
+                }
+            };
+
+            // Add the element to the aggregates it completes
+            loop {
+                let Some(partial) = self.partials.last_mut() else {
+                    return Ok(Some(element));
+                };
+                partial.elements.push(element);
+                partial.remaining -= 1;
+                if partial.remaining > 0 {
+                    break;
+                }
+                element = self.partials.pop().expect("checked above").finish();
+            }
+        }
+    }
+}
+
+impl Partial {
+    fn finish(self) -> Element {
+        match self.kind {
+            AggregateKind::Array => Element::Array(self.elements),
+            AggregateKind::Map => Element::Map(into_pairs(self.elements)),
+            AggregateKind::Set => Element::Set(self.elements),
+            AggregateKind::Push => Element::Push(self.elements),
+            AggregateKind::Attribute => {
+                let mut elements = self.elements;
+                let element = elements.pop().expect("attributes precede an element");
+                Element::Attribute {
+                    attributes: into_pairs(elements),
+                    element: Box::new(element),
+                }
+            }
+        }
+    }
+}
+
+fn into_pairs(elements: Vec<Element>) -> Vec<(Element, Element)> {
+    let mut elements = elements.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+        pairs.push((key, value));
+    }
+    pairs
+}
+
+/// Reads an inline command, or `None` if the line is blank.
+fn read_inline_command(buffer: &mut BytesMut) -> Result<Option<Element>> {
+    let line_len = match buffer.iter().position(|&b| b == b'\n') {
+        Some(line_len) => line_len,
+        None if buffer.len() > MAX_INLINE_LEN => bail!("too big inline request"),
+        None => bail!(Incomplete),
+    };
+    let line = buffer[..line_len]
+        .strip_suffix(b"\r")
+        .unwrap_or(&buffer[..line_len]);
+    let args = split_args(line).ok_or(anyhow!("unbalanced quotes in request"))?;
+    buffer.advance(line_len + 1);
+
+    if args.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(Element::Array(
+            args.into_iter().map(Element::BulkString).collect(),
+        )))
+    }
+}
+
+/// Takes the data of a bulk string out of the buffer, along with the header
+/// preceding it, or returns `None` if it hasn't been fully received yet.
+fn read_string_data(
+    buffer: &mut BytesMut,
+    header_len: usize,
+    len: usize,
+    verbatim: bool,
+) -> Result<Option<Element>> {
+    let end = header_len + len;
+    if buffer.len() < end + 2 {
+        return Ok(None);
+    }
+    if &buffer[end..end + 2] != b"\r\n" {
+        bail!("Expected \\r\\n after {len} bytes of string data");
+    }
+    let format = if verbatim {
+        let data = &buffer[header_len..end];
+        if data.len() < 4 || data[3] != b':' {
+            bail!("Verbatim strings must start with a 3 character format and ':'");
+        }
+        Some(String::from_utf8(data[..3].to_vec())?)
+    } else {
+        None
+    };
+
+    buffer.advance(header_len);
+    let mut data = buffer.split_to(len).freeze();
+    buffer.advance(2);
+
+    Ok(Some(match format {
+        Some(format) => {
+            data.advance(4);
+            Element::VerbatimString { format, data }
+        }
+        None => Element::BulkString(data),
+    }))
+}
+
+/// Reads the header of the element at the start of a buffer, without consuming
+/// anything from the buffer itself.
+struct HeaderReader<'a> {
     bytes: Cursor<&'a [u8]>,
 }
 
-impl<'a> ElementParser<'a> {
-    pub fn new(bytes: &'a [u8]) -> ElementParser<'a> {
-        ElementParser {
+impl<'a> HeaderReader<'a> {
+    fn new(bytes: &'a [u8]) -> HeaderReader<'a> {
+        HeaderReader {
             bytes: Cursor::new(bytes),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Element> {
-        match self.read_u8() {
-            Some(b'+') => self.read_simple_string(),
-            Some(b'$') => self.read_bulk_string(),
-            Some(b'*') => self.read_array(),
+    /// Number of bytes read so far.
+    fn position(&self) -> usize {
+        self.bytes.position() as usize
+    }
+
+    fn read_header(&mut self) -> Result<Header> {
+        let element = match self.read_u8() {
+            Some(b'+') => self.read_simple_string()?,
+            Some(b'-') => Element::Error(String::from_utf8(self.read_line()?)?),
+            Some(b'$') if self.read_null_length()? => Element::Null,
+            Some(b'$') => return self.read_string_header(false),
+            Some(b'*') if self.read_null_length()? => Element::Null,
+            Some(b'*') => return self.read_aggregate_header(AggregateKind::Array),
+            Some(b':') => Element::Integer(self.read_i64_crlf()?),
+            Some(b'_') => {
+                self.expect_crlf()?;
+                Element::Null
+            }
+            Some(b'#') => self.read_boolean()?,
+            Some(b',') => self.read_double()?,
+            Some(b'(') => self.read_big_number()?,
+            Some(b'%') => return self.read_aggregate_header(AggregateKind::Map),
+            Some(b'~') => return self.read_aggregate_header(AggregateKind::Set),
+            Some(b'=') => return self.read_string_header(true),
+            Some(b'|') => return self.read_aggregate_header(AggregateKind::Attribute),
+            Some(b'>') => return self.read_aggregate_header(AggregateKind::Push),
             Some(other) => bail!("Unsupported element '{}'", other.escape_ascii()),
-            None => bail!("Expected at least one byte"),
-        }
+            None => bail!(Incomplete),
+        };
+        Ok(Header::Complete(element))
     }
 
     fn read_u8(&mut self) -> Option<u8> {
@@ -42,10 +323,7 @@ impl<'a> ElementParser<'a> {
                 b.escape_ascii().to_string(),
                 other.escape_ascii().to_string()
             ),
-            None => bail!(
-                "Expected {}, but there are no bytes remaining",
-                b.escape_ascii().to_string()
-            ),
+            None => bail!(Incomplete),
         }
     }
 
@@ -54,29 +332,73 @@ impl<'a> ElementParser<'a> {
         self.consume_byte(b'\n')
     }
 
-    fn read_simple_string(&mut self) -> Result<Element> {
+    fn read_line(&mut self) -> Result<Vec<u8>> {
         let mut buffer = Vec::new();
         loop {
             match self.read_u8() {
                 Some(b'\r') => break,
+                // Like inline commands, lines can't grow the buffer forever
+                Some(_) if buffer.len() >= MAX_INLINE_LEN => bail!("too big line in request"),
                 Some(b) => buffer.push(b),
-                None => bail!("buffer terminated before \\r\\n"),
+                None => bail!(Incomplete),
             }
         }
 
         self.consume_byte(b'\n')?;
 
-        Ok(Element::SimpleString(String::from_utf8(buffer)?))
+        Ok(buffer)
+    }
+
+    fn read_simple_string(&mut self) -> Result<Element> {
+        Ok(Element::SimpleString(String::from_utf8(self.read_line()?)?))
+    }
+
+    fn read_i64_crlf(&mut self) -> Result<i64> {
+        let line = self.read_line()?;
+        std::str::from_utf8(&line)?
+            .parse()
+            .context("parsing integer")
+    }
+
+    fn read_boolean(&mut self) -> Result<Element> {
+        match self.read_line()?.as_slice() {
+            b"t" => Ok(Element::Boolean(true)),
+            b"f" => Ok(Element::Boolean(false)),
+            other => bail!("Invalid boolean {}", other.escape_ascii()),
+        }
+    }
+
+    fn read_double(&mut self) -> Result<Element> {
+        let line = self.read_line()?;
+        Ok(Element::Double(
+            std::str::from_utf8(&line)?
+                .parse()
+                .context("parsing double")?,
+        ))
+    }
+
+    fn read_big_number(&mut self) -> Result<Element> {
+        let line = String::from_utf8(self.read_line()?)?;
+        let digits = line.strip_prefix(['-', '+']).unwrap_or(&line);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            bail!("Invalid big number {line}");
+        }
+        Ok(Element::BigNumber(line))
     }
 
     fn read_usize_crlf(&mut self) -> Result<usize> {
         let mut value: usize = 0;
         loop {
             match self.read_u8() {
-                Some(b) if b.is_ascii_digit() => value = value * 10 + usize::from(b - b'0'),
+                Some(b) if b.is_ascii_digit() => {
+                    value = value
+                        .checked_mul(10)
+                        .and_then(|value| value.checked_add(usize::from(b - b'0')))
+                        .ok_or(anyhow!("length overflows usize"))?
+                }
                 Some(b'\r') => break,
                 Some(other) => bail!("Expected digit, found {}", other.escape_ascii().to_string()),
-                None => bail!("buffer terminated before \\r\\n"),
+                None => bail!(Incomplete),
             }
         }
 
@@ -85,40 +407,124 @@ impl<'a> ElementParser<'a> {
         Ok(value)
     }
 
-    fn read_bulk_string(&mut self) -> Result<Element> {
-        let n = self.read_usize_crlf()?;
-        if self.bytes.remaining() < n {
-            bail!(
-                "Attempted to read string of length {}, but buffer only has {} bytes remaining",
-                n,
-                self.bytes.remaining()
-            );
+    /// Consumes the `-1` length RESP2 uses for null bulk strings and arrays, if
+    /// that is what follows.
+    fn read_null_length(&mut self) -> Result<bool> {
+        if self.bytes.chunk().first() != Some(&b'-') {
+            return Ok(false);
         }
-
-        let s = self.bytes.chunk()[..n].to_vec();
-        self.bytes.advance(n);
-
+        self.consume_byte(b'-')?;
+        self.consume_byte(b'1')?;
         self.expect_crlf()?;
+        Ok(true)
+    }
 
-        Ok(Element::BulkString(s))
+    fn read_string_header(&mut self, verbatim: bool) -> Result<Header> {
+        let len = self.read_usize_crlf()?;
+        if len > MAX_BULK_LEN {
+            bail!("invalid bulk length {len}");
+        }
+        Ok(Header::String { len, verbatim })
+    }
+
+    fn read_aggregate_header(&mut self, kind: AggregateKind) -> Result<Header> {
+        let len = self.read_usize_crlf()?;
+        if len > MAX_ARRAY_LEN {
+            match kind {
+                AggregateKind::Map | AggregateKind::Attribute => bail!("invalid map length {len}"),
+                _ => bail!("invalid array length {len}"),
+            }
+        }
+        Ok(Header::Aggregate(kind, len))
     }
+}
 
-    fn read_array(&mut self) -> Result<Element> {
-        let n = self.read_usize_crlf()?;
-        let mut elements = Vec::with_capacity(n);
+/// Splits an inline command into its arguments, following the quoting rules of
+/// `redis-cli`: arguments are separated by whitespace, and may contain a part
+/// wrapped in double quotes (supporting escape sequences such as `\n` or `\x2a`)
+/// or in single quotes (where only `\'` is escaped), which ends the argument.
+///
+/// Returns `None` if the quotes are unbalanced, or a closing quote isn't followed
+/// by whitespace.
+fn split_args(line: &[u8]) -> Option<Vec<Bytes>> {
+    let mut args = Vec::new();
+    let mut i = 0;
 
-        for _ in 0..n {
-            elements.push(self.parse()?)
+    loop {
+        while line.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
         }
 
-        Ok(Element::Array(elements))
+        let mut arg = Vec::new();
+        while let Some(&b) = line.get(i).filter(|b| !b.is_ascii_whitespace()) {
+            i += 1;
+            match b {
+                b'"' => {
+                    loop {
+                        match *line.get(i)? {
+                            b'"' => break,
+                            b'\\' => {
+                                i += 1;
+                                let hex = line
+                                    .get(i + 1..i + 3)
+                                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                                match (*line.get(i)?, hex) {
+                                    (b'x', Some(b)) => {
+                                        arg.push(b);
+                                        i += 2;
+                                    }
+                                    (b'n', _) => arg.push(b'\n'),
+                                    (b'r', _) => arg.push(b'\r'),
+                                    (b't', _) => arg.push(b'\t'),
+                                    (b'b', _) => arg.push(0x08),
+                                    (b'a', _) => arg.push(0x07),
+                                    (other, _) => arg.push(other),
+                                }
+                            }
+                            other => arg.push(other),
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                b'\'' => {
+                    loop {
+                        match *line.get(i)? {
+                            b'\'' => break,
+                            b'\\' if line.get(i + 1) == Some(&b'\'') => {
+                                arg.push(b'\'');
+                                i += 1;
+                            }
+                            other => arg.push(other),
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                other => {
+                    arg.push(other);
+                    continue;
+                }
+            }
+            // The closing quote must be followed by a space or nothing at all
+            if line.get(i).is_some_and(|b| !b.is_ascii_whitespace()) {
+                return None;
+            }
+            break;
+        }
+        args.push(Bytes::from(arg));
     }
 }
 
 impl TryInto<Command> for Element {
     type Error = anyhow::Error;
 
-    fn try_into(self) -> std::result::Result<Command, Self::Error> {
+    fn try_into(self) -> Result<Command> {
         let args = match self {
             Element::Array(elements) => {
                 let mut args = Vec::with_capacity(elements.len());
@@ -136,85 +542,1968 @@ impl TryInto<Command> for Element {
         };
 
         if args.is_empty() {
-            bail!("Expected at least 1 arg, but got none");
+            bail!(RedisError::Protocol("empty command".to_string()));
         }
 
         match args[0].to_ascii_lowercase().deref() {
             b"ping" => parse_ping(&args[1..]),
             b"echo" => parse_echo(&args[1..]),
             b"set" => parse_set(&args[1..]),
-            b"get" => parse_get(&args[1..]),
+            b"get" => parse_key(&args[1..], "get").map(Command::Get),
+            b"expire" => parse_expire(&args[1..], "expire", TimeUnit::Seconds, false),
+            b"pexpire" => parse_expire(&args[1..], "pexpire", TimeUnit::Milliseconds, false),
+            b"expireat" => parse_expire(&args[1..], "expireat", TimeUnit::Seconds, true),
+            b"pexpireat" => parse_expire(&args[1..], "pexpireat", TimeUnit::Milliseconds, true),
+            b"ttl" => parse_key(&args[1..], "ttl").map(|key| Command::Ttl(key, TimeUnit::Seconds)),
+            b"pttl" => {
+                parse_key(&args[1..], "pttl").map(|key| Command::Ttl(key, TimeUnit::Milliseconds))
+            }
+            b"expiretime" => parse_key(&args[1..], "expiretime")
+                .map(|key| Command::ExpireTime(key, TimeUnit::Seconds)),
+            b"pexpiretime" => parse_key(&args[1..], "pexpiretime")
+                .map(|key| Command::ExpireTime(key, TimeUnit::Milliseconds)),
+            b"persist" => parse_key(&args[1..], "persist").map(Command::Persist),
+            b"del" => parse_keys(&args[1..], "del").map(Command::Del),
+            b"unlink" => parse_keys(&args[1..], "unlink").map(Command::Unlink),
+            b"exists" => parse_keys(&args[1..], "exists").map(Command::Exists),
+            b"type" => parse_key(&args[1..], "type").map(Command::Type),
+            b"rename" => match &args[1..] {
+                [source, destination] => Ok(Command::Rename(source.clone(), destination.clone())),
+                _ => bail!(RedisError::WrongArity("rename")),
+            },
+            b"renamenx" => match &args[1..] {
+                [source, destination] => Ok(Command::RenameNx(source.clone(), destination.clone())),
+                _ => bail!(RedisError::WrongArity("renamenx")),
+            },
+            b"copy" => parse_copy(&args[1..]),
+            b"touch" => parse_keys(&args[1..], "touch").map(Command::Touch),
+            b"incr" => parse_key(&args[1..], "incr").map(|key| Command::IncrBy(key, 1)),
+            b"decr" => parse_key(&args[1..], "decr").map(|key| Command::IncrBy(key, -1)),
+            b"incrby" => match &args[1..] {
+                [key, increment] => Ok(Command::IncrBy(key.clone(), parse_integer(increment)?)),
+                _ => bail!(RedisError::WrongArity("incrby")),
+            },
+            b"decrby" => match &args[1..] {
+                [key, decrement] => Ok(Command::IncrBy(
+                    key.clone(),
+                    parse_integer(decrement)?
+                        .checked_neg()
+                        .ok_or(RedisError::Err("decrement would overflow".to_string()))?,
+                )),
+                _ => bail!(RedisError::WrongArity("decrby")),
+            },
+            b"incrbyfloat" => match &args[1..] {
+                [key, increment] => Ok(Command::IncrByFloat(
+                    key.clone(),
+                    parse_long_double(increment)?,
+                )),
+                _ => bail!(RedisError::WrongArity("incrbyfloat")),
+            },
+            b"append" => match &args[1..] {
+                [key, value] => Ok(Command::Append(key.clone(), value.clone())),
+                _ => bail!(RedisError::WrongArity("append")),
+            },
+            b"strlen" => parse_key(&args[1..], "strlen").map(Command::Strlen),
+            b"getrange" => match &args[1..] {
+                [key, start, end] => Ok(Command::GetRange(
+                    key.clone(),
+                    parse_integer(start)?,
+                    parse_integer(end)?,
+                )),
+                _ => bail!(RedisError::WrongArity("getrange")),
+            },
+            b"setrange" => match &args[1..] {
+                [key, offset, value] => Ok(Command::SetRange(
+                    key.clone(),
+                    parse_integer(offset)?,
+                    value.clone(),
+                )),
+                _ => bail!(RedisError::WrongArity("setrange")),
+            },
+            b"getdel" => parse_key(&args[1..], "getdel").map(Command::GetDel),
+            b"getex" => parse_getex(&args[1..]),
+            // Equivalent to `SET key value GET`
+            b"getset" => match &args[1..] {
+                [key, value] => Ok(Command::Set(Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                    expiration: None,
+                    keep_ttl: false,
+                    condition: None,
+                    get: true,
+                })),
+                _ => bail!(RedisError::WrongArity("getset")),
+            },
+            b"lcs" => parse_lcs(&args[1..]),
+            b"mget" => parse_keys(&args[1..], "mget").map(Command::MGet),
+            b"mset" => parse_key_values(&args[1..], "mset").map(Command::MSet),
+            b"msetnx" => parse_key_values(&args[1..], "msetnx").map(Command::MSetNx),
+            b"setbit" => match &args[1..] {
+                [key, offset, bit] => Ok(Command::SetBit(
+                    key.clone(),
+                    parse_bit_offset(offset, 1)?,
+                    match parse_i64(bit) {
+                        Some(0) => false,
+                        Some(1) => true,
+                        _ => bail!(RedisError::Err(
+                            "bit is not an integer or out of range".to_string()
+                        )),
+                    },
+                )),
+                _ => bail!(RedisError::WrongArity("setbit")),
+            },
+            b"getbit" => match &args[1..] {
+                [key, offset] => Ok(Command::GetBit(key.clone(), parse_bit_offset(offset, 1)?)),
+                _ => bail!(RedisError::WrongArity("getbit")),
+            },
+            b"bitcount" => match &args[1..] {
+                [key] => Ok(Command::BitCount(key.clone(), None)),
+                [key, range @ ..] => Ok(Command::BitCount(
+                    key.clone(),
+                    Some(match parse_bit_range(range)? {
+                        range @ BitRange { end: Some(_), .. } => range,
+                        _ => bail!(RedisError::Syntax),
+                    }),
+                )),
+                [] => bail!(RedisError::WrongArity("bitcount")),
+            },
+            b"bitpos" => match &args[1..] {
+                [key, bit, range @ ..] => Ok(Command::BitPos(BitPos {
+                    key: key.clone(),
+                    bit: match parse_i64(bit) {
+                        Some(0) => false,
+                        Some(1) => true,
+                        _ => bail!(RedisError::Err(
+                            "The bit argument must be 1 or 0.".to_string()
+                        )),
+                    },
+                    range: match range {
+                        [] => None,
+                        range => Some(parse_bit_range(range)?),
+                    },
+                })),
+                _ => bail!(RedisError::WrongArity("bitpos")),
+            },
+            b"bitop" => parse_bitop(&args[1..]),
+            b"bitfield" => match &args[1..] {
+                [key, operations @ ..] => Ok(Command::BitField(
+                    key.clone(),
+                    parse_bitfield_ops(operations)?,
+                )),
+                [] => bail!(RedisError::WrongArity("bitfield")),
+            },
+            b"bitfield_ro" => match &args[1..] {
+                [key, operations @ ..] => {
+                    let operations = parse_bitfield_ops(operations)?;
+                    if operations
+                        .iter()
+                        .any(|operation| !matches!(operation, BitFieldOp::Get(..)))
+                    {
+                        bail!(RedisError::Err(
+                            "BITFIELD_RO only supports the GET subcommand".to_string()
+                        ));
+                    }
+                    Ok(Command::BitFieldRo(key.clone(), operations))
+                }
+                [] => bail!(RedisError::WrongArity("bitfield_ro")),
+            },
+            b"pfadd" => match &args[1..] {
+                [key, elements @ ..] => Ok(Command::PfAdd(key.clone(), elements.to_vec())),
+                [] => bail!(RedisError::WrongArity("pfadd")),
+            },
+            b"pfcount" => parse_keys(&args[1..], "pfcount").map(Command::PfCount),
+            b"pfmerge" => match &args[1..] {
+                [destination, sources @ ..] => {
+                    Ok(Command::PfMerge(destination.clone(), sources.to_vec()))
+                }
+                [] => bail!(RedisError::WrongArity("pfmerge")),
+            },
+            b"pfdebug" => match &args[1..] {
+                [subcommand, key] => Ok(Command::PfDebug(
+                    match subcommand.to_ascii_lowercase().deref() {
+                        b"getreg" => PfDebug::GetReg,
+                        b"decode" => PfDebug::Decode,
+                        b"encoding" => PfDebug::Encoding,
+                        b"todense" => PfDebug::ToDense,
+                        _ => bail!(RedisError::Err(format!(
+                            "Unknown PFDEBUG subcommand '{}'",
+                            String::from_utf8_lossy(subcommand)
+                        ))),
+                    },
+                    key.clone(),
+                )),
+                _ => bail!(RedisError::WrongArity("pfdebug")),
+            },
+            b"lpush" => parse_push(&args[1..], "lpush")
+                .map(|(key, elements)| Command::Push(ListEnd::Left, key, elements)),
+            b"rpush" => parse_push(&args[1..], "rpush")
+                .map(|(key, elements)| Command::Push(ListEnd::Right, key, elements)),
+            b"lpushx" => parse_push(&args[1..], "lpushx")
+                .map(|(key, elements)| Command::PushX(ListEnd::Left, key, elements)),
+            b"rpushx" => parse_push(&args[1..], "rpushx")
+                .map(|(key, elements)| Command::PushX(ListEnd::Right, key, elements)),
+            b"lpop" => parse_pop(&args[1..], ListEnd::Left, "lpop"),
+            b"rpop" => parse_pop(&args[1..], ListEnd::Right, "rpop"),
+            b"llen" => parse_key(&args[1..], "llen").map(Command::LLen),
+            b"lrange" => match &args[1..] {
+                [key, start, stop] => Ok(Command::LRange(
+                    key.clone(),
+                    parse_integer(start)?,
+                    parse_integer(stop)?,
+                )),
+                _ => bail!(RedisError::WrongArity("lrange")),
+            },
+            b"lindex" => match &args[1..] {
+                [key, index] => Ok(Command::LIndex(key.clone(), parse_integer(index)?)),
+                _ => bail!(RedisError::WrongArity("lindex")),
+            },
+            b"lset" => match &args[1..] {
+                [key, index, element] => Ok(Command::LSet(
+                    key.clone(),
+                    parse_integer(index)?,
+                    element.clone(),
+                )),
+                _ => bail!(RedisError::WrongArity("lset")),
+            },
+            b"linsert" => match &args[1..] {
+                [key, position, pivot, element] => Ok(Command::LInsert(LInsert {
+                    key: key.clone(),
+                    before: match position.to_ascii_lowercase().deref() {
+                        b"before" => true,
+                        b"after" => false,
+                        _ => bail!(RedisError::Syntax),
+                    },
+                    pivot: pivot.clone(),
+                    element: element.clone(),
+                })),
+                _ => bail!(RedisError::WrongArity("linsert")),
+            },
+            b"lrem" => match &args[1..] {
+                [key, count, element] => Ok(Command::LRem(
+                    key.clone(),
+                    parse_integer(count)?,
+                    element.clone(),
+                )),
+                _ => bail!(RedisError::WrongArity("lrem")),
+            },
+            b"ltrim" => match &args[1..] {
+                [key, start, stop] => Ok(Command::LTrim(
+                    key.clone(),
+                    parse_integer(start)?,
+                    parse_integer(stop)?,
+                )),
+                _ => bail!(RedisError::WrongArity("ltrim")),
+            },
+            b"lpos" => parse_lpos(&args[1..]),
+            b"lmove" => match &args[1..] {
+                [source, destination, from, to] => Ok(Command::LMove(LMove {
+                    source: source.clone(),
+                    destination: destination.clone(),
+                    from: parse_list_end(from)?,
+                    to: parse_list_end(to)?,
+                })),
+                _ => bail!(RedisError::WrongArity("lmove")),
+            },
+            b"rpoplpush" => match &args[1..] {
+                [source, destination] => Ok(Command::LMove(LMove {
+                    source: source.clone(),
+                    destination: destination.clone(),
+                    from: ListEnd::Right,
+                    to: ListEnd::Left,
+                })),
+                _ => bail!(RedisError::WrongArity("rpoplpush")),
+            },
+            b"lmpop" => {
+                let (keys, end, count) = parse_mpop(&args[1..], "lmpop", parse_list_end)?;
+                Ok(Command::LMPop(LMPop { keys, end, count }))
+            }
+            b"blpop" => parse_bpop(&args[1..], ListEnd::Left, "blpop"),
+            b"brpop" => parse_bpop(&args[1..], ListEnd::Right, "brpop"),
+            b"blmove" => match &args[1..] {
+                [source, destination, from, to, timeout] => Ok(Command::Blocking(Blocking {
+                    keys: vec![source.clone()],
+                    operation: BlockingOp::LMove {
+                        destination: destination.clone(),
+                        from: parse_list_end(from)?,
+                        to: parse_list_end(to)?,
+                    },
+                    timeout: parse_timeout(timeout)?,
+                })),
+                _ => bail!(RedisError::WrongArity("blmove")),
+            },
+            b"brpoplpush" => match &args[1..] {
+                [source, destination, timeout] => Ok(Command::Blocking(Blocking {
+                    keys: vec![source.clone()],
+                    operation: BlockingOp::LMove {
+                        destination: destination.clone(),
+                        from: ListEnd::Right,
+                        to: ListEnd::Left,
+                    },
+                    timeout: parse_timeout(timeout)?,
+                })),
+                _ => bail!(RedisError::WrongArity("brpoplpush")),
+            },
+            b"blmpop" => match &args[1..] {
+                [timeout, args @ ..] if args.len() >= 3 => {
+                    let timeout = parse_timeout(timeout)?;
+                    let (keys, end, count) = parse_mpop(args, "blmpop", parse_list_end)?;
+                    Ok(Command::Blocking(Blocking {
+                        keys,
+                        operation: BlockingOp::LMPop { end, count },
+                        timeout,
+                    }))
+                }
+                _ => bail!(RedisError::WrongArity("blmpop")),
+            },
+            b"hset" => match &args[1..] {
+                [key, pairs @ ..] => {
+                    Ok(Command::HSet(key.clone(), parse_key_values(pairs, "hset")?))
+                }
+                [] => bail!(RedisError::WrongArity("hset")),
+            },
+            b"hsetnx" => match &args[1..] {
+                [key, field, value] => {
+                    Ok(Command::HSetNx(key.clone(), field.clone(), value.clone()))
+                }
+                _ => bail!(RedisError::WrongArity("hsetnx")),
+            },
+            b"hget" => match &args[1..] {
+                [key, field] => Ok(Command::HGet(key.clone(), field.clone())),
+                _ => bail!(RedisError::WrongArity("hget")),
+            },
+            b"hmget" => match &args[1..] {
+                [key, fields @ ..] if !fields.is_empty() => {
+                    Ok(Command::HMGet(key.clone(), fields.to_vec()))
+                }
+                _ => bail!(RedisError::WrongArity("hmget")),
+            },
+            b"hdel" => match &args[1..] {
+                [key, fields @ ..] if !fields.is_empty() => {
+                    Ok(Command::HDel(key.clone(), fields.to_vec()))
+                }
+                _ => bail!(RedisError::WrongArity("hdel")),
+            },
+            b"hexists" => match &args[1..] {
+                [key, field] => Ok(Command::HExists(key.clone(), field.clone())),
+                _ => bail!(RedisError::WrongArity("hexists")),
+            },
+            b"hlen" => parse_key(&args[1..], "hlen").map(Command::HLen),
+            b"hkeys" => parse_key(&args[1..], "hkeys").map(Command::HKeys),
+            b"hvals" => parse_key(&args[1..], "hvals").map(Command::HVals),
+            b"hgetall" => parse_key(&args[1..], "hgetall").map(Command::HGetAll),
+            b"hincrby" => match &args[1..] {
+                [key, field, increment] => Ok(Command::HIncrBy(
+                    key.clone(),
+                    field.clone(),
+                    parse_integer(increment)?,
+                )),
+                _ => bail!(RedisError::WrongArity("hincrby")),
+            },
+            b"hincrbyfloat" => match &args[1..] {
+                [key, field, increment] => Ok(Command::HIncrByFloat(
+                    key.clone(),
+                    field.clone(),
+                    parse_long_double(increment)?,
+                )),
+                _ => bail!(RedisError::WrongArity("hincrbyfloat")),
+            },
+            b"hstrlen" => match &args[1..] {
+                [key, field] => Ok(Command::HStrlen(key.clone(), field.clone())),
+                _ => bail!(RedisError::WrongArity("hstrlen")),
+            },
+            b"hrandfield" => parse_hrandfield(&args[1..]),
+            b"hscan" => parse_element_scan(&args[1..], "hscan").map(Command::HScan),
+            b"hexpire" => parse_hexpire(&args[1..], "hexpire", TimeUnit::Seconds, false),
+            b"hpexpire" => parse_hexpire(&args[1..], "hpexpire", TimeUnit::Milliseconds, false),
+            b"hexpireat" => parse_hexpire(&args[1..], "hexpireat", TimeUnit::Seconds, true),
+            b"hpexpireat" => parse_hexpire(&args[1..], "hpexpireat", TimeUnit::Milliseconds, true),
+            b"httl" => match &args[1..] {
+                [key, fields @ ..] => Ok(Command::HTtl(
+                    key.clone(),
+                    parse_fields(fields, "httl")?,
+                    TimeUnit::Seconds,
+                )),
+                [] => bail!(RedisError::WrongArity("httl")),
+            },
+            b"hpttl" => match &args[1..] {
+                [key, fields @ ..] => Ok(Command::HTtl(
+                    key.clone(),
+                    parse_fields(fields, "hpttl")?,
+                    TimeUnit::Milliseconds,
+                )),
+                [] => bail!(RedisError::WrongArity("hpttl")),
+            },
+            b"hexpiretime" => match &args[1..] {
+                [key, fields @ ..] => Ok(Command::HExpireTime(
+                    key.clone(),
+                    parse_fields(fields, "hexpiretime")?,
+                    TimeUnit::Seconds,
+                )),
+                [] => bail!(RedisError::WrongArity("hexpiretime")),
+            },
+            b"hpexpiretime" => match &args[1..] {
+                [key, fields @ ..] => Ok(Command::HExpireTime(
+                    key.clone(),
+                    parse_fields(fields, "hpexpiretime")?,
+                    TimeUnit::Milliseconds,
+                )),
+                [] => bail!(RedisError::WrongArity("hpexpiretime")),
+            },
+            b"hpersist" => match &args[1..] {
+                [key, fields @ ..] => Ok(Command::HPersist(
+                    key.clone(),
+                    parse_fields(fields, "hpersist")?,
+                )),
+                [] => bail!(RedisError::WrongArity("hpersist")),
+            },
+            b"sadd" => match &args[1..] {
+                [key, members @ ..] if !members.is_empty() => {
+                    Ok(Command::SAdd(key.clone(), members.to_vec()))
+                }
+                _ => bail!(RedisError::WrongArity("sadd")),
+            },
+            b"srem" => match &args[1..] {
+                [key, members @ ..] if !members.is_empty() => {
+                    Ok(Command::SRem(key.clone(), members.to_vec()))
+                }
+                _ => bail!(RedisError::WrongArity("srem")),
+            },
+            b"sismember" => match &args[1..] {
+                [key, member] => Ok(Command::SIsMember(key.clone(), member.clone())),
+                _ => bail!(RedisError::WrongArity("sismember")),
+            },
+            b"smismember" => match &args[1..] {
+                [key, members @ ..] if !members.is_empty() => {
+                    Ok(Command::SMIsMember(key.clone(), members.to_vec()))
+                }
+                _ => bail!(RedisError::WrongArity("smismember")),
+            },
+            b"scard" => parse_key(&args[1..], "scard").map(Command::SCard),
+            b"smembers" => parse_key(&args[1..], "smembers").map(Command::SMembers),
+            b"spop" => match &args[1..] {
+                [key] => Ok(Command::SPop(key.clone(), None)),
+                [key, count] => Ok(Command::SPop(key.clone(), Some(parse_positive(count)?))),
+                [_, _, _] => bail!(RedisError::Syntax),
+                _ => bail!(RedisError::WrongArity("spop")),
+            },
+            b"srandmember" => match &args[1..] {
+                [key] => Ok(Command::SRandMember(key.clone(), None)),
+                [key, count] => Ok(Command::SRandMember(
+                    key.clone(),
+                    Some(parse_integer(count)?),
+                )),
+                [_, _, _] => bail!(RedisError::Syntax),
+                _ => bail!(RedisError::WrongArity("srandmember")),
+            },
+            b"smove" => match &args[1..] {
+                [source, destination, member] => Ok(Command::SMove(
+                    source.clone(),
+                    destination.clone(),
+                    member.clone(),
+                )),
+                _ => bail!(RedisError::WrongArity("smove")),
+            },
+            b"sinter" => parse_keys(&args[1..], "sinter")
+                .map(|keys| Command::SetOp(SetOperation::Inter, keys)),
+            b"sunion" => parse_keys(&args[1..], "sunion")
+                .map(|keys| Command::SetOp(SetOperation::Union, keys)),
+            b"sdiff" => {
+                parse_keys(&args[1..], "sdiff").map(|keys| Command::SetOp(SetOperation::Diff, keys))
+            }
+            b"sinterstore" => parse_set_op_store(&args[1..], SetOperation::Inter, "sinterstore"),
+            b"sunionstore" => parse_set_op_store(&args[1..], SetOperation::Union, "sunionstore"),
+            b"sdiffstore" => parse_set_op_store(&args[1..], SetOperation::Diff, "sdiffstore"),
+            b"sintercard" => parse_intercard(&args[1..], "sintercard")
+                .map(|(keys, limit)| Command::SInterCard(keys, limit)),
+            b"sscan" => parse_element_scan(&args[1..], "sscan").map(Command::SScan),
+            b"zadd" => parse_zadd(&args[1..]),
+            b"zrem" => match &args[1..] {
+                [key, members @ ..] if !members.is_empty() => {
+                    Ok(Command::ZRem(key.clone(), members.to_vec()))
+                }
+                _ => bail!(RedisError::WrongArity("zrem")),
+            },
+            b"zscore" => match &args[1..] {
+                [key, member] => Ok(Command::ZScore(key.clone(), member.clone())),
+                _ => bail!(RedisError::WrongArity("zscore")),
+            },
+            b"zmscore" => match &args[1..] {
+                [key, members @ ..] if !members.is_empty() => {
+                    Ok(Command::ZMScore(key.clone(), members.to_vec()))
+                }
+                _ => bail!(RedisError::WrongArity("zmscore")),
+            },
+            b"zincrby" => match &args[1..] {
+                [key, increment, member] => Ok(Command::ZIncrBy(
+                    key.clone(),
+                    parse_float(increment)?,
+                    member.clone(),
+                )),
+                _ => bail!(RedisError::WrongArity("zincrby")),
+            },
+            b"zcard" => parse_key(&args[1..], "zcard").map(Command::ZCard),
+            b"zcount" => match &args[1..] {
+                [key, min, max] => Ok(Command::ZCount(key.clone(), parse_score_range(min, max)?)),
+                _ => bail!(RedisError::WrongArity("zcount")),
+            },
+            b"zrank" => parse_zrank(&args[1..], false, "zrank"),
+            b"zrevrank" => parse_zrank(&args[1..], true, "zrevrank"),
+            b"zrange" => parse_zrange(&args[1..], "zrange").map(Command::ZRange),
+            b"zrangestore" => match &args[1..] {
+                [destination, args @ ..] => Ok(Command::ZRangeStore(
+                    destination.clone(),
+                    parse_zrange(args, "zrangestore")?,
+                )),
+                [] => bail!(RedisError::WrongArity("zrangestore")),
+            },
+            b"zscan" => parse_element_scan(&args[1..], "zscan").map(Command::ZScan),
+            b"zunion" => {
+                parse_zset_op(&args[1..], SetOperation::Union, "zunion", false).map(Command::ZSetOp)
+            }
+            b"zinter" => {
+                parse_zset_op(&args[1..], SetOperation::Inter, "zinter", false).map(Command::ZSetOp)
+            }
+            b"zdiff" => {
+                parse_zset_op(&args[1..], SetOperation::Diff, "zdiff", false).map(Command::ZSetOp)
+            }
+            b"zunionstore" => parse_zset_op_store(&args[1..], SetOperation::Union, "zunionstore"),
+            b"zinterstore" => parse_zset_op_store(&args[1..], SetOperation::Inter, "zinterstore"),
+            b"zdiffstore" => parse_zset_op_store(&args[1..], SetOperation::Diff, "zdiffstore"),
+            b"zintercard" => parse_intercard(&args[1..], "zintercard")
+                .map(|(keys, limit)| Command::ZInterCard(keys, limit)),
+            b"zremrangebyrank" => match &args[1..] {
+                [key, start, stop] => Ok(Command::ZRemRange(
+                    key.clone(),
+                    ZRangeBy::Rank(parse_integer(start)?, parse_integer(stop)?),
+                )),
+                _ => bail!(RedisError::WrongArity("zremrangebyrank")),
+            },
+            b"zremrangebyscore" => match &args[1..] {
+                [key, min, max] => Ok(Command::ZRemRange(
+                    key.clone(),
+                    ZRangeBy::Score(parse_score_range(min, max)?),
+                )),
+                _ => bail!(RedisError::WrongArity("zremrangebyscore")),
+            },
+            b"zremrangebylex" => match &args[1..] {
+                [key, min, max] => Ok(Command::ZRemRange(
+                    key.clone(),
+                    ZRangeBy::Lex(parse_lex_range(min, max)?),
+                )),
+                _ => bail!(RedisError::WrongArity("zremrangebylex")),
+            },
+            b"zpopmin" => parse_zpop(&args[1..], SortedSetEnd::Min, "zpopmin"),
+            b"zpopmax" => parse_zpop(&args[1..], SortedSetEnd::Max, "zpopmax"),
+            b"zmpop" => {
+                let (keys, end, count) = parse_mpop(&args[1..], "zmpop", parse_sorted_set_end)?;
+                Ok(Command::ZMPop(ZMPop { keys, end, count }))
+            }
+            b"bzpopmin" => parse_bzpop(&args[1..], SortedSetEnd::Min, "bzpopmin"),
+            b"bzpopmax" => parse_bzpop(&args[1..], SortedSetEnd::Max, "bzpopmax"),
+            b"xadd" => parse_xadd(&args[1..]),
+            b"xlen" => parse_key(&args[1..], "xlen").map(Command::XLen),
+            b"xrange" => parse_xrange(&args[1..], false, "xrange"),
+            b"xrevrange" => parse_xrange(&args[1..], true, "xrevrange"),
+            b"xdel" => match &args[1..] {
+                [key, ids @ ..] if !ids.is_empty() => Ok(Command::XDel(
+                    key.clone(),
+                    ids.iter()
+                        .map(|id| parse_stream_id(id, 0))
+                        .collect::<Result<_>>()?,
+                )),
+                _ => bail!(RedisError::WrongArity("xdel")),
+            },
+            b"xtrim" => match &args[1..] {
+                [key, args @ ..] if args.len() >= 2 => match parse_stream_trim(args)? {
+                    (trim, []) => Ok(Command::XTrim(key.clone(), trim)),
+                    _ => bail!(RedisError::Syntax),
+                },
+                _ => bail!(RedisError::WrongArity("xtrim")),
+            },
+            b"xsetid" => parse_xsetid(&args[1..]),
+            b"xread" => parse_xread(&args[1..]),
+            b"bzmpop" => match &args[1..] {
+                [timeout, args @ ..] if args.len() >= 3 => {
+                    let timeout = parse_timeout(timeout)?;
+                    let (keys, end, count) = parse_mpop(args, "bzmpop", parse_sorted_set_end)?;
+                    Ok(Command::Blocking(Blocking {
+                        keys,
+                        operation: BlockingOp::ZMPop { end, count },
+                        timeout,
+                    }))
+                }
+                _ => bail!(RedisError::WrongArity("bzmpop")),
+            },
+            b"multi" => match &args[1..] {
+                [] => Ok(Command::Multi),
+                _ => bail!(RedisError::WrongArity("multi")),
+            },
+            b"exec" => match &args[1..] {
+                [] => Ok(Command::Exec),
+                _ => bail!(RedisError::WrongArity("exec")),
+            },
+            b"discard" => match &args[1..] {
+                [] => Ok(Command::Discard),
+                _ => bail!(RedisError::WrongArity("discard")),
+            },
+            b"keys" => parse_key(&args[1..], "keys").map(Command::Keys),
+            b"scan" => parse_scan(&args[1..]),
             b"info" => parse_info(&args[1..]),
             b"replconf" => parse_replconf(&args[1..]),
             b"psync" => parse_psync(&args[1..]),
-            other => bail!("Unrecognized command {}", String::from_utf8_lossy(other)),
+            b"hello" => parse_hello(&args[1..]),
+            _ => bail!(RedisError::UnknownCommand {
+                name: String::from_utf8_lossy(&args[0]).into_owned(),
+                args: args[1..]
+                    .iter()
+                    .map(|arg| String::from_utf8_lossy(&arg[..arg.len().min(128)]).into_owned())
+                    .fold(String::new(), |mut args, arg| {
+                        // Like Redis, only quote the first 128 or so characters
+                        if args.len() < 128 {
+                            args.push_str(&format!("'{arg}' "));
+                        }
+                        args
+                    }),
+            }),
         }
     }
 }
 
-fn parse_ping(args: &[Vec<u8>]) -> Result<Command> {
-    match args.first() {
-        None => Ok(Command::Ping(None)),
-        Some(bytes) => Ok(Command::Ping(Some(String::from_utf8(bytes.clone())?))),
+fn parse_ping(args: &[Bytes]) -> Result<Command> {
+    match args {
+        [] => Ok(Command::Ping(None)),
+        [bytes] => Ok(Command::Ping(Some(bytes.clone()))),
+        _ => bail!(RedisError::WrongArity("ping")),
     }
 }
 
-fn parse_echo(args: &[Vec<u8>]) -> Result<Command> {
-    match args.first() {
-        None => bail!("ECHO command requires an argument"),
-        Some(bytes) => Ok(Command::Echo(String::from_utf8(bytes.clone())?)),
+fn parse_echo(args: &[Bytes]) -> Result<Command> {
+    match args {
+        [bytes] => Ok(Command::Echo(bytes.clone())),
+        _ => bail!(RedisError::WrongArity("echo")),
     }
 }
 
-fn parse_set(args: &[Vec<u8>]) -> Result<Command> {
+fn parse_set(args: &[Bytes]) -> Result<Command> {
     let mut args = args.iter();
 
-    let key = String::from_utf8(
-        args.next()
-            .ok_or(anyhow!("missing mandatory key argument"))?
-            .clone(),
-    )?;
-    let value = String::from_utf8(
-        args.next()
-            .ok_or(anyhow!("missing mandatory value argument"))?
-            .clone(),
-    )?;
+    let key = args.next().ok_or(RedisError::WrongArity("set"))?.clone();
+    let value = args.next().ok_or(RedisError::WrongArity("set"))?.clone();
     let mut expiration = None;
+    let mut keep_ttl = false;
+    let mut condition = None;
+    let mut get = false;
 
-    match args.next() {
-        Some(arg) if arg.to_ascii_lowercase() == b"px" => {
-            let expiration_raw = args.next().ok_or(anyhow!(
-                "PX needs to be followed by the expiration date in milliseconds",
-            ))?;
-            let expiration_millis = String::from_utf8(expiration_raw.clone())
-                .context("parsing millis as utf8")?
-                .parse()
-                .context("parsing millis as number")?;
-            expiration = Some(Duration::from_millis(expiration_millis));
+    while let Some(arg) = args.next() {
+        match arg.to_ascii_lowercase().deref() {
+            option @ (b"ex" | b"px" | b"exat" | b"pxat") if expiration.is_none() && !keep_ttl => {
+                let raw = args.next().ok_or(RedisError::Syntax)?;
+                expiration = Some(parse_expiration(option, raw, "set")?);
+            }
+            b"keepttl" if expiration.is_none() && !keep_ttl => keep_ttl = true,
+            b"nx" if condition.is_none() => condition = Some(SetCondition::Nx),
+            b"xx" if condition.is_none() => condition = Some(SetCondition::Xx),
+            b"get" => get = true,
+            _ => bail!(RedisError::Syntax),
         }
-        Some(other) => bail!("Unsupported argument {}", String::from_utf8_lossy(other)),
-        None => {}
     }
 
     Ok(Command::Set(Set {
         key,
         value,
         expiration,
+        keep_ttl,
+        condition,
+        get,
+    }))
+}
+
+fn parse_getex(args: &[Bytes]) -> Result<Command> {
+    let mut args = args.iter();
+
+    let key = args.next().ok_or(RedisError::WrongArity("getex"))?.clone();
+    let mut expiration = None;
+    let mut persist = false;
+
+    while let Some(arg) = args.next() {
+        match arg.to_ascii_lowercase().deref() {
+            option @ (b"ex" | b"px" | b"exat" | b"pxat") if expiration.is_none() && !persist => {
+                let raw = args.next().ok_or(RedisError::Syntax)?;
+                expiration = Some(parse_expiration(option, raw, "getex")?);
+            }
+            b"persist" if expiration.is_none() && !persist => persist = true,
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::GetEx(GetEx {
+        key,
+        expiration,
+        persist,
+    }))
+}
+
+fn parse_lcs(args: &[Bytes]) -> Result<Command> {
+    let (key1, key2, options) = match args {
+        [key1, key2, options @ ..] => (key1.clone(), key2.clone(), options),
+        _ => bail!(RedisError::WrongArity("lcs")),
+    };
+
+    let mut len = false;
+    let mut idx = false;
+    let mut min_match_len = 0;
+    let mut with_match_len = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().deref() {
+            b"len" => len = true,
+            b"idx" => idx = true,
+            b"withmatchlen" => with_match_len = true,
+            b"minmatchlen" => {
+                let raw = options.next().ok_or(RedisError::Syntax)?;
+                // Negative lengths are the same as no minimum at all
+                min_match_len = parse_integer(raw)?.max(0) as usize;
+            }
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+    if len && idx {
+        bail!(RedisError::Err(
+            "If you want both the length and indexes, please just use IDX.".to_string()
+        ));
+    }
+
+    Ok(Command::Lcs(Lcs {
+        key1,
+        key2,
+        len,
+        idx,
+        min_match_len,
+        with_match_len,
+    }))
+}
+
+/// Parses an offset in bits, or in multiples of `width` bits if prefixed by
+/// `#`. The offset must fall within the largest string allowed.
+fn parse_bit_offset(raw: &[u8], width: u32) -> Result<u64> {
+    let offset = match raw.strip_prefix(b"#") {
+        Some(index) => parse_i64(index).and_then(|index| index.checked_mul(width as i64)),
+        None => parse_i64(raw),
+    };
+    match offset {
+        Some(offset) if offset >= 0 && (offset as u64 >> 3) < MAX_BULK_LEN as u64 => {
+            Ok(offset as u64)
+        }
+        _ => bail!(RedisError::Err(
+            "bit offset is not an integer or out of range".to_string()
+        )),
+    }
+}
+
+/// Parses the `start [end [BYTE | BIT]]` arguments of `BITCOUNT` and `BITPOS`.
+fn parse_bit_range(args: &[Bytes]) -> Result<BitRange> {
+    let (start, end, unit) = match args {
+        [start] => (start, None, None),
+        [start, end] => (start, Some(end), None),
+        [start, end, unit] => (start, Some(end), Some(unit)),
+        _ => bail!(RedisError::Syntax),
+    };
+
+    Ok(BitRange {
+        start: parse_integer(start)?,
+        end: end.map(|end| parse_integer(end)).transpose()?,
+        unit: match unit.map(|unit| unit.to_ascii_lowercase()).as_deref() {
+            None | Some(b"byte") => BitUnit::Byte,
+            Some(b"bit") => BitUnit::Bit,
+            Some(_) => bail!(RedisError::Syntax),
+        },
+    })
+}
+
+fn parse_bitop(args: &[Bytes]) -> Result<Command> {
+    let (operation, destination, keys) = match args {
+        [operation, destination, keys @ ..] if !keys.is_empty() => {
+            (operation, destination.clone(), keys.to_vec())
+        }
+        _ => bail!(RedisError::WrongArity("bitop")),
+    };
+
+    let operation = match operation.to_ascii_lowercase().deref() {
+        b"and" => BitOperation::And,
+        b"or" => BitOperation::Or,
+        b"xor" => BitOperation::Xor,
+        b"not" if keys.len() == 1 => BitOperation::Not,
+        b"not" => bail!(RedisError::Err(
+            "BITOP NOT must be called with a single source key.".to_string()
+        )),
+        _ => bail!(RedisError::Syntax),
+    };
+
+    Ok(Command::BitOp(operation, destination, keys))
+}
+
+fn parse_bitfield_ops(args: &[Bytes]) -> Result<Vec<BitFieldOp>> {
+    let mut operations = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut next = || args.next().ok_or(RedisError::Syntax);
+        operations.push(match arg.to_ascii_lowercase().deref() {
+            b"get" => {
+                let field_type = parse_bitfield_type(next()?)?;
+                let offset = parse_bit_offset(next()?, field_type.bits)?;
+                BitFieldOp::Get(field_type, offset)
+            }
+            b"set" => {
+                let field_type = parse_bitfield_type(next()?)?;
+                let offset = parse_bit_offset(next()?, field_type.bits)?;
+                BitFieldOp::Set(field_type, offset, parse_integer(next()?)?)
+            }
+            b"incrby" => {
+                let field_type = parse_bitfield_type(next()?)?;
+                let offset = parse_bit_offset(next()?, field_type.bits)?;
+                BitFieldOp::IncrBy(field_type, offset, parse_integer(next()?)?)
+            }
+            b"overflow" => BitFieldOp::Overflow(match next()?.to_ascii_lowercase().deref() {
+                b"wrap" => BitFieldOverflow::Wrap,
+                b"sat" => BitFieldOverflow::Sat,
+                b"fail" => BitFieldOverflow::Fail,
+                _ => bail!(RedisError::Err(
+                    "Invalid OVERFLOW type specified".to_string()
+                )),
+            }),
+            _ => bail!(RedisError::Syntax),
+        });
+    }
+    Ok(operations)
+}
+
+/// Parses types such as `i8` or `u16`.
+fn parse_bitfield_type(raw: &[u8]) -> Result<BitFieldType> {
+    let (signed, bits) = match raw.split_first() {
+        Some((b'i' | b'I', bits)) => (true, parse_i64(bits)),
+        Some((b'u' | b'U', bits)) => (false, parse_i64(bits)),
+        _ => (false, None),
+    };
+    match bits {
+        Some(bits @ 1..=64) if signed => Ok(BitFieldType {
+            signed,
+            bits: bits as u32,
+        }),
+        Some(bits @ 1..=63) if !signed => Ok(BitFieldType {
+            signed,
+            bits: bits as u32,
+        }),
+        _ => bail!(RedisError::Err(
+            "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+                .to_string()
+        )),
+    }
+}
+
+fn parse_list_end(raw: &[u8]) -> Result<ListEnd> {
+    match raw.to_ascii_lowercase().deref() {
+        b"left" => Ok(ListEnd::Left),
+        b"right" => Ok(ListEnd::Right),
+        _ => bail!(RedisError::Syntax),
+    }
+}
+
+fn parse_push(args: &[Bytes], command: &'static str) -> Result<(Bytes, Vec<Bytes>)> {
+    match args {
+        [key, elements @ ..] if !elements.is_empty() => Ok((key.clone(), elements.to_vec())),
+        _ => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+fn parse_pop(args: &[Bytes], end: ListEnd, command: &'static str) -> Result<Command> {
+    match args {
+        [key] => Ok(Command::Pop(end, key.clone(), None)),
+        [key, count] => Ok(Command::Pop(end, key.clone(), Some(parse_positive(count)?))),
+        _ => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+/// Parses a count that can't be negative.
+fn parse_positive(raw: &[u8]) -> Result<usize> {
+    match parse_integer(raw)? {
+        count if count < 0 => bail!(RedisError::Err(
+            "value is out of range, must be positive".to_string()
+        )),
+        count => Ok(count as usize),
+    }
+}
+
+fn parse_sorted_set_end(raw: &[u8]) -> Result<SortedSetEnd> {
+    match raw.to_ascii_lowercase().deref() {
+        b"min" => Ok(SortedSetEnd::Min),
+        b"max" => Ok(SortedSetEnd::Max),
+        _ => bail!(RedisError::Syntax),
+    }
+}
+
+fn parse_bpop(args: &[Bytes], end: ListEnd, command: &'static str) -> Result<Command> {
+    match args {
+        [keys @ .., timeout] if !keys.is_empty() => Ok(Command::Blocking(Blocking {
+            keys: keys.to_vec(),
+            operation: BlockingOp::Pop(end),
+            timeout: parse_timeout(timeout)?,
+        })),
+        _ => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+/// Parses the timeout of blocking commands, in seconds. Like Redis, timeouts
+/// are truncated to milliseconds, and 0 means waiting forever.
+fn parse_timeout(raw: &[u8]) -> Result<Option<Duration>> {
+    let seconds = parse_f64(raw).ok_or(RedisError::Err(
+        "timeout is not a float or out of range".to_string(),
+    ))?;
+    let millis = seconds * 1000.0;
+    if !millis.is_finite() || millis > i64::MAX as f64 {
+        bail!(RedisError::Err("timeout is out of range".to_string()));
+    }
+    if millis < 0.0 {
+        bail!(RedisError::Err("timeout is negative".to_string()));
+    }
+    Ok(match millis as u64 {
+        0 => None,
+        millis => Some(Duration::from_millis(millis)),
+    })
+}
+
+fn parse_lpos(args: &[Bytes]) -> Result<Command> {
+    let (key, element, options) = match args {
+        [key, element, options @ ..] => (key.clone(), element.clone(), options),
+        _ => bail!(RedisError::WrongArity("lpos")),
+    };
+
+    let mut rank = 1;
+    let mut count = None;
+    let mut max_len = 0;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = parse_integer(options.next().ok_or(RedisError::Syntax)?)?;
+        match option.to_ascii_lowercase().deref() {
+            b"rank" if value == 0 || value == i64::MIN => bail!(RedisError::Err(
+                "RANK can't be zero: use 1 to start from the first match, 2 from the second ... \
+                 or use negative to start from the end of the list"
+                    .to_string()
+            )),
+            b"rank" => rank = value,
+            b"count" if value < 0 => {
+                bail!(RedisError::Err("COUNT can't be negative".to_string()))
+            }
+            b"count" => count = Some(value as usize),
+            b"maxlen" if value < 0 => {
+                bail!(RedisError::Err("MAXLEN can't be negative".to_string()))
+            }
+            b"maxlen" => max_len = value as usize,
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::LPos(LPos {
+        key,
+        element,
+        rank,
+        count,
+        max_len,
+    }))
+}
+
+/// Parses the `numkeys key [key ...] <end> [COUNT count]` arguments of `LMPOP`,
+/// `BLMPOP`, `ZMPOP` and `BZMPOP`, the end being parsed by `parse_end`.
+fn parse_mpop<E>(
+    args: &[Bytes],
+    command: &'static str,
+    parse_end: fn(&[u8]) -> Result<E>,
+) -> Result<(Vec<Bytes>, E, usize)> {
+    let (num_keys, args) = match args {
+        [num_keys, args @ ..] if args.len() >= 2 => (parse_integer(num_keys)?, args),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+    if num_keys <= 0 {
+        bail!(RedisError::Err(
+            "numkeys should be greater than 0".to_string()
+        ));
+    }
+    let num_keys = num_keys as usize;
+    if num_keys > args.len() {
+        bail!(RedisError::Err(
+            "Number of keys can't be greater than number of args".to_string()
+        ));
+    }
+
+    let (keys, args) = args.split_at(num_keys);
+    let (end, options) = args.split_first().ok_or(RedisError::Syntax)?;
+    let end = parse_end(end)?;
+    let count = match options {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => match parse_integer(count)? {
+            count if count <= 0 => bail!(RedisError::Err(
+                "count should be greater than 0".to_string()
+            )),
+            count => count as usize,
+        },
+        _ => bail!(RedisError::Syntax),
+    };
+
+    Ok((keys.to_vec(), end, count))
+}
+
+/// Parses a stream ID, `<ms>-<seq>` or `<ms>` alone with `default_seq` as the
+/// sequence number.
+fn parse_stream_id(raw: &[u8], default_seq: u64) -> Result<StreamId> {
+    let parse_u64 = |raw: &[u8]| {
+        if raw.is_empty() || !raw.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(raw).ok()?.parse().ok()
+    };
+    let id = match raw.iter().position(|&b| b == b'-') {
+        Some(dash) => parse_u64(&raw[..dash])
+            .zip(parse_u64(&raw[dash + 1..]))
+            .map(|(ms, seq)| StreamId::new(ms, seq)),
+        None => parse_u64(raw).map(|ms| StreamId::new(ms, default_seq)),
+    };
+    Ok(id.ok_or(RedisError::Err(
+        "Invalid stream ID specified as stream command argument".to_string(),
+    ))?)
+}
+
+/// Parses a bound of the interval of `XRANGE` and `XREVRANGE`: `-` or `+` for
+/// the smallest or greatest ID, or an ID, excluded if prefixed with `(`.
+fn parse_stream_bound(raw: &[u8], start: bool) -> Result<StreamId> {
+    let default_seq = if start { 0 } else { u64::MAX };
+    match raw {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        [b'(', raw @ ..] if !raw.is_empty() => {
+            let id = parse_stream_id(raw, default_seq)?;
+            if start {
+                Ok(id.next().ok_or(RedisError::Err(
+                    "invalid start ID for the interval".to_string(),
+                ))?)
+            } else {
+                Ok(id.previous().ok_or(RedisError::Err(
+                    "invalid end ID for the interval".to_string(),
+                ))?)
+            }
+        }
+        raw => parse_stream_id(raw, default_seq),
+    }
+}
+
+fn parse_xadd(args: &[Bytes]) -> Result<Command> {
+    let (key, mut args) = match args {
+        [key, args @ ..] if args.len() >= 3 => (key.clone(), args),
+        _ => bail!(RedisError::WrongArity("xadd")),
+    };
+
+    let mut no_mkstream = false;
+    let mut trim = None;
+    while let [option, rest @ ..] = args {
+        match option.to_ascii_lowercase().deref() {
+            b"nomkstream" => {
+                no_mkstream = true;
+                args = rest;
+            }
+            b"maxlen" | b"minid" => {
+                let (parsed, rest) = parse_stream_trim(args)?;
+                trim = Some(parsed);
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+
+    let (id, pairs) = match args {
+        [id, pairs @ ..] if !pairs.is_empty() && pairs.len() % 2 == 0 => (id, pairs),
+        _ => bail!(RedisError::WrongArity("xadd")),
+    };
+    let id = match id.deref() {
+        b"*" => XAddId::Auto,
+        [ms @ .., b'-', b'*'] => XAddId::AutoSeq(parse_stream_id(ms, 0)?.ms),
+        id => match parse_stream_id(id, 0)? {
+            StreamId::MIN => bail!(RedisError::Err(
+                "The ID specified in XADD must be greater than 0-0".to_string()
+            )),
+            id => XAddId::Explicit(id),
+        },
+    };
+
+    Ok(Command::XAdd(XAdd {
+        key,
+        no_mkstream,
+        trim,
+        id,
+        fields: pairs
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect(),
+    }))
+}
+
+/// Parses `MAXLEN | MINID [= | ~] threshold [LIMIT count]`, returning the
+/// arguments that follow.
+fn parse_stream_trim(args: &[Bytes]) -> Result<(StreamTrim, &[Bytes])> {
+    let (strategy, args) = args.split_first().ok_or(RedisError::Syntax)?;
+    let (approximate, args) = match args {
+        [mode, args @ ..] if mode.deref() == b"~" => (true, args),
+        [mode, args @ ..] if mode.deref() == b"=" => (false, args),
+        args => (false, args),
+    };
+    let (threshold, mut args) = args.split_first().ok_or(RedisError::Syntax)?;
+    let strategy = match strategy.to_ascii_lowercase().deref() {
+        b"maxlen" => match parse_integer(threshold)? {
+            max_len if max_len < 0 => bail!(RedisError::Err(
+                "The MAXLEN argument must be >= 0.".to_string()
+            )),
+            max_len => TrimStrategy::MaxLen(max_len as usize),
+        },
+        b"minid" => TrimStrategy::MinId(parse_stream_id(threshold, 0)?),
+        _ => bail!(RedisError::Syntax),
+    };
+
+    let mut limit = None;
+    if let [option, count, rest @ ..] = args {
+        if option.eq_ignore_ascii_case(b"limit") {
+            if !approximate {
+                bail!(RedisError::Err(
+                    "syntax error, LIMIT cannot be used without the special ~ option".to_string()
+                ));
+            }
+            limit = match parse_integer(count)? {
+                count if count < 0 => bail!(RedisError::Err(
+                    "The LIMIT argument must be >= 0.".to_string()
+                )),
+                count => Some(count as usize),
+            };
+            args = rest;
+        }
+    }
+    if let [option, ..] = args {
+        if option.eq_ignore_ascii_case(b"maxlen") || option.eq_ignore_ascii_case(b"minid") {
+            bail!(RedisError::Err(
+                "syntax error, MAXLEN and MINID options at the same time are not compatible"
+                    .to_string()
+            ));
+        }
+    }
+
+    Ok((
+        StreamTrim {
+            strategy,
+            approximate,
+            limit,
+        },
+        args,
+    ))
+}
+
+fn parse_xrange(args: &[Bytes], rev: bool, command: &'static str) -> Result<Command> {
+    let (key, first, second, options) = match args {
+        [key, first, second, options @ ..] => (key.clone(), first, second, options),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+    // `XREVRANGE` takes the end of the interval first
+    let (start, end) = if rev {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    let start = parse_stream_bound(start, true)?;
+    let end = parse_stream_bound(end, false)?;
+    let count = match options {
+        [] => None,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => {
+            Some(parse_integer(count)?.max(0) as usize)
+        }
+        _ => bail!(RedisError::Syntax),
+    };
+
+    Ok(Command::XRange(XRange {
+        key,
+        start,
+        end,
+        rev,
+        count,
+    }))
+}
+
+fn parse_xsetid(args: &[Bytes]) -> Result<Command> {
+    let (key, last_id, options) = match args {
+        [key, last_id, options @ ..] => (key.clone(), parse_stream_id(last_id, 0)?, options),
+        _ => bail!(RedisError::WrongArity("xsetid")),
+    };
+
+    let mut entries_added = None;
+    let mut max_deleted_id = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(RedisError::Syntax)?;
+        match option.to_ascii_lowercase().deref() {
+            b"entriesadded" => match parse_integer(value)? {
+                entries_added if entries_added < 0 => bail!(RedisError::Err(
+                    "entries_added must be positive".to_string()
+                )),
+                value => entries_added = Some(value as u64),
+            },
+            b"maxdeletedid" => max_deleted_id = Some(parse_stream_id(value, 0)?),
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::XSetId(XSetId {
+        key,
+        last_id,
+        entries_added,
+        max_deleted_id,
+    }))
+}
+
+/// Parses `XREAD`, which only blocks with the `BLOCK` option.
+fn parse_xread(args: &[Bytes]) -> Result<Command> {
+    if args.len() < 3 {
+        bail!(RedisError::WrongArity("xread"));
+    }
+
+    let mut count = None;
+    let mut timeout = None;
+    let mut args = args;
+    let streams = loop {
+        match args {
+            [option, rest @ ..] if option.eq_ignore_ascii_case(b"streams") => break rest,
+            [option, value, rest @ ..] if option.eq_ignore_ascii_case(b"count") => {
+                // Like Redis, a count of 0 or less reads every entry
+                count = Some(parse_integer(value)?).filter(|&count| count > 0);
+                args = rest;
+            }
+            [option, value, rest @ ..] if option.eq_ignore_ascii_case(b"block") => {
+                let millis = parse_i64(value).ok_or(RedisError::Err(
+                    "timeout is not an integer or out of range".to_string(),
+                ))?;
+                timeout = Some(match millis {
+                    millis if millis < 0 => {
+                        bail!(RedisError::Err("timeout is negative".to_string()))
+                    }
+                    0 => None,
+                    millis => Some(Duration::from_millis(millis as u64)),
+                });
+                args = rest;
+            }
+            _ => bail!(RedisError::Syntax),
+        }
+    };
+    if streams.is_empty() || streams.len() % 2 != 0 {
+        bail!(RedisError::Err(
+            "Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be \
+             specified."
+                .to_string()
+        ));
+    }
+
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+    let xread = XRead {
+        streams: keys
+            .iter()
+            .zip(ids)
+            .map(|(key, id)| {
+                let id = match id.deref() {
+                    b"$" => None,
+                    id => Some(parse_stream_id(id, 0)?),
+                };
+                Ok((key.clone(), id))
+            })
+            .collect::<Result<_>>()?,
+        count: count.map(|count| count as usize),
+    };
+    Ok(match timeout {
+        Some(timeout) => Command::Blocking(Blocking {
+            keys: keys.to_vec(),
+            operation: BlockingOp::XRead(xread),
+            timeout,
+        }),
+        None => Command::XRead(xread),
+    })
+}
+
+/// Parses the argument of one of the `EX`, `PX`, `EXAT` or `PXAT` options.
+fn parse_expiration(option: &[u8], raw: &[u8], command: &str) -> Result<Expiration> {
+    let invalid_time = || RedisError::Err(format!("invalid expire time in '{command}' command"));
+    let value = parse_integer(raw)?;
+    let millis = match option {
+        b"ex" | b"exat" => value.checked_mul(1000),
+        _ => Some(value),
+    }
+    .filter(|millis| *millis > 0)
+    .ok_or_else(invalid_time)?;
+
+    let duration = Duration::from_millis(millis as u64);
+    match option {
+        b"ex" | b"px" => {
+            // Like Redis, the expire time counted from now must fit as well
+            millis
+                .checked_add(unix_millis(SystemTime::now()))
+                .ok_or_else(invalid_time)?;
+            Ok(Expiration::After(duration))
+        }
+        _ => Ok(Expiration::At(UNIX_EPOCH + duration)),
+    }
+}
+
+fn parse_integer(raw: &[u8]) -> Result<i64> {
+    Ok(parse_i64(raw).ok_or(RedisError::NotInteger)?)
+}
+
+fn parse_float(raw: &[u8]) -> Result<f64> {
+    Ok(parse_f64(raw).ok_or(RedisError::Err("value is not a valid float".to_string()))?)
+}
+
+fn parse_long_double(raw: &[u8]) -> Result<LongDouble> {
+    Ok(LongDouble::parse(raw).ok_or(RedisError::Err("value is not a valid float".to_string()))?)
+}
+
+/// Parses the arguments of commands that take a single key.
+fn parse_key(args: &[Bytes], command: &'static str) -> Result<Bytes> {
+    match args {
+        [key] => Ok(key.clone()),
+        _ => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+/// Parses the arguments of commands that take one or more keys.
+fn parse_keys(args: &[Bytes], command: &'static str) -> Result<Vec<Bytes>> {
+    if args.is_empty() {
+        bail!(RedisError::WrongArity(command));
+    }
+    Ok(args.to_vec())
+}
+
+/// Parses the arguments of commands that take one or more key value pairs.
+fn parse_key_values(args: &[Bytes], command: &'static str) -> Result<Vec<(Bytes, Bytes)>> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        bail!(RedisError::WrongArity(command));
+    }
+    Ok(args
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+fn parse_copy(args: &[Bytes]) -> Result<Command> {
+    let (source, destination, options) = match args {
+        [source, destination, options @ ..] => (source.clone(), destination.clone(), options),
+        _ => bail!(RedisError::WrongArity("copy")),
+    };
+
+    let mut replace = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().deref() {
+            b"replace" => replace = true,
+            // There's a single database
+            b"db" => match options.next().map(|db| parse_integer(db)).transpose()? {
+                Some(0) => {}
+                Some(_) => bail!(RedisError::Err("DB index is out of range".to_string())),
+                None => bail!(RedisError::Syntax),
+            },
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::Copy(Copy {
+        source,
+        destination,
+        replace,
+    }))
+}
+
+fn parse_scan(args: &[Bytes]) -> Result<Command> {
+    let (cursor, options) = match args {
+        [cursor, options @ ..] => (parse_cursor(cursor)?, options),
+        _ => bail!(RedisError::WrongArity("scan")),
+    };
+
+    let mut pattern = None;
+    let mut count = 10;
+    let mut value_type = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(RedisError::Syntax)?;
+        match option.to_ascii_lowercase().deref() {
+            b"match" => pattern = Some(value.clone()),
+            b"count" => count = parse_count(value)?,
+            b"type" => value_type = Some(parse_value_type(value)?),
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::Scan(Scan {
+        cursor,
+        pattern,
+        count,
+        value_type,
+    }))
+}
+
+fn parse_hrandfield(args: &[Bytes]) -> Result<Command> {
+    let (key, args) = match args {
+        [key, args @ ..] => (key.clone(), args),
+        [] => bail!(RedisError::WrongArity("hrandfield")),
+    };
+    let (count, with_values) = match args {
+        [] => (None, false),
+        [count, options @ ..] => {
+            let count = parse_integer(count)?;
+            let with_values = match options {
+                [] => false,
+                [option] if option.eq_ignore_ascii_case(b"withvalues") => true,
+                _ => bail!(RedisError::Syntax),
+            };
+            // Twice as many elements are replied with the values
+            if with_values && count.checked_mul(2).is_none() {
+                bail!(RedisError::Err("value is out of range".to_string()));
+            }
+            (Some(count), with_values)
+        }
+    };
+    Ok(Command::HRandField(HRandField {
+        key,
+        count,
+        with_values,
+    }))
+}
+
+/// Parses the arguments of `HSCAN`, `SSCAN` and `ZSCAN`, only the first one
+/// accepting `NOVALUES`.
+fn parse_element_scan(args: &[Bytes], command: &'static str) -> Result<ElementScan> {
+    let (key, cursor, options) = match args {
+        [key, cursor, options @ ..] => (key.clone(), parse_cursor(cursor)?, options),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+
+    let mut pattern = None;
+    let mut count = 10;
+    let mut no_values = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().deref() {
+            b"match" => pattern = Some(options.next().ok_or(RedisError::Syntax)?.clone()),
+            b"count" => count = parse_count(options.next().ok_or(RedisError::Syntax)?)?,
+            b"novalues" if command == "hscan" => no_values = true,
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(ElementScan {
+        key,
+        cursor,
+        pattern,
+        count,
+        no_values,
+    })
+}
+
+fn parse_set_op_store(
+    args: &[Bytes],
+    operation: SetOperation,
+    command: &'static str,
+) -> Result<Command> {
+    match args {
+        [destination, keys @ ..] if !keys.is_empty() => Ok(Command::SetOpStore(
+            operation,
+            destination.clone(),
+            keys.to_vec(),
+        )),
+        _ => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+/// Parses the `numkeys key [key ...] [LIMIT limit]` arguments of `SINTERCARD`
+/// and `ZINTERCARD`.
+fn parse_intercard(args: &[Bytes], command: &'static str) -> Result<(Vec<Bytes>, usize)> {
+    let (num_keys, args) = match args {
+        [num_keys, args @ ..] if !args.is_empty() => (parse_integer(num_keys)?, args),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+    if num_keys <= 0 {
+        bail!(RedisError::Err(
+            "numkeys should be greater than 0".to_string()
+        ));
+    }
+    let num_keys = num_keys as usize;
+    if num_keys > args.len() {
+        bail!(RedisError::Err(
+            "Number of keys can't be greater than number of args".to_string()
+        ));
+    }
+
+    let (keys, options) = args.split_at(num_keys);
+    let limit = match options {
+        [] => 0,
+        [option, limit] if option.eq_ignore_ascii_case(b"limit") => match parse_integer(limit)? {
+            limit if limit < 0 => bail!(RedisError::Err("LIMIT can't be negative".to_string())),
+            limit => limit as usize,
+        },
+        _ => bail!(RedisError::Syntax),
+    };
+    Ok((keys.to_vec(), limit))
+}
+
+fn parse_zpop(args: &[Bytes], end: SortedSetEnd, command: &'static str) -> Result<Command> {
+    match args {
+        [key] => Ok(Command::ZPop(end, key.clone(), None)),
+        [key, count] => Ok(Command::ZPop(
+            end,
+            key.clone(),
+            Some(parse_positive(count)?),
+        )),
+        [_, _, _, ..] => bail!(RedisError::Syntax),
+        [] => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+fn parse_bzpop(args: &[Bytes], end: SortedSetEnd, command: &'static str) -> Result<Command> {
+    match args {
+        [keys @ .., timeout] if !keys.is_empty() => Ok(Command::Blocking(Blocking {
+            keys: keys.to_vec(),
+            operation: BlockingOp::ZPop(end),
+            timeout: parse_timeout(timeout)?,
+        })),
+        _ => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+fn parse_zset_op_store(
+    args: &[Bytes],
+    operation: SetOperation,
+    command: &'static str,
+) -> Result<Command> {
+    match args {
+        [destination, args @ ..] if args.len() >= 2 => Ok(Command::ZSetOpStore(
+            destination.clone(),
+            parse_zset_op(args, operation, command, true)?,
+        )),
+        _ => bail!(RedisError::WrongArity(command)),
+    }
+}
+
+/// Parses the `numkeys key [key ...]` arguments of `ZUNION`, `ZINTER`, `ZDIFF`
+/// and their `STORE` variants, followed by their options. Only `ZDIFF` has no
+/// `WEIGHTS` and `AGGREGATE`, and only the variants that don't store have
+/// `WITHSCORES`.
+fn parse_zset_op(
+    args: &[Bytes],
+    operation: SetOperation,
+    command: &'static str,
+    store: bool,
+) -> Result<ZSetOp> {
+    let (num_keys, args) = match args {
+        [num_keys, args @ ..] if !args.is_empty() => (parse_integer(num_keys)?, args),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+    if num_keys <= 0 {
+        bail!(RedisError::Err(format!(
+            "at least 1 input key is needed for '{command}' command"
+        )));
+    }
+    let num_keys = num_keys as usize;
+    if num_keys > args.len() {
+        bail!(RedisError::Syntax);
+    }
+
+    let (keys, options) = args.split_at(num_keys);
+    let mut weights = vec![1.0; num_keys];
+    let mut aggregate = Aggregate::Sum;
+    let mut with_scores = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().deref() {
+            b"weights" if operation != SetOperation::Diff => {
+                for weight in &mut weights {
+                    let raw = options.next().ok_or(RedisError::Syntax)?;
+                    *weight = parse_f64(raw)
+                        .ok_or(RedisError::Err("weight value is not a float".to_string()))?;
+                }
+            }
+            b"aggregate" if operation != SetOperation::Diff => {
+                let raw = options.next().ok_or(RedisError::Syntax)?;
+                aggregate = match raw.to_ascii_lowercase().deref() {
+                    b"sum" => Aggregate::Sum,
+                    b"min" => Aggregate::Min,
+                    b"max" => Aggregate::Max,
+                    _ => bail!(RedisError::Syntax),
+                };
+            }
+            b"withscores" if !store => with_scores = true,
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(ZSetOp {
+        operation,
+        keys: keys.to_vec(),
+        weights,
+        aggregate,
+        with_scores,
+    })
+}
+
+fn parse_zadd(args: &[Bytes]) -> Result<Command> {
+    let (key, args) = match args {
+        [key, args @ ..] if args.len() >= 2 => (key.clone(), args),
+        _ => bail!(RedisError::WrongArity("zadd")),
+    };
+
+    let mut condition = None;
+    let mut comparison = None;
+    let mut changed = false;
+    let mut incr = false;
+    let mut nx_and_xx = false;
+    let mut gt_and_lt = false;
+    let mut options = 0;
+    for option in args {
+        match option.to_ascii_lowercase().deref() {
+            b"nx" => nx_and_xx |= condition.replace(SetCondition::Nx) == Some(SetCondition::Xx),
+            b"xx" => nx_and_xx |= condition.replace(SetCondition::Xx) == Some(SetCondition::Nx),
+            b"gt" => {
+                gt_and_lt |= comparison.replace(ScoreComparison::Gt) == Some(ScoreComparison::Lt)
+            }
+            b"lt" => {
+                gt_and_lt |= comparison.replace(ScoreComparison::Lt) == Some(ScoreComparison::Gt)
+            }
+            b"ch" => changed = true,
+            b"incr" => incr = true,
+            _ => break,
+        }
+        options += 1;
+    }
+
+    let elements = &args[options..];
+    if elements.is_empty() || elements.len() % 2 != 0 {
+        bail!(RedisError::Syntax);
+    }
+    if nx_and_xx {
+        bail!(RedisError::Err(
+            "XX and NX options at the same time are not compatible".to_string()
+        ));
+    }
+    if gt_and_lt || (comparison.is_some() && condition == Some(SetCondition::Nx)) {
+        bail!(RedisError::Err(
+            "GT, LT, and/or NX options at the same time are not compatible".to_string()
+        ));
+    }
+    if incr && elements.len() > 2 {
+        bail!(RedisError::Err(
+            "INCR option supports a single increment-element pair".to_string()
+        ));
+    }
+
+    Ok(Command::ZAdd(ZAdd {
+        key,
+        condition,
+        comparison,
+        changed,
+        incr,
+        elements: elements
+            .chunks_exact(2)
+            .map(|pair| Ok((parse_float(&pair[0])?, pair[1].clone())))
+            .collect::<Result<_>>()?,
+    }))
+}
+
+/// Parses the ends of a range of scores, `(` making them exclusive.
+fn parse_score_range(min: &[u8], max: &[u8]) -> Result<ScoreRange> {
+    let parse = |raw: &[u8]| match raw.strip_prefix(b"(") {
+        Some(raw) => parse_f64(raw).map(|score| (score, true)),
+        None => parse_f64(raw).map(|score| (score, false)),
+    };
+    let ((min, min_exclusive), (max, max_exclusive)) = parse(min)
+        .zip(parse(max))
+        .ok_or(RedisError::Err("min or max is not a float".to_string()))?;
+    Ok(ScoreRange {
+        min,
+        min_exclusive,
+        max,
+        max_exclusive,
+    })
+}
+
+fn parse_lex_range(min: &[u8], max: &[u8]) -> Result<LexRange> {
+    let parse = |raw: &[u8]| match raw {
+        b"-" => Some(LexBound::Min),
+        b"+" => Some(LexBound::Max),
+        [b'[', member @ ..] => Some(LexBound::Inclusive(Bytes::copy_from_slice(member))),
+        [b'(', member @ ..] => Some(LexBound::Exclusive(Bytes::copy_from_slice(member))),
+        _ => None,
+    };
+    let (min, max) = parse(min).zip(parse(max)).ok_or(RedisError::Err(
+        "min or max not valid string range item".to_string(),
+    ))?;
+    Ok(LexRange { min, max })
+}
+
+fn parse_zrank(args: &[Bytes], rev: bool, command: &'static str) -> Result<Command> {
+    let (key, member, with_score) = match args {
+        [key, member] => (key, member, false),
+        [key, member, option] if option.eq_ignore_ascii_case(b"withscore") => (key, member, true),
+        [_, _, _] => bail!(RedisError::Syntax),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+    Ok(Command::ZRank(ZRank {
+        key: key.clone(),
+        member: member.clone(),
+        rev,
+        with_score,
+    }))
+}
+
+/// Parses the arguments of `ZRANGE`, or those of `ZRANGESTORE` after the
+/// destination, which doesn't accept `WITHSCORES`.
+fn parse_zrange(args: &[Bytes], command: &'static str) -> Result<ZRange> {
+    let (key, start, stop, options) = match args {
+        [key, start, stop, options @ ..] => (key.clone(), start, stop, options),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+
+    let mut by_score = false;
+    let mut by_lex = false;
+    let mut rev = false;
+    let mut limit = None;
+    let mut with_scores = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_lowercase().deref() {
+            b"byscore" => (by_score, by_lex) = (true, false),
+            b"bylex" => (by_score, by_lex) = (false, true),
+            b"rev" => rev = true,
+            b"limit" => {
+                let offset = parse_integer(options.next().ok_or(RedisError::Syntax)?)?;
+                let count = parse_integer(options.next().ok_or(RedisError::Syntax)?)?;
+                limit = Some((offset, count));
+            }
+            b"withscores" if command == "zrange" => with_scores = true,
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    if limit.is_some() && !by_score && !by_lex {
+        bail!(RedisError::Err(
+            "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .to_string()
+        ));
+    }
+    if with_scores && by_lex {
+        bail!(RedisError::Err(
+            "syntax error, WITHSCORES not supported in combination with BYLEX".to_string()
+        ));
+    }
+
+    // Reversed ranges of scores and members start from the maximum
+    let (min, max) = if rev { (stop, start) } else { (start, stop) };
+    let by = if by_score {
+        ZRangeBy::Score(parse_score_range(min, max)?)
+    } else if by_lex {
+        ZRangeBy::Lex(parse_lex_range(min, max)?)
+    } else {
+        ZRangeBy::Rank(parse_integer(start)?, parse_integer(stop)?)
+    };
+    let (offset, count) = limit.unwrap_or((0, -1));
+    Ok(ZRange {
+        key,
+        by,
+        rev,
+        offset,
+        count,
+        with_scores,
+    })
+}
+
+fn parse_value_type(raw: &[u8]) -> Result<ValueType> {
+    match raw.to_ascii_lowercase().deref() {
+        b"string" => Ok(ValueType::String),
+        b"list" => Ok(ValueType::List),
+        b"hash" => Ok(ValueType::Hash),
+        b"set" => Ok(ValueType::Set),
+        b"zset" => Ok(ValueType::SortedSet),
+        b"stream" => Ok(ValueType::Stream),
+        _ => bail!(RedisError::Err(format!(
+            "unknown type name '{}'",
+            String::from_utf8_lossy(raw)
+        ))),
+    }
+}
+
+fn parse_cursor(raw: &[u8]) -> Result<u64> {
+    std::str::from_utf8(raw)
+        .ok()
+        .and_then(|cursor| cursor.parse().ok())
+        .ok_or_else(|| RedisError::Err("invalid cursor".to_string()).into())
+}
+
+/// Parses the argument of a `COUNT` option, which must be strictly positive.
+fn parse_count(raw: &[u8]) -> Result<usize> {
+    match parse_integer(raw)? {
+        count if count < 1 => bail!(RedisError::Syntax),
+        count => Ok(count as usize),
+    }
+}
+
+fn parse_expire(
+    args: &[Bytes],
+    command: &'static str,
+    unit: TimeUnit,
+    absolute: bool,
+) -> Result<Command> {
+    let (key, time, options) = match args {
+        [key, time, options @ ..] => (key.clone(), time, options),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+
+    let invalid_time = || RedisError::Err(format!("invalid expire time in '{command}' command"));
+    let time = parse_integer(time)?;
+    let millis = match unit {
+        TimeUnit::Seconds => time.checked_mul(1000).ok_or_else(invalid_time)?,
+        TimeUnit::Milliseconds => time,
+    };
+    let at = if absolute {
+        millis
+    } else {
+        millis
+            .checked_add(unix_millis(SystemTime::now()))
+            .ok_or_else(invalid_time)?
+    };
+
+    let mut conditions = Vec::new();
+    for option in options {
+        conditions.push(match option.to_ascii_lowercase().deref() {
+            b"nx" => ExpireCondition::Nx,
+            b"xx" => ExpireCondition::Xx,
+            b"gt" => ExpireCondition::Gt,
+            b"lt" => ExpireCondition::Lt,
+            other => bail!(RedisError::Err(format!(
+                "Unsupported option {}",
+                String::from_utf8_lossy(other)
+            ))),
+        });
+    }
+    let has = |condition| conditions.contains(&condition);
+    if has(ExpireCondition::Nx)
+        && (has(ExpireCondition::Xx) || has(ExpireCondition::Gt) || has(ExpireCondition::Lt))
+    {
+        bail!(RedisError::Err(
+            "NX and XX, GT or LT options at the same time are not compatible".to_string()
+        ));
+    }
+    if has(ExpireCondition::Gt) && has(ExpireCondition::Lt) {
+        bail!(RedisError::Err(
+            "GT and LT options at the same time are not compatible".to_string()
+        ));
+    }
+
+    Ok(Command::Expire(Expire {
+        key,
+        at,
+        conditions,
     }))
 }
 
-fn parse_get(args: &[Vec<u8>]) -> Result<Command> {
-    match args.first() {
-        None => bail!("GET command requires an argument"),
-        Some(bytes) => Ok(Command::Get(String::from_utf8(bytes.clone())?)),
+fn parse_hexpire(
+    args: &[Bytes],
+    command: &'static str,
+    unit: TimeUnit,
+    absolute: bool,
+) -> Result<Command> {
+    let (key, time, args) = match args {
+        // At least `FIELDS numfields field` follow the time
+        [key, time, args @ ..] if args.len() >= 3 => (key.clone(), time, args),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+
+    // Like Redis, field expirations are limited to 48 bits of milliseconds
+    const MAX_MILLIS: i64 = (1 << 48) - 1;
+    let invalid_time = || RedisError::Err(format!("invalid expire time in '{command}' command"));
+    let time = parse_integer(time)?;
+    // Unlike keys, fields can't be given a negative time to delete them
+    if time < 0 {
+        bail!(RedisError::Err(
+            "invalid expire time, must be >= 0".to_string()
+        ));
+    }
+    let millis = match unit {
+        TimeUnit::Seconds => time.checked_mul(1000).ok_or_else(invalid_time)?,
+        TimeUnit::Milliseconds => time,
+    };
+    let at = if absolute {
+        millis
+    } else {
+        millis
+            .checked_add(unix_millis(SystemTime::now()))
+            .ok_or_else(invalid_time)?
+    };
+    if at > MAX_MILLIS {
+        bail!(invalid_time());
     }
+
+    let (condition, fields) = match args {
+        [option, fields @ ..] if !option.eq_ignore_ascii_case(b"fields") => {
+            let condition = match option.to_ascii_lowercase().deref() {
+                b"nx" => ExpireCondition::Nx,
+                b"xx" => ExpireCondition::Xx,
+                b"gt" => ExpireCondition::Gt,
+                b"lt" => ExpireCondition::Lt,
+                _ => bail!(RedisError::Err(
+                    "Mandatory argument FIELDS is missing or not at the right position".to_string()
+                )),
+            };
+            (Some(condition), fields)
+        }
+        fields => (None, fields),
+    };
+
+    Ok(Command::HExpire(HExpire {
+        key,
+        at,
+        condition,
+        fields: parse_fields(fields, command)?,
+    }))
 }
 
-fn parse_info(args: &[Vec<u8>]) -> Result<Command> {
+/// Parses the `FIELDS numfields field [field ...]` arguments of the commands
+/// operating on the expiration of hash fields.
+fn parse_fields(args: &[Bytes], command: &'static str) -> Result<Vec<Bytes>> {
+    let (num_fields, fields) = match args {
+        [option, num_fields, fields @ ..]
+            if option.eq_ignore_ascii_case(b"fields") && !fields.is_empty() =>
+        {
+            (parse_integer(num_fields)?, fields)
+        }
+        [_, _, _, ..] => bail!(RedisError::Err(
+            "Mandatory argument FIELDS is missing or not at the right position".to_string()
+        )),
+        _ => bail!(RedisError::WrongArity(command)),
+    };
+    if num_fields <= 0 {
+        bail!(RedisError::Err(
+            "Parameter `numFields` should be greater than 0".to_string()
+        ));
+    }
+    if num_fields as usize != fields.len() {
+        bail!(RedisError::Err(
+            "The `numfields` parameter must match the number of arguments".to_string()
+        ));
+    }
+    Ok(fields.to_vec())
+}
+
+fn parse_info(args: &[Bytes]) -> Result<Command> {
     let mut sections = Vec::new();
     for arg in args.iter().map(|arg| arg.to_ascii_lowercase()) {
         match arg.deref() {
             b"replication" => sections.push(InfoSection::Replication),
+            b"stats" => sections.push(InfoSection::Stats),
+            b"keyspace" => sections.push(InfoSection::Keyspace),
             other => {
                 bail!(
                     "Unsupported info section {}",
@@ -227,7 +2516,7 @@ fn parse_info(args: &[Vec<u8>]) -> Result<Command> {
     Ok(Command::Info(sections))
 }
 
-fn parse_replconf(args: &[Vec<u8>]) -> Result<Command> {
+fn parse_replconf(args: &[Bytes]) -> Result<Command> {
     let mut args = args.iter();
     let repl_opt = match args.next().map(|arg| arg.to_ascii_lowercase()).as_deref() {
         Some(b"listening-port") => {
@@ -261,12 +2550,12 @@ fn parse_replconf(args: &[Vec<u8>]) -> Result<Command> {
     Ok(Command::ReplConf(repl_opt))
 }
 
-fn parse_psync(args: &[Vec<u8>]) -> Result<Command> {
+fn parse_psync(args: &[Bytes]) -> Result<Command> {
     let mut args = args.iter();
     let mut replication_id = Some(String::from_utf8(
         args.next()
             .ok_or(anyhow!("Missing required argument replication_id"))?
-            .clone(),
+            .to_vec(),
     )?);
     if replication_id.as_deref() == Some("?") {
         replication_id = None;
@@ -275,7 +2564,7 @@ fn parse_psync(args: &[Vec<u8>]) -> Result<Command> {
     let replication_offset = Some(String::from_utf8(
         args.next()
             .ok_or(anyhow!("Missing required argument replication_id"))?
-            .clone(),
+            .to_vec(),
     )?)
     .filter(|offset| offset != "-1");
     let replication_offset = match replication_offset {
@@ -288,3 +2577,256 @@ fn parse_psync(args: &[Vec<u8>]) -> Result<Command> {
         replication_offset,
     }))
 }
+
+fn parse_hello(args: &[Bytes]) -> Result<Command> {
+    let mut args = args.iter();
+    let protocol = match args
+        .next()
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+    {
+        None => None,
+        Some(version) => match version.parse::<i64>() {
+            Ok(2) => Some(Protocol::Resp2),
+            Ok(3) => Some(Protocol::Resp3),
+            Ok(_) => bail!(RedisError::NoProto),
+            Err(_) => bail!("Protocol version is not an integer or out of range"),
+        },
+    };
+
+    let mut auth = None;
+    let mut client_name = None;
+    while let Some(arg) = args.next() {
+        match arg.to_ascii_lowercase().deref() {
+            b"auth" => {
+                let username = args.next().ok_or(RedisError::Syntax)?;
+                let password = args.next().ok_or(RedisError::Syntax)?;
+                auth = Some((
+                    String::from_utf8(username.to_vec())?,
+                    String::from_utf8(password.to_vec())?,
+                ));
+            }
+            b"setname" => {
+                let name = args.next().ok_or(RedisError::Syntax)?;
+                client_name = Some(String::from_utf8(name.to_vec())?);
+            }
+            _ => bail!(RedisError::Syntax),
+        }
+    }
+
+    Ok(Command::Hello(Hello {
+        protocol,
+        auth,
+        client_name,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(bytes: &[u8]) -> Result<Vec<Element>> {
+        let mut parser = ElementParser::new();
+        let mut buffer = BytesMut::from(bytes);
+        let mut elements = Vec::new();
+        while let Some(element) = parser.parse(&mut buffer)? {
+            elements.push(element);
+        }
+        assert!(buffer.is_empty() && !parser.in_progress());
+        Ok(elements)
+    }
+
+    fn command(args: &[&str]) -> Result<Command> {
+        let args = args
+            .iter()
+            .map(|arg| Element::BulkString(arg.to_string().into()));
+        Element::Array(args.collect()).try_into()
+    }
+
+    fn error(result: Result<Command>) -> String {
+        result.expect_err("the command is invalid").to_string()
+    }
+
+    #[test]
+    fn parses_elements_received_one_byte_at_a_time() {
+        let bytes = b"*2\r\n$3\r\nget\r\n$4\r\nk\r\ny\r\n%1\r\n+a\r\n*1\r\n:1\r\nping\r\n";
+        let expected = format!("{:?}", parse_all(bytes).unwrap());
+
+        let mut parser = ElementParser::new();
+        let mut buffer = BytesMut::new();
+        let mut elements = Vec::new();
+        for &b in bytes {
+            buffer.extend_from_slice(&[b]);
+            while let Some(element) = parser.parse(&mut buffer).unwrap() {
+                elements.push(element);
+            }
+        }
+        assert_eq!(format!("{elements:?}"), expected);
+        assert_eq!(
+            expected,
+            r#"[Array([BulkString(b"get"), BulkString(b"k\r\ny")]), Map([(SimpleString("a"), Array([Integer(1)]))]), Array([BulkString(b"ping")])]"#
+        );
+    }
+
+    #[test]
+    fn keeps_aggregates_waiting_for_their_elements() {
+        let mut parser = ElementParser::new();
+        let mut buffer = BytesMut::from(&b"*3\r\n$1\r\na\r\n$1\r\nb"[..]);
+        assert!(parser.parse(&mut buffer).unwrap().is_none());
+        // Complete elements are consumed, the rest is kept for the next call
+        assert_eq!(&buffer[..], b"$1\r\nb");
+        assert!(parser.in_progress());
+
+        buffer.extend_from_slice(b"\r\n*0\r\n");
+        let element = parser.parse(&mut buffer).unwrap().unwrap();
+        assert_eq!(
+            format!("{element:?}"),
+            r#"Array([BulkString(b"a"), BulkString(b"b"), Array([])])"#
+        );
+        assert!(buffer.is_empty() && !parser.in_progress());
+    }
+
+    #[test]
+    fn rejects_deeply_nested_aggregates() {
+        let nested = b"*1\r\n".repeat(MAX_NESTING);
+        assert!(parse_all(&[&nested[..], b":1\r\n"].concat()).is_ok());
+        assert!(parse_all(&[&nested[..], b"*1\r\n:1\r\n"].concat()).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_bulk_strings() {
+        assert!(parse_all(b"$3\r\nabcd\r\n").is_err());
+        assert!(parse_all(b"=3\r\nabc\r\n").is_err());
+        assert!(parse_all(b"$-2\r\n").is_err());
+    }
+
+    fn split(line: &str) -> Option<Vec<String>> {
+        split_args(line.as_bytes()).map(|args| {
+            args.iter()
+                .map(|arg| String::from_utf8(arg.to_vec()).unwrap())
+                .collect()
+        })
+    }
+
+    fn args(args: &[&str]) -> Option<Vec<String>> {
+        Some(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn rejects_lines_that_are_too_long() {
+        for prefix in [b'+', b'-', b':'] {
+            let mut parser = ElementParser::new();
+            let mut buffer = BytesMut::from(&[prefix][..]);
+            buffer.extend_from_slice(&[b'1'; MAX_INLINE_LEN]);
+            assert!(parser.parse(&mut buffer).unwrap().is_none());
+            buffer.extend_from_slice(b"1");
+            let error = parser.parse(&mut buffer).unwrap_err();
+            assert_eq!(error.to_string(), "too big line in request");
+        }
+
+        // Lengths overflow long before their line gets too big
+        let mut parser = ElementParser::new();
+        let mut buffer = BytesMut::from(&b"*"[..]);
+        buffer.extend_from_slice(&[b'1'; 32]);
+        assert!(parser.parse(&mut buffer).is_err());
+
+        let mut parser = ElementParser::new();
+        let mut buffer = BytesMut::from(&[b'a'; MAX_INLINE_LEN + 1][..]);
+        let error = parser.parse(&mut buffer).unwrap_err();
+        assert_eq!(error.to_string(), "too big inline request");
+    }
+
+    #[test]
+    fn splits_args_on_whitespace() {
+        assert_eq!(split(""), args(&[]));
+        assert_eq!(split(" \t "), args(&[]));
+        assert_eq!(split("set  key\tvalue "), args(&["set", "key", "value"]));
+    }
+
+    #[test]
+    fn splits_quoted_args() {
+        assert_eq!(
+            split(r#"set "a key" 'a value'"#),
+            args(&["set", "a key", "a value"])
+        );
+        assert_eq!(split(r#""" ''"#), args(&["", ""]));
+        // Quotes may start in the middle of an argument, but end it
+        assert_eq!(split(r#"a"b c" d'e'"#), args(&["ab c", "de"]));
+        assert_eq!(split(r#"a"b"c"#), None);
+        assert_eq!(split(r#""a"b"#), None);
+        assert_eq!(split("'a'b"), None);
+        assert_eq!(split("'a'\"b\""), None);
+    }
+
+    #[test]
+    fn splits_escaped_args() {
+        assert_eq!(
+            split(r#""\n\r\t\b\a\"\\\q""#),
+            args(&["\n\r\t\u{8}\u{7}\"\\q"])
+        );
+        // Only quotes are escaped in single quotes
+        assert_eq!(split(r"'\'\n\\x'"), args(&[r"'\n\\x"]));
+        assert_eq!(split(r#"'"' "'""#), args(&["\"", "'"]));
+        // Escapes aren't interpreted outside of quotes
+        assert_eq!(split(r"a\nb"), args(&[r"a\nb"]));
+    }
+
+    #[test]
+    fn splits_hex_escapes() {
+        assert_eq!(split(r#""\x41\x6a\x2A""#), args(&["Aj*"]));
+        assert_eq!(
+            split_args(br#""\x00\xff""#),
+            Some(vec![Bytes::from_static(b"\x00\xff")])
+        );
+        // Invalid escapes keep the `x`
+        assert_eq!(split(r#""\x4" "\xg1" "\x+f""#), args(&["x4", "xg1", "x+f"]));
+    }
+
+    #[test]
+    fn rejects_unbalanced_quotes() {
+        assert_eq!(split(r#"set "key"#), None);
+        assert_eq!(split("set 'key"), None);
+        assert_eq!(split(r#"set "key\""#), None);
+        assert_eq!(split(r"set 'key\'"), None);
+        assert_eq!(split(r#"set "key\"#), None);
+    }
+
+    #[test]
+    fn rejects_set_expire_times_that_overflow() {
+        let max = i64::MAX.to_string();
+        let too_big = (i64::MAX / 1000).to_string();
+        for (option, time) in [("px", &max), ("ex", &too_big), ("exat", &max)] {
+            assert_eq!(
+                error(command(&["set", "k", "v", option, time])),
+                "ERR invalid expire time in 'set' command"
+            );
+        }
+        // Absolute times only need to fit themselves
+        let set = command(&["set", "k", "v", "pxat", &max]).unwrap();
+        assert!(matches!(
+            set,
+            Command::Set(Set { expiration: Some(Expiration::At(at)), .. })
+                if unix_millis(at) == i64::MAX
+        ));
+        for option in ["px", "ex"] {
+            assert!(command(&["set", "k", "v", option, "0"]).is_err());
+            assert!(command(&["set", "k", "v", option, "-1"]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_getex_expire_times_that_overflow() {
+        let max = i64::MAX.to_string();
+        assert_eq!(
+            error(command(&["getex", "k", "px", &max])),
+            "ERR invalid expire time in 'getex' command"
+        );
+        assert!(command(&["getex", "k", "ex", &(i64::MAX / 1000).to_string()]).is_err());
+        assert!(command(&["getex", "k", "px", "-1"]).is_err());
+        let getex = command(&["getex", "k", "pxat", &max]).unwrap();
+        assert!(matches!(
+            getex,
+            Command::GetEx(GetEx { expiration: Some(Expiration::At(at)), .. })
+                if unix_millis(at) == i64::MAX
+        ));
+    }
+}
@@ -0,0 +1,138 @@
+use bytes::Bytes;
+
+use crate::{
+    dict::Dict,
+    utils::{parse_i64, Rng},
+};
+
+/// Largest number of members of a set stored as an intset, matching Redis'
+/// default `set-max-intset-entries`.
+const MAX_INTSET_ENTRIES: usize = 512;
+
+/// An unordered set of strings. Like Redis, sets that only hold integers are
+/// stored as a sorted array of them, an intset, which takes a lot less memory
+/// than a hash table. Sets are converted to a hash table for good once they get
+/// a member that isn't an integer, or grow too large.
+#[derive(Debug, Clone)]
+pub enum Set {
+    IntSet(Vec<i64>),
+    Dict(Dict<Bytes, ()>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::IntSet(Vec::new())
+    }
+}
+
+impl Set {
+    pub fn new() -> Self {
+        Set::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Set::IntSet(integers) => integers.len(),
+            Set::Dict(dict) => dict.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(integers) => {
+                parse_i64(member).is_some_and(|n| integers.binary_search(&n).is_ok())
+            }
+            Set::Dict(dict) => dict.get(member).is_some(),
+        }
+    }
+
+    /// Adds a member, returning whether it wasn't already there.
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        if let Set::IntSet(integers) = self {
+            if let Some(n) = parse_i64(&member) {
+                let Err(index) = integers.binary_search(&n) else {
+                    return false;
+                };
+                if integers.len() < MAX_INTSET_ENTRIES {
+                    integers.insert(index, n);
+                    return true;
+                }
+            }
+            self.convert_to_dict();
+        }
+        match self {
+            Set::IntSet(_) => unreachable!("converted above"),
+            Set::Dict(dict) => dict.insert(member, ()).is_none(),
+        }
+    }
+
+    /// Removes a member, returning whether it was there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Set::IntSet(integers) => match parse_i64(member).map(|n| integers.binary_search(&n)) {
+                Some(Ok(index)) => {
+                    integers.remove(index);
+                    true
+                }
+                _ => false,
+            },
+            Set::Dict(dict) => dict.remove(member).is_some(),
+        }
+    }
+
+    pub fn members(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Set::IntSet(integers) => Box::new(integers.iter().map(|n| n.to_string().into())),
+            Set::Dict(dict) => Box::new(dict.iter().map(|(member, _)| member.clone())),
+        }
+    }
+
+    /// Returns a random member, or `None` if the set is empty.
+    pub fn random_member(&self, rng: &mut Rng) -> Option<Bytes> {
+        match self {
+            Set::IntSet(integers) if integers.is_empty() => None,
+            Set::IntSet(integers) => Some(integers[rng.below(integers.len())].to_string().into()),
+            Set::Dict(dict) => dict.random_entry(rng).map(|(member, _)| member.clone()),
+        }
+    }
+
+    /// Like [`Dict::scan_entries`], except that intsets are small enough to be
+    /// returned whole, with a cursor of 0.
+    pub fn scan_members(&self, cursor: u64, count: usize) -> (u64, Vec<Bytes>) {
+        match self {
+            Set::IntSet(_) => (0, self.members().collect()),
+            Set::Dict(dict) => {
+                let (cursor, entries) = dict.scan_entries(cursor, count);
+                let members = entries
+                    .into_iter()
+                    .map(|(member, _)| member.clone())
+                    .collect();
+                (cursor, members)
+            }
+        }
+    }
+
+    fn convert_to_dict(&mut self) {
+        if let Set::IntSet(integers) = self {
+            let mut dict = Dict::new();
+            for n in integers.iter() {
+                dict.insert(Bytes::from(n.to_string()), ());
+            }
+            *self = Set::Dict(dict);
+        }
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        let mut set = Set::new();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
@@ -0,0 +1,420 @@
+//! Commands operating on set values. Sets are never left empty: the key is
+//! removed along with the last member.
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    keyspace::{Keyspace, Value},
+    protocol::{Element, ElementScan, SetOperation},
+    set::Set,
+    utils::{glob_match, Rng},
+};
+
+/// Adds members, replying with how many of them weren't already there.
+pub fn sadd(db: &mut Keyspace, key: Bytes, members: Vec<Bytes>) -> Result<Element> {
+    if db.get_set(&key)?.is_none() {
+        db.insert(key.clone(), Value::set(Set::new()));
+    }
+    let set = db.get_set_mut(&key)?.expect("inserted above");
+    let added = members
+        .into_iter()
+        .filter(|member| set.insert(member.clone()))
+        .count();
+    Ok(Element::Integer(added as i64))
+}
+
+pub fn srem(db: &mut Keyspace, key: &[u8], members: &[Bytes]) -> Result<Element> {
+    let Some(set) = db.get_set_mut(key)? else {
+        return Ok(Element::Integer(0));
+    };
+    let removed = members.iter().filter(|member| set.remove(member)).count();
+    if set.is_empty() {
+        db.remove(key);
+    }
+    Ok(Element::Integer(removed as i64))
+}
+
+pub fn sismember(db: &mut Keyspace, key: &[u8], member: &[u8]) -> Result<Element> {
+    let contains = db.get_set(key)?.is_some_and(|set| set.contains(member));
+    Ok(Element::Integer(contains as i64))
+}
+
+pub fn smismember(db: &mut Keyspace, key: &[u8], members: &[Bytes]) -> Result<Element> {
+    let set = db.get_set(key)?;
+    Ok(Element::Array(
+        members
+            .iter()
+            .map(|member| {
+                let contains = set.is_some_and(|set| set.contains(member));
+                Element::Integer(contains as i64)
+            })
+            .collect(),
+    ))
+}
+
+pub fn scard(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let len = db.get_set(key)?.map_or(0, Set::len);
+    Ok(Element::Integer(len as i64))
+}
+
+pub fn smembers(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let members = db.get_set(key)?.map_or_else(Vec::new, |set| {
+        set.members().map(Element::BulkString).collect()
+    });
+    Ok(Element::Set(members))
+}
+
+/// Removes and replies with a random member, or with up to `count` distinct
+/// ones if given.
+pub fn spop(db: &mut Keyspace, key: &[u8], count: Option<usize>) -> Result<Element> {
+    let Some(set) = db.get_set_mut(key)? else {
+        return Ok(match count {
+            Some(_) => Element::Set(Vec::new()),
+            None => Element::Null,
+        });
+    };
+
+    let mut rng = Rng::new();
+    let mut popped = Vec::new();
+    while popped.len() < count.unwrap_or(1) {
+        let Some(member) = set.random_member(&mut rng) else {
+            break;
+        };
+        set.remove(&member);
+        popped.push(member);
+    }
+    if set.is_empty() {
+        db.remove(key);
+    }
+
+    Ok(match count {
+        Some(_) => Element::Set(popped.into_iter().map(Element::BulkString).collect()),
+        None => Element::BulkString(popped.pop().expect("sets are never empty")),
+    })
+}
+
+/// Replies with a random member, or with `count` of them if given: distinct
+/// ones if positive, possibly repeated ones if negative.
+pub fn srandmember(db: &mut Keyspace, key: &[u8], count: Option<i64>) -> Result<Element> {
+    let set = db.get_set(key)?;
+    let mut rng = Rng::new();
+    let Some(count) = count else {
+        return Ok(match set.and_then(|set| set.random_member(&mut rng)) {
+            Some(member) => Element::BulkString(member),
+            None => Element::Null,
+        });
+    };
+    let Some(set) = set else {
+        return Ok(Element::Array(Vec::new()));
+    };
+
+    let members: Vec<Bytes> = if count < 0 {
+        (0..count.unsigned_abs())
+            .map_while(|_| set.random_member(&mut rng))
+            .collect()
+    } else {
+        // Partial Fisher-Yates shuffle, for distinct members
+        let mut members: Vec<Bytes> = set.members().collect();
+        let count = (count as usize).min(members.len());
+        for i in 0..count {
+            let j = i + rng.below(members.len() - i);
+            members.swap(i, j);
+        }
+        members.truncate(count);
+        members
+    };
+    Ok(Element::Array(
+        members.into_iter().map(Element::BulkString).collect(),
+    ))
+}
+
+/// Moves a member from one set to another, replying with whether it was in the
+/// source set.
+pub fn smove(
+    db: &mut Keyspace,
+    source: &[u8],
+    destination: Bytes,
+    member: Bytes,
+) -> Result<Element> {
+    let Some(set) = db.get_set(source)? else {
+        return Ok(Element::Integer(0));
+    };
+    let contains = set.contains(&member);
+    db.get_set(&destination)?;
+    if !contains || source == destination {
+        return Ok(Element::Integer(contains as i64));
+    }
+
+    let set = db.get_set_mut(source)?.expect("checked above");
+    set.remove(&member);
+    if set.is_empty() {
+        db.remove(source);
+    }
+    sadd(db, destination, vec![member])?;
+    Ok(Element::Integer(1))
+}
+
+pub fn set_op(db: &mut Keyspace, operation: SetOperation, keys: &[Bytes]) -> Result<Element> {
+    let members = combine(db, operation, keys, 0)?;
+    Ok(Element::Set(
+        members.into_iter().map(Element::BulkString).collect(),
+    ))
+}
+
+/// Stores the result of an operation, replacing the destination whatever its
+/// type, and replies with its size.
+pub fn set_op_store(
+    db: &mut Keyspace,
+    operation: SetOperation,
+    destination: Bytes,
+    keys: &[Bytes],
+) -> Result<Element> {
+    let set: Set = combine(db, operation, keys, 0)?.into_iter().collect();
+    let len = set.len();
+    if set.is_empty() {
+        db.remove(&destination);
+    } else {
+        db.insert(destination, Value::set(set));
+    }
+    Ok(Element::Integer(len as i64))
+}
+
+/// Replies with the size of the intersection of the sets, counting up to
+/// `limit` members unless it's 0.
+pub fn sintercard(db: &mut Keyspace, keys: &[Bytes], limit: usize) -> Result<Element> {
+    let members = combine(db, SetOperation::Inter, keys, limit)?;
+    Ok(Element::Integer(members.len() as i64))
+}
+
+/// Iterates the members of a set, see [`crate::dict::Dict::scan`] for the
+/// guarantees this provides.
+pub fn sscan(db: &mut Keyspace, scan: ElementScan) -> Result<Element> {
+    let (cursor, members) = match db.get_set(&scan.key)? {
+        Some(set) => set.scan_members(scan.cursor, scan.count),
+        None => (0, Vec::new()),
+    };
+    let members = members
+        .into_iter()
+        .filter(|member| {
+            scan.pattern
+                .as_ref()
+                .is_none_or(|pattern| glob_match(pattern, member))
+        })
+        .map(Element::BulkString)
+        .collect();
+    Ok(Element::Array(vec![
+        Element::BulkString(cursor.to_string().into()),
+        Element::Array(members),
+    ]))
+}
+
+/// The members resulting from an operation on the sets stored at `keys`. The
+/// intersection stops after `limit` members, unless it's 0.
+fn combine(
+    db: &mut Keyspace,
+    operation: SetOperation,
+    keys: &[Bytes],
+    limit: usize,
+) -> Result<Vec<Bytes>> {
+    let sets = db.get_sets(keys)?;
+    let limit = if limit == 0 { usize::MAX } else { limit };
+    Ok(match operation {
+        SetOperation::Inter => {
+            // Any missing key makes the intersection empty
+            let Some(mut sets) = sets.into_iter().collect::<Option<Vec<&Set>>>() else {
+                return Ok(Vec::new());
+            };
+            // Only the members of the smallest set need to be checked
+            sets.sort_by_key(|set| set.len());
+            let (smallest, others) = sets.split_first().expect("there's at least one key");
+            smallest
+                .members()
+                .filter(|member| others.iter().all(|set| set.contains(member)))
+                .take(limit)
+                .collect()
+        }
+        SetOperation::Union => {
+            let union: Set = sets.into_iter().flatten().flat_map(Set::members).collect();
+            union.members().collect()
+        }
+        SetOperation::Diff => {
+            let (first, others) = sets.split_first().expect("there's at least one key");
+            first.map_or_else(Vec::new, |first| {
+                first
+                    .members()
+                    .filter(|member| others.iter().flatten().all(|set| !set.contains(member)))
+                    .collect()
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(members: &[&str]) -> Vec<Bytes> {
+        members
+            .iter()
+            .map(|member| Bytes::copy_from_slice(member.as_bytes()))
+            .collect()
+    }
+
+    fn add(db: &mut Keyspace, key: &str, members: &[&str]) {
+        sadd(db, Bytes::copy_from_slice(key.as_bytes()), bytes(members)).unwrap();
+    }
+
+    /// The members replied, sorted.
+    fn members(reply: Element) -> Vec<Bytes> {
+        let (Element::Set(elements) | Element::Array(elements)) = reply else {
+            panic!("replied {reply:?}");
+        };
+        let mut members: Vec<Bytes> = elements
+            .into_iter()
+            .map(|element| match element {
+                Element::BulkString(member) => member,
+                other => panic!("replied {other:?}"),
+            })
+            .collect();
+        members.sort();
+        members
+    }
+
+    fn integer(reply: Element) -> i64 {
+        match reply {
+            Element::Integer(i) => i,
+            other => panic!("replied {other:?}"),
+        }
+    }
+
+    #[test]
+    fn converts_intsets_to_hash_tables() {
+        let mut db = Keyspace::default();
+        let integers: Vec<String> = (0..512).map(|i| (i * 7 - 1000).to_string()).collect();
+        let integers: Vec<&str> = integers.iter().map(String::as_str).collect();
+        add(&mut db, "s", &integers);
+        assert!(matches!(db.get_set(b"s").unwrap(), Some(Set::IntSet(_))));
+        // Members already there don't count towards the limit
+        add(&mut db, "s", &["-1000"]);
+        assert!(matches!(db.get_set(b"s").unwrap(), Some(Set::IntSet(_))));
+        add(&mut db, "s", &["5000"]);
+        assert!(matches!(db.get_set(b"s").unwrap(), Some(Set::Dict(_))));
+        assert_eq!(integer(scard(&mut db, b"s").unwrap()), 513);
+        let mut expected = bytes(&integers);
+        expected.push(Bytes::from("5000"));
+        expected.sort();
+        assert_eq!(members(smembers(&mut db, b"s").unwrap()), expected);
+
+        // Only integers written the way Redis formats them fit in intsets
+        for member in ["a", "01", "+1", "-0", " 1", "1.0", "9223372036854775808"] {
+            let mut db = Keyspace::default();
+            add(&mut db, "s", &["1", member]);
+            assert!(matches!(db.get_set(b"s").unwrap(), Some(Set::Dict(_))));
+            let mut expected = bytes(&["1", member]);
+            expected.sort();
+            assert_eq!(members(smembers(&mut db, b"s").unwrap()), expected);
+            assert_eq!(integer(sismember(&mut db, b"s", b"1").unwrap()), 1);
+        }
+        let mut db = Keyspace::default();
+        add(
+            &mut db,
+            "s",
+            &["-9223372036854775808", "0", "9223372036854775807"],
+        );
+        assert!(matches!(db.get_set(b"s").unwrap(), Some(Set::IntSet(_))));
+        assert_eq!(integer(sismember(&mut db, b"s", b"00").unwrap()), 0);
+    }
+
+    #[test]
+    fn sintercard_stops_at_the_limit() {
+        let mut db = Keyspace::default();
+        add(&mut db, "a", &["1", "2", "3", "4", "x", "y"]);
+        add(&mut db, "b", &["2", "3", "4", "5", "y", "z"]);
+        let keys = bytes(&["a", "b"]);
+        for (limit, len) in [(0, 4), (1, 1), (3, 3), (4, 4), (100, 4)] {
+            assert_eq!(integer(sintercard(&mut db, &keys, limit).unwrap()), len);
+        }
+        // Any missing key makes the intersection empty
+        let keys = bytes(&["a", "b", "missing"]);
+        assert_eq!(integer(sintercard(&mut db, &keys, 0).unwrap()), 0);
+    }
+
+    #[test]
+    fn srandmember_repeats_members_for_negative_counts() {
+        let mut db = Keyspace::default();
+        add(&mut db, "s", &["a", "b"]);
+        let reply = members(srandmember(&mut db, b"s", Some(-20)).unwrap());
+        assert_eq!(reply.len(), 20);
+        assert!(reply.iter().all(|member| member == "a" || member == "b"));
+
+        // Positive counts are capped at the size of the set
+        let reply = members(srandmember(&mut db, b"s", Some(20)).unwrap());
+        assert_eq!(reply, bytes(&["a", "b"]));
+        let reply = members(srandmember(&mut db, b"s", Some(1)).unwrap());
+        assert_eq!(reply.len(), 1);
+        assert!(members(srandmember(&mut db, b"s", Some(0)).unwrap()).is_empty());
+        assert!(members(srandmember(&mut db, b"x", Some(-5)).unwrap()).is_empty());
+        assert!(matches!(
+            srandmember(&mut db, b"x", None).unwrap(),
+            Element::Null
+        ));
+
+        // Nothing is removed
+        assert_eq!(integer(scard(&mut db, b"s").unwrap()), 2);
+    }
+
+    #[test]
+    fn spop_removes_the_key_with_its_last_member() {
+        let mut db = Keyspace::default();
+        add(&mut db, "s", &["1", "2", "3", "a"]);
+        assert!(members(spop(&mut db, b"s", Some(0)).unwrap()).is_empty());
+        let popped = members(spop(&mut db, b"s", Some(3)).unwrap());
+        assert_eq!(popped.len(), 3);
+        assert_eq!(integer(scard(&mut db, b"s").unwrap()), 1);
+        let Element::BulkString(last) = spop(&mut db, b"s", None).unwrap() else {
+            panic!("popped a single member");
+        };
+        let mut all = popped;
+        all.push(last);
+        all.sort();
+        assert_eq!(all, bytes(&["1", "2", "3", "a"]));
+        assert!(db.get(b"s").is_none());
+
+        assert!(matches!(spop(&mut db, b"s", None).unwrap(), Element::Null));
+        assert!(members(spop(&mut db, b"s", Some(2)).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn stores_the_result_of_operations() {
+        let mut db = Keyspace::default();
+        add(&mut db, "a", &["1", "2", "x"]);
+        add(&mut db, "b", &["2", "3", "x"]);
+        for (operation, keys, expected) in [
+            (SetOperation::Inter, &["a", "b"][..], &["2", "x"][..]),
+            (SetOperation::Inter, &["a", "b", "missing"], &[]),
+            (
+                SetOperation::Union,
+                &["a", "missing", "b"],
+                &["1", "2", "3", "x"],
+            ),
+            (SetOperation::Diff, &["a", "b"], &["1"]),
+            (SetOperation::Diff, &["a", "missing"], &["1", "2", "x"]),
+            (SetOperation::Diff, &["missing", "a"], &[]),
+        ] {
+            let keys = bytes(keys);
+            let reply = set_op(&mut db, operation, &keys).unwrap();
+            assert_eq!(members(reply), bytes(expected), "{operation:?} {keys:?}");
+
+            // The destination is replaced, or removed if the result is empty
+            db.insert(Bytes::from("d"), Value::string(Bytes::from("v")));
+            let reply = set_op_store(&mut db, operation, Bytes::from("d"), &keys).unwrap();
+            assert_eq!(integer(reply), expected.len() as i64);
+            if expected.is_empty() {
+                assert!(db.get(b"d").is_none());
+            } else {
+                assert_eq!(members(smembers(&mut db, b"d").unwrap()), bytes(expected));
+            }
+        }
+    }
+}
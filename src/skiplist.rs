@@ -0,0 +1,373 @@
+use std::cmp::Ordering;
+
+use bytes::Bytes;
+
+use crate::utils::Rng;
+
+/// Largest number of levels of a node, enough for 4^32 elements.
+const MAX_LEVEL: usize = 32;
+
+/// The header node, which holds no element and starts every level.
+const HEAD: usize = 0;
+
+/// Elements of a sorted set, ordered by score and then by member, as in Redis'
+/// zskiplist. Each link also records how many elements it skips over, which is
+/// what makes finding the rank of an element, or the element at a rank,
+/// O(log n) rather than O(n).
+///
+/// Nodes are stored in a vector and linked by index, reusing the slots of
+/// removed ones.
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    /// Slots of removed nodes, to reuse before growing `nodes`
+    free: Vec<usize>,
+    /// Number of levels in use by the header node
+    level: usize,
+    len: usize,
+    rng: Rng,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Link>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    forward: Option<usize>,
+    /// Number of elements between the node and `forward`, counting the latter
+    span: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![Link::default(); MAX_LEVEL],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: Vec::new(),
+            level: 1,
+            len: 0,
+            rng: Rng::new(),
+        }
+    }
+}
+
+impl SkipList {
+    /// Inserts an element, which must not already be in the list.
+    pub fn insert(&mut self, score: f64, member: Bytes) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if self.nodes[next].cmp(score, &member) != Ordering::Less {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = self.random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let x = self.allocate(Node {
+            member,
+            score,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            levels: vec![Link::default(); level],
+        });
+        for i in 0..level {
+            let previous = self.nodes[update[i]].levels[i];
+            self.nodes[x].levels[i] = Link {
+                forward: previous.forward,
+                span: previous.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                forward: Some(x),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        // Links above the new node's levels now skip over one more element
+        for (i, &previous) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[previous].levels[i].span += 1;
+        }
+        if let Some(next) = self.nodes[x].levels[0].forward {
+            self.nodes[next].backward = Some(x);
+        }
+        self.len += 1;
+    }
+
+    /// Removes an element, returning whether it was in the list.
+    pub fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if self.nodes[next].cmp(score, member) != Ordering::Less {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let Some(x) = self.nodes[x].levels[0].forward else {
+            return false;
+        };
+        if self.nodes[x].cmp(score, member) != Ordering::Equal {
+            return false;
+        }
+
+        for (i, &previous) in update.iter().enumerate().take(self.level) {
+            let removed = self.nodes[x].levels.get(i).copied();
+            let link = &mut self.nodes[previous].levels[i];
+            match removed {
+                Some(removed) if link.forward == Some(x) => {
+                    link.span += removed.span;
+                    link.span -= 1;
+                    link.forward = removed.forward;
+                }
+                _ => link.span -= 1,
+            }
+        }
+        if let Some(next) = self.nodes[x].levels[0].forward {
+            self.nodes[next].backward = self.nodes[x].backward;
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+        self.nodes[x].member = Bytes::new();
+        self.nodes[x].levels = Vec::new();
+        self.free.push(x);
+        self.len -= 1;
+        true
+    }
+
+    /// Number of elements at the start of the list for which `before` holds,
+    /// which must be true for every element up to some point and false for
+    /// every element after it. This is the rank of the first element for which
+    /// it doesn't hold.
+    pub fn count_before(&self, before: impl Fn(f64, &[u8]) -> bool) -> usize {
+        let mut count = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                let node = &self.nodes[next];
+                if !before(node.score, &node.member) {
+                    break;
+                }
+                count += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        count
+    }
+
+    /// Iterates the elements starting from the one at `rank`, towards the end of
+    /// the list or, if `rev` is set, towards its start.
+    pub fn iter_from(&self, rank: usize, rev: bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            next: self.node_at(rank),
+            rev,
+        }
+    }
+
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None;
+        }
+        // Ranks of the header's links start at 1 for the first element
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                let span = self.nodes[x].levels[i].span;
+                if traversed + span > target {
+                    break;
+                }
+                traversed += span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    fn random_level(&mut self) -> usize {
+        // Each level is used by a quarter of the nodes of the level below
+        let mut level = 1;
+        while level < MAX_LEVEL && self.rng.next_u64().is_multiple_of(4) {
+            level += 1;
+        }
+        level
+    }
+
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(x) => {
+                self.nodes[x] = node;
+                x
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+}
+
+impl Node {
+    fn cmp(&self, score: f64, member: &[u8]) -> Ordering {
+        // Scores are never NaN
+        self.score
+            .partial_cmp(&score)
+            .expect("scores are comparable")
+            .then_with(|| self.member[..].cmp(member))
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    next: Option<usize>,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.list.nodes[self.next?];
+        self.next = if self.rev {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+        Some((&node.member, node.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Model = Vec<(f64, Bytes)>;
+
+    fn position(model: &Model, score: f64, member: &[u8]) -> Result<usize, usize> {
+        model.binary_search_by(|(s, m)| s.partial_cmp(&score).unwrap().then(m[..].cmp(member)))
+    }
+
+    /// Checks the list against a sorted model, along with the invariants of
+    /// its links.
+    fn check(list: &SkipList, model: &Model) {
+        assert_eq!(list.len, model.len());
+        assert!(list
+            .iter_from(0, false)
+            .eq(model.iter().map(|(s, m)| (m, *s))));
+
+        // The spans of each level add up to the length
+        for i in 0..list.level {
+            let mut x = Some(HEAD);
+            let mut traversed = 0;
+            while let Some(node) = x {
+                traversed += list.nodes[node].levels[i].span;
+                x = list.nodes[node].levels[i].forward;
+            }
+            assert_eq!(traversed, list.len, "spans of level {i}");
+        }
+        // Backward links mirror the first level
+        let mut previous = None;
+        let mut x = list.nodes[HEAD].levels[0].forward;
+        while let Some(node) = x {
+            assert_eq!(list.nodes[node].backward, previous);
+            previous = Some(node);
+            x = list.nodes[node].levels[0].forward;
+        }
+        // Levels are only kept as long as some node uses them
+        if list.len > 0 {
+            assert!(list.nodes[HEAD].levels[list.level - 1].forward.is_some());
+        } else {
+            assert_eq!(list.level, 1);
+        }
+        assert_eq!(list.nodes.len(), list.len + list.free.len() + 1);
+    }
+
+    #[test]
+    fn matches_a_sorted_vec() {
+        let mut rng = Rng::new();
+        let mut list = SkipList::default();
+        let mut model = Model::new();
+        let mut peak = 0;
+        let mut peak_level = 1;
+        for round in 0..5000 {
+            // Grow, then shrink back to empty
+            let growing = round % 2500 < 1000;
+            let score = rng.below(20) as f64 / 2.0;
+            let member = Bytes::from(format!("m{}", rng.below(100)));
+            match position(&model, score, &member) {
+                Err(index) if growing || rng.below(4) == 0 => {
+                    list.insert(score, member.clone());
+                    model.insert(index, (score, member));
+                }
+                Err(_) => assert!(!list.remove(score, &member)),
+                Ok(index) => {
+                    assert!(list.remove(score, &member));
+                    model.remove(index);
+                }
+            }
+            peak = peak.max(model.len());
+            if !growing && !model.is_empty() {
+                // Remove an existing element as well, so that the list empties
+                let (score, member) = model.remove(rng.below(model.len()));
+                assert!(list.remove(score, &member));
+            }
+            // Slots of removed nodes are reused
+            assert!(list.nodes.len() <= peak + 1);
+
+            if round % 50 == 0 {
+                check(&list, &model);
+                for rank in (0..=model.len()).step_by(model.len() / 20 + 1) {
+                    let elements = |rev| -> Model {
+                        let elements = list.iter_from(rank, rev);
+                        elements.map(|(m, s)| (s, m.clone())).collect()
+                    };
+                    let Some((score, member)) = model.get(rank) else {
+                        assert!(elements(false).is_empty() && elements(true).is_empty());
+                        continue;
+                    };
+                    assert_eq!(elements(false), model[rank..]);
+                    let mut before: Model = model[..=rank].to_vec();
+                    before.reverse();
+                    assert_eq!(elements(true), before);
+                    let before = |s: f64, m: &[u8]| (s, m) < (*score, &member[..]);
+                    assert_eq!(list.count_before(before), rank);
+                }
+            }
+            peak_level = peak_level.max(list.level);
+        }
+        assert!(peak_level > 2);
+        assert!(model.is_empty());
+        check(&list, &model);
+    }
+}
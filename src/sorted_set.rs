@@ -0,0 +1,113 @@
+use std::ops::Range;
+
+use bytes::Bytes;
+
+use crate::{
+    dict::Dict,
+    protocol::{LexRange, ScoreRange},
+    skiplist::SkipList,
+};
+
+/// A set of members, each with a score, ordered by score and then by member.
+/// Like Redis, the score of each member is kept in a hash table for O(1)
+/// lookups, and the members are also kept in a skiplist for ordered access,
+/// which finds ranks and ranges in O(log n).
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: Dict<Bytes, f64>,
+    list: SkipList,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        SortedSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds a member or updates its score, returning the previous one.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        let previous = self.scores.insert(member.clone(), score);
+        match previous {
+            Some(previous) if previous == score => {}
+            Some(previous) => {
+                self.list.remove(previous, &member);
+                self.list.insert(score, member);
+            }
+            None => self.list.insert(score, member),
+        }
+        previous
+    }
+
+    /// Removes a member, returning its score.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.list.remove(score, member);
+        Some(score)
+    }
+
+    /// The position of a member, starting from 0 for the lowest score or, if
+    /// `rev` is set, for the highest.
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self
+            .list
+            .count_before(|s, m| s < score || (s == score && m < member));
+        Some(if rev { self.len() - 1 - rank } else { rank })
+    }
+
+    /// Ranks of the members whose score is in `range`.
+    pub fn score_range(&self, range: &ScoreRange) -> Range<usize> {
+        let start = self.list.count_before(|score, _| range.is_below(score));
+        let end = self.list.count_before(|score, _| !range.is_above(score));
+        start..end.max(start)
+    }
+
+    /// Ranks of the members in `range`, assuming they all have the same score.
+    pub fn lex_range(&self, range: &LexRange) -> Range<usize> {
+        let start = self.list.count_before(|_, member| range.is_below(member));
+        let end = self.list.count_before(|_, member| !range.is_above(member));
+        start..end.max(start)
+    }
+
+    /// Iterates the members whose rank is in `ranks`, from the highest rank to
+    /// the lowest if `rev` is set.
+    pub fn iter_ranks(
+        &self,
+        ranks: Range<usize>,
+        rev: bool,
+    ) -> impl Iterator<Item = (&Bytes, f64)> + '_ {
+        let len = ranks.len();
+        let start = if rev {
+            ranks.end.saturating_sub(1)
+        } else {
+            ranks.start
+        };
+        self.list.iter_from(start, rev).take(len)
+    }
+
+    /// Iterates the members in order of score.
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> + '_ {
+        self.iter_ranks(0..self.len(), false)
+    }
+
+    /// See [`Dict::scan_entries`].
+    pub fn scan_entries(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, f64)>) {
+        let (cursor, entries) = self.scores.scan_entries(cursor, count);
+        let entries = entries
+            .into_iter()
+            .map(|(member, score)| (member, *score))
+            .collect();
+        (cursor, entries)
+    }
+}
@@ -0,0 +1,894 @@
+//! Commands operating on sorted set values. Sorted sets are never left empty:
+//! the key is removed along with the last member.
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use std::ops::Range;
+
+use crate::{
+    error::RedisError,
+    keyspace::{Data, Keyspace, Value},
+    lists::resolve_range,
+    protocol::{
+        Aggregate, Element, ElementScan, ScoreComparison, ScoreRange, SetCondition, SetOperation,
+        SortedSetEnd, ZAdd, ZMPop, ZRange, ZRangeBy, ZRank, ZSetOp,
+    },
+    set::Set,
+    sorted_set::SortedSet,
+    utils::{format_f64, glob_match},
+};
+
+/// Adds members or updates their scores, see [`ZAdd`] for the options. Replies
+/// with the number of members added, or with the new score of the member with
+/// `INCR`.
+pub fn zadd(db: &mut Keyspace, zadd: ZAdd) -> Result<Element> {
+    let ZAdd {
+        key,
+        condition,
+        comparison,
+        changed,
+        incr,
+        elements,
+    } = zadd;
+    let no_reply = || {
+        if incr {
+            Element::Null
+        } else {
+            Element::Integer(0)
+        }
+    };
+
+    let sorted_set = match db.get_sorted_set_mut(&key)? {
+        Some(sorted_set) => sorted_set,
+        None if condition == Some(SetCondition::Xx) => return Ok(no_reply()),
+        None => {
+            db.insert(key.clone(), Value::sorted_set(SortedSet::new()));
+            db.get_sorted_set_mut(&key)?.expect("inserted above")
+        }
+    };
+
+    let mut added = 0;
+    let mut updated = 0;
+    let mut score_reply = None;
+    for (score, member) in elements {
+        match sorted_set.score(&member) {
+            Some(current) => {
+                if condition == Some(SetCondition::Nx) {
+                    continue;
+                }
+                let score = if incr {
+                    increment(current, score)?
+                } else {
+                    score
+                };
+                let allowed = match comparison {
+                    Some(ScoreComparison::Gt) => score > current,
+                    Some(ScoreComparison::Lt) => score < current,
+                    None => true,
+                };
+                if !allowed {
+                    continue;
+                }
+                if score != current {
+                    sorted_set.insert(member, score);
+                    updated += 1;
+                }
+                score_reply = Some(score);
+            }
+            None => {
+                if condition == Some(SetCondition::Xx) {
+                    continue;
+                }
+                sorted_set.insert(member, score);
+                added += 1;
+                score_reply = Some(score);
+            }
+        }
+    }
+
+    // Nothing may have been added to a new sorted set
+    if sorted_set.is_empty() {
+        db.remove(&key);
+    }
+    Ok(if incr {
+        score_reply.map_or_else(no_reply, Element::Double)
+    } else if changed {
+        Element::Integer(added + updated)
+    } else {
+        Element::Integer(added)
+    })
+}
+
+pub fn zrem(db: &mut Keyspace, key: &[u8], members: &[Bytes]) -> Result<Element> {
+    let Some(sorted_set) = db.get_sorted_set_mut(key)? else {
+        return Ok(Element::Integer(0));
+    };
+    let removed = members
+        .iter()
+        .filter(|member| sorted_set.remove(member).is_some())
+        .count();
+    if sorted_set.is_empty() {
+        db.remove(key);
+    }
+    Ok(Element::Integer(removed as i64))
+}
+
+pub fn zscore(db: &mut Keyspace, key: &[u8], member: &[u8]) -> Result<Element> {
+    Ok(
+        match db
+            .get_sorted_set(key)?
+            .and_then(|sorted_set| sorted_set.score(member))
+        {
+            Some(score) => Element::Double(score),
+            None => Element::Null,
+        },
+    )
+}
+
+pub fn zmscore(db: &mut Keyspace, key: &[u8], members: &[Bytes]) -> Result<Element> {
+    let sorted_set = db.get_sorted_set(key)?;
+    Ok(Element::Array(
+        members
+            .iter()
+            .map(
+                |member| match sorted_set.and_then(|sorted_set| sorted_set.score(member)) {
+                    Some(score) => Element::Double(score),
+                    None => Element::Null,
+                },
+            )
+            .collect(),
+    ))
+}
+
+pub fn zincrby(db: &mut Keyspace, key: Bytes, increment: f64, member: Bytes) -> Result<Element> {
+    zadd(
+        db,
+        ZAdd {
+            key,
+            condition: None,
+            comparison: None,
+            changed: false,
+            incr: true,
+            elements: vec![(increment, member)],
+        },
+    )
+}
+
+pub fn zcard(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let len = db.get_sorted_set(key)?.map_or(0, SortedSet::len);
+    Ok(Element::Integer(len as i64))
+}
+
+pub fn zcount(db: &mut Keyspace, key: &[u8], range: &ScoreRange) -> Result<Element> {
+    let count = db
+        .get_sorted_set(key)?
+        .map_or(0, |sorted_set| sorted_set.score_range(range).len());
+    Ok(Element::Integer(count as i64))
+}
+
+/// Replies with the rank of a member, and its score with `WITHSCORE`.
+pub fn zrank(db: &mut Keyspace, zrank: ZRank) -> Result<Element> {
+    let sorted_set = db.get_sorted_set(&zrank.key)?;
+    let rank = sorted_set.and_then(|sorted_set| {
+        let rank = sorted_set.rank(&zrank.member, zrank.rev)?;
+        Some((rank, sorted_set.score(&zrank.member)?))
+    });
+    Ok(match rank {
+        Some((rank, score)) if zrank.with_score => {
+            Element::Array(vec![Element::Integer(rank as i64), Element::Double(score)])
+        }
+        Some((rank, _)) => Element::Integer(rank as i64),
+        None if zrank.with_score => Element::NullArray,
+        None => Element::Null,
+    })
+}
+
+pub fn zrange(db: &mut Keyspace, zrange: ZRange) -> Result<Element> {
+    let elements = match db.get_sorted_set(&zrange.key)? {
+        Some(sorted_set) => range(sorted_set, &zrange),
+        None => Vec::new(),
+    };
+    Ok(reply(elements, zrange.with_scores))
+}
+
+/// Stores the result of a `ZRANGE`, replacing the destination whatever its
+/// type, and replies with its size.
+pub fn zrangestore(db: &mut Keyspace, destination: Bytes, zrange: ZRange) -> Result<Element> {
+    let elements = match db.get_sorted_set(&zrange.key)? {
+        Some(sorted_set) => range(sorted_set, &zrange),
+        None => Vec::new(),
+    };
+    let len = elements.len();
+    if elements.is_empty() {
+        db.remove(&destination);
+    } else {
+        let mut sorted_set = SortedSet::new();
+        for (member, score) in elements {
+            sorted_set.insert(member, score);
+        }
+        db.insert(destination, Value::sorted_set(sorted_set));
+    }
+    Ok(Element::Integer(len as i64))
+}
+
+/// Iterates the members of a sorted set and their scores, see
+/// [`crate::dict::Dict::scan`] for the guarantees this provides.
+pub fn zscan(db: &mut Keyspace, scan: ElementScan) -> Result<Element> {
+    let (cursor, elements) = match db.get_sorted_set(&scan.key)? {
+        Some(sorted_set) => {
+            let (cursor, entries) = sorted_set.scan_entries(scan.cursor, scan.count);
+            let mut elements = Vec::new();
+            for (member, score) in entries {
+                if let Some(pattern) = &scan.pattern {
+                    if !glob_match(pattern, member) {
+                        continue;
+                    }
+                }
+                elements.push(Element::BulkString(member.clone()));
+                elements.push(Element::BulkString(format_f64(score).into()));
+            }
+            (cursor, elements)
+        }
+        None => (0, Vec::new()),
+    };
+    Ok(Element::Array(vec![
+        Element::BulkString(cursor.to_string().into()),
+        Element::Array(elements),
+    ]))
+}
+
+pub fn zset_op(db: &mut Keyspace, zset_op: ZSetOp) -> Result<Element> {
+    let result = combine(db, &zset_op, 0)?;
+    Ok(reply(
+        result.iter().map(|(member, score)| (member.clone(), score)),
+        zset_op.with_scores,
+    ))
+}
+
+/// Stores the result of an operation, replacing the destination whatever its
+/// type, and replies with its size.
+pub fn zset_op_store(db: &mut Keyspace, destination: Bytes, zset_op: ZSetOp) -> Result<Element> {
+    let result = combine(db, &zset_op, 0)?;
+    let len = result.len();
+    if result.is_empty() {
+        db.remove(&destination);
+    } else {
+        db.insert(destination, Value::sorted_set(result));
+    }
+    Ok(Element::Integer(len as i64))
+}
+
+/// Replies with the size of the intersection of the sorted sets, counting up
+/// to `limit` members unless it's 0.
+pub fn zintercard(db: &mut Keyspace, keys: Vec<Bytes>, limit: usize) -> Result<Element> {
+    let weights = vec![1.0; keys.len()];
+    let zset_op = ZSetOp {
+        operation: SetOperation::Inter,
+        keys,
+        weights,
+        aggregate: Aggregate::Sum,
+        with_scores: false,
+    };
+    let result = combine(db, &zset_op, limit)?;
+    Ok(Element::Integer(result.len() as i64))
+}
+
+/// Removes the members in a range (`ZREMRANGEBYRANK`, `ZREMRANGEBYSCORE`,
+/// `ZREMRANGEBYLEX`), replying with how many there were.
+pub fn zremrange(db: &mut Keyspace, key: &[u8], by: &ZRangeBy) -> Result<Element> {
+    let Some(sorted_set) = db.get_sorted_set_mut(key)? else {
+        return Ok(Element::Integer(0));
+    };
+    let ranks = ranks(sorted_set, by, false);
+    let members: Vec<Bytes> = sorted_set
+        .iter_ranks(ranks, false)
+        .map(|(member, _)| member.clone())
+        .collect();
+    for member in &members {
+        sorted_set.remove(member);
+    }
+    if sorted_set.is_empty() {
+        db.remove(key);
+    }
+    Ok(Element::Integer(members.len() as i64))
+}
+
+/// Replies with a member and its score, or with up to `count` of them if given.
+pub fn zpop(
+    db: &mut Keyspace,
+    end: SortedSetEnd,
+    key: &[u8],
+    count: Option<usize>,
+) -> Result<Element> {
+    let elements = pop_elements(db, key, end, count.unwrap_or(1))?;
+    Ok(match count {
+        Some(_) => reply(elements, true),
+        None => Element::Array(
+            elements
+                .into_iter()
+                .flat_map(|(member, score)| [Element::BulkString(member), Element::Double(score)])
+                .collect(),
+        ),
+    })
+}
+
+/// Pops up to `count` members from the first non empty sorted set, replying
+/// with its key and the members with their scores.
+pub fn zmpop(db: &mut Keyspace, zmpop: ZMPop) -> Result<Element> {
+    for key in zmpop.keys {
+        if db.get_sorted_set(&key)?.is_some() {
+            let elements = pop_elements(db, &key, zmpop.end, zmpop.count)?;
+            return Ok(Element::Array(vec![
+                Element::BulkString(key),
+                Element::Array(
+                    elements
+                        .into_iter()
+                        .map(|(member, score)| {
+                            Element::Array(vec![
+                                Element::BulkString(member),
+                                Element::Double(score),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]));
+        }
+    }
+    Ok(Element::NullArray)
+}
+
+/// Pops up to `count` members from a sorted set, removing the key if it's left
+/// empty.
+pub fn pop_elements(
+    db: &mut Keyspace,
+    key: &[u8],
+    end: SortedSetEnd,
+    count: usize,
+) -> Result<Vec<(Bytes, f64)>> {
+    let Some(sorted_set) = db.get_sorted_set_mut(key)? else {
+        return Ok(Vec::new());
+    };
+
+    let len = sorted_set.len();
+    let count = count.min(len);
+    let (ranks, rev) = match end {
+        SortedSetEnd::Min => (0..count, false),
+        SortedSetEnd::Max => (len - count..len, true),
+    };
+    let elements: Vec<(Bytes, f64)> = sorted_set
+        .iter_ranks(ranks, rev)
+        .map(|(member, score)| (member.clone(), score))
+        .collect();
+    for (member, _) in &elements {
+        sorted_set.remove(member);
+    }
+
+    if sorted_set.is_empty() {
+        db.remove(key);
+    }
+    Ok(elements)
+}
+
+/// A value that sorted set operations accept as input.
+enum Input<'a> {
+    Set(&'a Set),
+    SortedSet(&'a SortedSet),
+}
+
+impl<'a> Input<'a> {
+    fn len(&self) -> usize {
+        match self {
+            Input::Set(set) => set.len(),
+            Input::SortedSet(sorted_set) => sorted_set.len(),
+        }
+    }
+
+    fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Input::Set(set) => set.contains(member).then_some(1.0),
+            Input::SortedSet(sorted_set) => sorted_set.score(member),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Bytes, f64)> + 'a> {
+        match *self {
+            Input::Set(set) => Box::new(set.members().map(|member| (member, 1.0))),
+            Input::SortedSet(sorted_set) => Box::new(
+                sorted_set
+                    .iter()
+                    .map(|(member, score)| (member.clone(), score)),
+            ),
+        }
+    }
+}
+
+/// The result of an operation on the values stored at its keys. The
+/// intersection stops after `limit` members, unless it's 0.
+fn combine(db: &mut Keyspace, zset_op: &ZSetOp, limit: usize) -> Result<SortedSet> {
+    let inputs = db
+        .get_many(&zset_op.keys)
+        .into_iter()
+        .map(|value| match value.map(|value| &value.data) {
+            None => Ok(None),
+            Some(Data::Set(set)) => Ok(Some(Input::Set(set))),
+            Some(Data::SortedSet(sorted_set)) => Ok(Some(Input::SortedSet(sorted_set))),
+            Some(_) => bail!(RedisError::WrongType),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let limit = if limit == 0 { usize::MAX } else { limit };
+    let weighted = |score: f64, i: usize| {
+        // Infinite scores weighted by 0 are 0 rather than NaN
+        let score = score * zset_op.weights[i];
+        if score.is_nan() {
+            0.0
+        } else {
+            score
+        }
+    };
+
+    let mut result = SortedSet::new();
+    match zset_op.operation {
+        SetOperation::Union => {
+            for (i, input) in inputs.iter().enumerate() {
+                let Some(input) = input else {
+                    continue;
+                };
+                for (member, score) in input.iter() {
+                    let score = weighted(score, i);
+                    let score = match result.score(&member) {
+                        Some(current) => aggregate(zset_op.aggregate, current, score),
+                        None => score,
+                    };
+                    result.insert(member, score);
+                }
+            }
+        }
+        SetOperation::Inter => {
+            // Any missing key makes the intersection empty
+            let Some(inputs) = inputs.into_iter().collect::<Option<Vec<Input>>>() else {
+                return Ok(result);
+            };
+            // Only the members of the smallest input need to be checked
+            let mut order: Vec<usize> = (0..inputs.len()).collect();
+            order.sort_by_key(|&i| inputs[i].len());
+            let (&smallest, others) = order.split_first().expect("there's at least one key");
+            'members: for (member, score) in inputs[smallest].iter() {
+                if result.len() == limit {
+                    break;
+                }
+                let mut total = weighted(score, smallest);
+                for &i in others {
+                    let Some(score) = inputs[i].score(&member) else {
+                        continue 'members;
+                    };
+                    total = aggregate(zset_op.aggregate, total, weighted(score, i));
+                }
+                result.insert(member, total);
+            }
+        }
+        SetOperation::Diff => {
+            let (first, others) = inputs.split_first().expect("there's at least one key");
+            if let Some(first) = first {
+                for (member, score) in first.iter() {
+                    if others
+                        .iter()
+                        .flatten()
+                        .all(|input| input.score(&member).is_none())
+                    {
+                        result.insert(member, score);
+                    }
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn aggregate(aggregate: Aggregate, a: f64, b: f64) -> f64 {
+    match aggregate {
+        Aggregate::Sum => {
+            // Infinities of opposite signs add up to 0 rather than NaN
+            let sum = a + b;
+            if sum.is_nan() {
+                0.0
+            } else {
+                sum
+            }
+        }
+        Aggregate::Min => a.min(b),
+        Aggregate::Max => a.max(b),
+    }
+}
+
+/// The members selected by a `ZRANGE`, in the order they're replied with.
+fn range(sorted_set: &SortedSet, zrange: &ZRange) -> Vec<(Bytes, f64)> {
+    let ranks = ranks(sorted_set, &zrange.by, zrange.rev);
+
+    if zrange.offset < 0 {
+        return Vec::new();
+    }
+    let skip = (zrange.offset as usize).min(ranks.len());
+    let take = match zrange.count {
+        count if count < 0 => ranks.len() - skip,
+        count => (count as usize).min(ranks.len() - skip),
+    };
+    let ranks = if zrange.rev {
+        ranks.end - skip - take..ranks.end - skip
+    } else {
+        ranks.start + skip..ranks.start + skip + take
+    };
+    sorted_set
+        .iter_ranks(ranks, zrange.rev)
+        .map(|(member, score)| (member.clone(), score))
+        .collect()
+}
+
+/// Ranks of the members in a range, with indexes counting from the highest
+/// score if `rev` is set.
+fn ranks(sorted_set: &SortedSet, by: &ZRangeBy, rev: bool) -> Range<usize> {
+    match by {
+        ZRangeBy::Rank(start, stop) => {
+            let len = sorted_set.len();
+            match resolve_range(len, *start, *stop) {
+                Some((start, stop)) if rev => len - 1 - stop..len - start,
+                Some((start, stop)) => start..stop + 1,
+                None => 0..0,
+            }
+        }
+        ZRangeBy::Score(range) => sorted_set.score_range(range),
+        ZRangeBy::Lex(range) => sorted_set.lex_range(range),
+    }
+}
+
+/// Replies with members, followed by their scores with `WITHSCORES`.
+fn reply(elements: impl IntoIterator<Item = (Bytes, f64)>, with_scores: bool) -> Element {
+    if with_scores {
+        Element::Pairs(
+            elements
+                .into_iter()
+                .map(|(member, score)| (Element::BulkString(member), Element::Double(score)))
+                .collect(),
+        )
+    } else {
+        Element::Array(
+            elements
+                .into_iter()
+                .map(|(member, _)| Element::BulkString(member))
+                .collect(),
+        )
+    }
+}
+
+fn increment(score: f64, increment: f64) -> Result<f64> {
+    let result = score + increment;
+    if result.is_nan() {
+        bail!(RedisError::Err(
+            "resulting score is not a number (NaN)".to_string()
+        ));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{LexBound, LexRange};
+
+    use super::*;
+
+    fn key(key: &str) -> Bytes {
+        Bytes::copy_from_slice(key.as_bytes())
+    }
+
+    fn insert(db: &mut Keyspace, key: &str, members: &[(&str, f64)]) {
+        let mut sorted_set = SortedSet::new();
+        for &(member, score) in members {
+            sorted_set.insert(self::key(member), score);
+        }
+        db.insert(self::key(key), Value::sorted_set(sorted_set));
+    }
+
+    /// The members of the sorted set stored at `key` with their scores, in
+    /// order.
+    fn contents(db: &mut Keyspace, key: &str) -> Vec<(String, f64)> {
+        let sorted_set = db.get_sorted_set(key.as_bytes()).unwrap();
+        sorted_set.map_or(Vec::new(), |sorted_set| {
+            sorted_set
+                .iter()
+                .map(|(member, score)| (String::from_utf8_lossy(member).into_owned(), score))
+                .collect()
+        })
+    }
+
+    fn expected(members: &[(&str, f64)]) -> Vec<(String, f64)> {
+        members
+            .iter()
+            .map(|&(member, score)| (member.to_string(), score))
+            .collect()
+    }
+
+    fn integer(reply: Element) -> i64 {
+        match reply {
+            Element::Integer(i) => i,
+            other => panic!("replied {other:?}"),
+        }
+    }
+
+    /// `x`, `y` and `z` spread over two sorted sets, and a plain set.
+    fn inputs() -> Keyspace {
+        let mut db = Keyspace::default();
+        insert(&mut db, "a", &[("x", 1.0), ("y", 2.0)]);
+        insert(&mut db, "b", &[("y", 10.0), ("z", 20.0)]);
+        let set: Set = [key("x"), key("w")].into_iter().collect();
+        db.insert(key("s"), Value::set(set));
+        db
+    }
+
+    fn weighted_op(
+        operation: SetOperation,
+        keys: &[&str],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> ZSetOp {
+        ZSetOp {
+            operation,
+            keys: keys.iter().map(|&k| key(k)).collect(),
+            weights: weights.to_vec(),
+            aggregate,
+            with_scores: false,
+        }
+    }
+
+    #[test]
+    fn zunionstore_weights_and_aggregates_scores() {
+        for (keys, weights, aggregate, result) in [
+            (
+                &["a", "b"][..],
+                &[1.0, 1.0][..],
+                Aggregate::Sum,
+                &[("x", 1.0), ("y", 12.0), ("z", 20.0)][..],
+            ),
+            (
+                &["a", "b"],
+                &[2.0, 0.5],
+                Aggregate::Sum,
+                &[("x", 2.0), ("y", 9.0), ("z", 10.0)],
+            ),
+            (
+                &["a", "b"],
+                &[1.0, 1.0],
+                Aggregate::Min,
+                &[("x", 1.0), ("y", 2.0), ("z", 20.0)],
+            ),
+            (
+                &["a", "b"],
+                &[1.0, -1.0],
+                Aggregate::Max,
+                &[("z", -20.0), ("x", 1.0), ("y", 2.0)],
+            ),
+            // Members of plain sets have a score of 1
+            (
+                &["a", "s", "missing"],
+                &[1.0, 3.0, 1.0],
+                Aggregate::Sum,
+                &[("y", 2.0), ("w", 3.0), ("x", 4.0)],
+            ),
+        ] {
+            let mut db = inputs();
+            let op = weighted_op(SetOperation::Union, keys, weights, aggregate);
+            let reply = zset_op_store(&mut db, key("d"), op).unwrap();
+            assert_eq!(integer(reply), result.len() as i64);
+            assert_eq!(
+                contents(&mut db, "d"),
+                expected(result),
+                "{keys:?} {weights:?} {aggregate:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn zinter_weights_and_aggregates_scores() {
+        for (keys, weights, aggregate, result) in [
+            (
+                &["a", "b"][..],
+                &[1.0, 1.0][..],
+                Aggregate::Sum,
+                &[("y", 12.0)][..],
+            ),
+            (&["a", "b"], &[1.0, -1.0], Aggregate::Max, &[("y", 2.0)]),
+            (&["b", "a"], &[1.0, 3.0], Aggregate::Min, &[("y", 6.0)]),
+            (&["a", "s"], &[2.0, 5.0], Aggregate::Sum, &[("x", 7.0)]),
+            (
+                &["a", "b", "missing"],
+                &[1.0, 1.0, 1.0],
+                Aggregate::Sum,
+                &[],
+            ),
+        ] {
+            let mut db = inputs();
+            let reply = zset_op(
+                &mut db,
+                weighted_op(SetOperation::Inter, keys, weights, aggregate),
+            );
+            let Element::Array(members) = reply.unwrap() else {
+                panic!("replied without scores");
+            };
+            assert_eq!(members.len(), result.len());
+
+            // An empty result deletes the destination
+            db.insert(key("d"), Value::string(key("v")));
+            let op = weighted_op(SetOperation::Inter, keys, weights, aggregate);
+            zset_op_store(&mut db, key("d"), op).unwrap();
+            assert_eq!(
+                contents(&mut db, "d"),
+                expected(result),
+                "{keys:?} {weights:?} {aggregate:?}"
+            );
+            if result.is_empty() {
+                assert!(db.get(b"d").is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn combines_infinite_scores_without_nan() {
+        let mut db = Keyspace::default();
+        insert(&mut db, "a", &[("x", f64::INFINITY)]);
+        insert(&mut db, "b", &[("x", f64::NEG_INFINITY)]);
+        for (weights, aggregate, score) in [
+            (&[1.0, 1.0], Aggregate::Sum, 0.0),
+            (&[0.0, 1.0], Aggregate::Max, 0.0),
+            (&[1.0, 1.0], Aggregate::Min, f64::NEG_INFINITY),
+        ] {
+            let op = weighted_op(SetOperation::Union, &["a", "b"], weights, aggregate);
+            zset_op_store(&mut db, key("d"), op).unwrap();
+            assert_eq!(
+                contents(&mut db, "d"),
+                expected(&[("x", score)]),
+                "{weights:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn zdiff_and_zintercard() {
+        let mut db = inputs();
+        let op = weighted_op(SetOperation::Diff, &["a", "b"], &[1.0, 1.0], Aggregate::Sum);
+        zset_op_store(&mut db, key("d"), op).unwrap();
+        assert_eq!(contents(&mut db, "d"), expected(&[("x", 1.0)]));
+        let op = weighted_op(
+            SetOperation::Diff,
+            &["a", "s", "b"],
+            &[1.0; 3],
+            Aggregate::Sum,
+        );
+        assert_eq!(integer(zset_op_store(&mut db, key("d"), op).unwrap()), 0);
+        assert!(db.get(b"d").is_none());
+
+        insert(
+            &mut db,
+            "c",
+            &[("x", 1.0), ("y", 2.0), ("z", 3.0), ("w", 4.0)],
+        );
+        insert(&mut db, "e", &[("x", 5.0), ("y", 6.0), ("w", 7.0)]);
+        for (limit, len) in [(0, 3), (1, 1), (2, 2), (3, 3), (10, 3)] {
+            let reply = zintercard(&mut db, vec![key("c"), key("e")], limit).unwrap();
+            assert_eq!(integer(reply), len);
+        }
+        let reply = zintercard(&mut db, vec![key("c"), key("missing")], 0).unwrap();
+        assert_eq!(integer(reply), 0);
+    }
+
+    #[test]
+    fn zrangestore_stores_ranges() {
+        let members = [("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 4.0), ("e", 5.0)];
+        let range = |by, rev, offset, count| ZRange {
+            key: key("z"),
+            by,
+            rev,
+            offset,
+            count,
+            with_scores: false,
+        };
+        let score = |min, max| {
+            ZRangeBy::Score(ScoreRange {
+                min,
+                min_exclusive: false,
+                max,
+                max_exclusive: true,
+            })
+        };
+        for (zrange, result) in [
+            (range(ZRangeBy::Rank(1, -2), false, 0, -1), &members[1..4]),
+            (range(ZRangeBy::Rank(0, 1), true, 0, -1), &members[3..]),
+            (range(score(2.0, 5.0), false, 1, 1), &members[2..3]),
+            // Reversed ranges are limited from the highest score
+            (range(score(2.0, 5.0), true, 0, 2), &members[2..4]),
+            (range(score(5.0, 2.0), false, 0, -1), &[]),
+            (range(ZRangeBy::Rank(3, 1), false, 0, -1), &[]),
+        ] {
+            let mut db = Keyspace::default();
+            insert(&mut db, "z", &members);
+            db.insert(key("d"), Value::string(key("v")));
+            let context = format!("{zrange:?}");
+            let reply = zrangestore(&mut db, key("d"), zrange).unwrap();
+            assert_eq!(integer(reply), result.len() as i64, "{context}");
+            assert_eq!(contents(&mut db, "d"), expected(result), "{context}");
+            if result.is_empty() {
+                assert!(db.get(b"d").is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn zremrange_removes_ranges() {
+        let members = [("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 4.0)];
+        let lex = |min, max| ZRangeBy::Lex(LexRange { min, max });
+        for (by, left) in [
+            (ZRangeBy::Rank(0, 1), &members[2..]),
+            (ZRangeBy::Rank(-1, -1), &members[..3]),
+            (ZRangeBy::Rank(2, 1), &members[..]),
+            (
+                ZRangeBy::Score(ScoreRange {
+                    min: 1.0,
+                    min_exclusive: true,
+                    max: 3.0,
+                    max_exclusive: false,
+                }),
+                &[members[0], members[3]][..],
+            ),
+            (
+                lex(LexBound::Exclusive(key("a")), LexBound::Max),
+                &members[..1],
+            ),
+            (lex(LexBound::Min, LexBound::Inclusive(key("d"))), &[]),
+        ] {
+            let mut db = Keyspace::default();
+            insert(&mut db, "z", &members);
+            let reply = zremrange(&mut db, b"z", &by).unwrap();
+            assert_eq!(
+                integer(reply),
+                (members.len() - left.len()) as i64,
+                "{by:?}"
+            );
+            assert_eq!(contents(&mut db, "z"), expected(left), "{by:?}");
+            if left.is_empty() {
+                assert!(db.get(b"z").is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn pops_from_either_end() {
+        let mut db = Keyspace::default();
+        insert(
+            &mut db,
+            "z",
+            &[("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 4.0)],
+        );
+        let popped = pop_elements(&mut db, b"z", SortedSetEnd::Max, 2).unwrap();
+        assert_eq!(popped, [(key("d"), 4.0), (key("c"), 3.0)]);
+        let popped = pop_elements(&mut db, b"z", SortedSetEnd::Min, 1).unwrap();
+        assert_eq!(popped, [(key("a"), 1.0)]);
+        assert_eq!(contents(&mut db, "z"), expected(&[("b", 2.0)]));
+
+        // ZMPOP pops from the first key that exists, removing it once empty
+        let pop = ZMPop {
+            keys: vec![key("missing"), key("z")],
+            end: SortedSetEnd::Min,
+            count: 10,
+        };
+        let reply = zmpop(&mut db, pop).unwrap();
+        assert_eq!(
+            format!("{reply:?}"),
+            r#"Array([BulkString(b"z"), Array([Array([BulkString(b"b"), Double(2.0)])])])"#
+        );
+        assert!(db.get(b"z").is_none());
+        let pop = ZMPop {
+            keys: vec![key("z")],
+            end: SortedSetEnd::Max,
+            count: 1,
+        };
+        assert!(matches!(zmpop(&mut db, pop).unwrap(), Element::NullArray));
+    }
+}
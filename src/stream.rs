@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+
+use crate::protocol::{StreamId, StreamTrim, TrimStrategy};
+
+/// Largest number of entries stored by each block, matching Redis' default
+/// `stream-node-max-entries`.
+const BLOCK_CAPACITY: usize = 100;
+
+/// Entries removed at most by approximate trimming without a `LIMIT`.
+const DEFAULT_TRIM_LIMIT: usize = 100 * BLOCK_CAPACITY;
+
+/// Entries ordered by ID, stored in the spirit of Redis' radix tree of
+/// listpacks: each block holds consecutive entries, and is indexed by the ID
+/// of the first entry added to it. Finding an entry only searches the index
+/// and a single block, and approximate trimming removes whole blocks at once.
+///
+/// Unlike other types, streams may be empty, so that the ID of the last entry
+/// added to them is kept.
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    blocks: BTreeMap<StreamId, Vec<Entry>>,
+    len: usize,
+    /// ID of the last entry added, which may have been removed since
+    last_id: StreamId,
+    /// Greatest ID of the entries removed with [`Stream::remove`]
+    max_deleted_id: StreamId,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: StreamId,
+    pub fields: Vec<(Bytes, Bytes)>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Stream::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Sets the ID that the IDs of new entries must be greater than.
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = id;
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    pub fn set_max_deleted_id(&mut self, id: StreamId) {
+        self.max_deleted_id = id;
+    }
+
+    /// ID of the last entry that is still in the stream.
+    pub fn last_entry_id(&self) -> Option<StreamId> {
+        let (_, entries) = self.blocks.last_key_value()?;
+        entries.last().map(|entry| entry.id)
+    }
+
+    /// Adds an entry, whose ID must be greater than the last ID.
+    pub fn push(&mut self, id: StreamId, fields: Vec<(Bytes, Bytes)>) {
+        debug_assert!(id > self.last_id, "stream IDs only grow");
+        let entry = Entry { id, fields };
+        match self.blocks.last_entry() {
+            Some(mut block) if block.get().len() < BLOCK_CAPACITY => block.get_mut().push(entry),
+            _ => {
+                self.blocks.insert(id, vec![entry]);
+            }
+        }
+        self.len += 1;
+        self.last_id = id;
+    }
+
+    /// Removes an entry, returning whether it was in the stream.
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        let Some((&key, entries)) = self.blocks.range_mut(..=id).next_back() else {
+            return false;
+        };
+        let Ok(index) = entries.binary_search_by_key(&id, |entry| entry.id) else {
+            return false;
+        };
+        entries.remove(index);
+        if entries.is_empty() {
+            self.blocks.remove(&key);
+        }
+        self.len -= 1;
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    /// Iterates the entries whose ID is between `start` and `end`, both
+    /// included, from the greatest ID to the smallest if `rev` is set.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        rev: bool,
+    ) -> Box<dyn Iterator<Item = &Entry> + '_> {
+        if start > end {
+            return Box::new(std::iter::empty());
+        }
+        if rev {
+            Box::new(
+                self.blocks
+                    .range(..=end)
+                    .rev()
+                    .flat_map(|(_, entries)| entries.iter().rev())
+                    .skip_while(move |entry| entry.id > end)
+                    .take_while(move |entry| entry.id >= start),
+            )
+        } else {
+            // The block holding `start` may be indexed by a smaller ID
+            let first = self
+                .blocks
+                .range(..=start)
+                .next_back()
+                .map_or(start, |(&key, _)| key);
+            Box::new(
+                self.blocks
+                    .range(first..)
+                    .flat_map(|(_, entries)| entries.iter())
+                    .skip_while(move |entry| entry.id < start)
+                    .take_while(move |entry| entry.id <= end),
+            )
+        }
+    }
+
+    /// Removes the oldest entries, returning how many there were.
+    pub fn trim(&mut self, trim: &StreamTrim) -> usize {
+        let limit = match trim.limit {
+            Some(0) => usize::MAX,
+            Some(limit) => limit,
+            None if trim.approximate => DEFAULT_TRIM_LIMIT,
+            None => usize::MAX,
+        };
+
+        let mut removed = 0;
+        while let Some(mut block) = self.blocks.first_entry() {
+            let entries = block.get_mut();
+            let whole_block = match trim.strategy {
+                TrimStrategy::MaxLen(max_len) => self.len - entries.len() >= max_len,
+                TrimStrategy::MinId(min_id) => {
+                    entries.last().expect("blocks are never empty").id < min_id
+                }
+            };
+            if whole_block {
+                if removed + entries.len() > limit {
+                    break;
+                }
+                removed += entries.len();
+                self.len -= entries.len();
+                block.remove();
+                continue;
+            }
+
+            // The remaining entries to remove are all in this block, and only
+            // exact trimming removes part of a block
+            if !trim.approximate {
+                let count = match trim.strategy {
+                    TrimStrategy::MaxLen(max_len) => self.len.saturating_sub(max_len),
+                    TrimStrategy::MinId(min_id) => {
+                        entries.partition_point(|entry| entry.id < min_id)
+                    }
+                };
+                let count = count.min(limit - removed);
+                entries.drain(..count);
+                removed += count;
+                self.len -= count;
+            }
+            break;
+        }
+        removed
+    }
+}
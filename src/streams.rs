@@ -0,0 +1,449 @@
+//! Commands operating on stream values. Unlike other types, streams are kept
+//! when their last entry is removed, as they still hold the last ID added.
+
+use std::time::SystemTime;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    error::RedisError,
+    keyspace::{Keyspace, Value},
+    protocol::{Element, StreamId, StreamTrim, XAdd, XAddId, XRange, XRead, XSetId},
+    stream::{Entry, Stream},
+    utils::unix_millis,
+};
+
+/// Adds an entry, creating the stream unless `NOMKSTREAM` is given, and replies
+/// with its ID.
+pub fn xadd(db: &mut Keyspace, xadd: XAdd) -> Result<Element> {
+    let XAdd {
+        key,
+        no_mkstream,
+        trim,
+        id,
+        fields,
+    } = xadd;
+
+    let stream = match db.get_stream_mut(&key)? {
+        Some(stream) => stream,
+        None if no_mkstream => return Ok(Element::Null),
+        None => {
+            db.insert(key.clone(), Value::stream(Stream::new()));
+            db.get_stream_mut(&key)?.expect("inserted above")
+        }
+    };
+
+    let id = next_id(stream.last_id(), id)?;
+    stream.push(id, fields);
+    if let Some(trim) = &trim {
+        stream.trim(trim);
+    }
+    // Clients may be blocked reading the stream even though it exists
+    db.blocked().signal_ready(&key);
+    Ok(Element::BulkString(id.to_string().into()))
+}
+
+pub fn xlen(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let len = db.get_stream(key)?.map_or(0, Stream::len);
+    Ok(Element::Integer(len as i64))
+}
+
+pub fn xrange(db: &mut Keyspace, xrange: XRange) -> Result<Element> {
+    // Like Redis, a count of 0 replies before even looking at the key
+    if xrange.count == Some(0) {
+        return Ok(Element::NullArray);
+    }
+    let Some(stream) = db.get_stream(&xrange.key)? else {
+        return Ok(Element::Array(Vec::new()));
+    };
+    let entries = stream
+        .range(xrange.start, xrange.end, xrange.rev)
+        .take(xrange.count.unwrap_or(usize::MAX))
+        .map(entry_reply)
+        .collect();
+    Ok(Element::Array(entries))
+}
+
+/// Removes entries, replying with how many of them were in the stream.
+pub fn xdel(db: &mut Keyspace, key: &[u8], ids: &[StreamId]) -> Result<Element> {
+    let Some(stream) = db.get_stream_mut(key)? else {
+        return Ok(Element::Integer(0));
+    };
+    let removed = ids.iter().filter(|&&id| stream.remove(id)).count();
+    Ok(Element::Integer(removed as i64))
+}
+
+/// Removes the oldest entries, replying with how many there were.
+pub fn xtrim(db: &mut Keyspace, key: &[u8], trim: &StreamTrim) -> Result<Element> {
+    let removed = match db.get_stream_mut(key)? {
+        Some(stream) => stream.trim(trim),
+        None => 0,
+    };
+    Ok(Element::Integer(removed as i64))
+}
+
+pub fn xsetid(db: &mut Keyspace, xsetid: XSetId) -> Result<Element> {
+    let Some(stream) = db.get_stream_mut(&xsetid.key)? else {
+        bail!(RedisError::Err("no such key".to_string()));
+    };
+    if xsetid
+        .max_deleted_id
+        .is_some_and(|max_deleted_id| xsetid.last_id < max_deleted_id)
+    {
+        bail!(RedisError::Err(
+            "The ID specified in XSETID is smaller than the provided max_deleted_entry_id"
+                .to_string()
+        ));
+    }
+    if xsetid.last_id < stream.max_deleted_id() {
+        bail!(RedisError::Err(
+            "The ID specified in XSETID is smaller than current max_deleted_entry_id".to_string()
+        ));
+    }
+    if stream
+        .last_entry_id()
+        .is_some_and(|last_entry_id| xsetid.last_id < last_entry_id)
+    {
+        bail!(RedisError::Err(
+            "The ID specified in XSETID is smaller than the target stream top item".to_string()
+        ));
+    }
+    if xsetid
+        .entries_added
+        .is_some_and(|entries_added| entries_added < stream.len() as u64)
+    {
+        bail!(RedisError::Err(
+            "The entries_added specified in XSETID is smaller than the target stream length"
+                .to_string()
+        ));
+    }
+
+    stream.set_last_id(xsetid.last_id);
+    if let Some(max_deleted_id) = xsetid.max_deleted_id {
+        stream.set_max_deleted_id(max_deleted_id);
+    }
+    Ok(Element::SimpleString("OK".to_string()))
+}
+
+/// Reads the entries added to each stream after the given ID, replying with
+/// `None` if there are none in any of them.
+pub fn xread(db: &mut Keyspace, xread: &XRead) -> Result<Option<Element>> {
+    let mut streams = Vec::new();
+    for (key, id) in &xread.streams {
+        let Some(stream) = db.get_stream(key)? else {
+            continue;
+        };
+        // Only entries added later come after the last ID
+        let Some(start) = id.unwrap_or(stream.last_id()).next() else {
+            continue;
+        };
+        let entries: Vec<Element> = stream
+            .range(start, StreamId::MAX, false)
+            .take(xread.count.unwrap_or(usize::MAX))
+            .map(entry_reply)
+            .collect();
+        if !entries.is_empty() {
+            streams.push((Element::BulkString(key.clone()), Element::Array(entries)));
+        }
+    }
+    Ok((!streams.is_empty()).then_some(Element::PairsMap(streams)))
+}
+
+/// The ID of an entry added to a stream whose last ID is `last_id`.
+fn next_id(last_id: StreamId, id: XAddId) -> Result<StreamId> {
+    let too_small = || {
+        RedisError::Err(
+            "The ID specified in XADD is equal or smaller than the target stream top item"
+                .to_string(),
+        )
+    };
+    Ok(match id {
+        XAddId::Auto => {
+            let now = unix_millis(SystemTime::now()).max(0) as u64;
+            if now > last_id.ms {
+                StreamId::new(now, 0)
+            } else {
+                // Added during the same millisecond, or the clock went backwards
+                last_id.next().ok_or(RedisError::Err(
+                    "The stream has exhausted the last possible ID, unable to add more items"
+                        .to_string(),
+                ))?
+            }
+        }
+        XAddId::AutoSeq(ms) if ms == last_id.ms => last_id
+            .seq
+            .checked_add(1)
+            .map(|seq| StreamId::new(ms, seq))
+            .ok_or_else(too_small)?,
+        XAddId::AutoSeq(ms) if ms > last_id.ms => StreamId::new(ms, 0),
+        XAddId::Explicit(id) if id > last_id => id,
+        XAddId::AutoSeq(_) | XAddId::Explicit(_) => bail!(too_small()),
+    })
+}
+
+fn entry_reply(entry: &Entry) -> Element {
+    Element::Array(vec![
+        Element::BulkString(entry.id.to_string().into()),
+        Element::Array(
+            entry
+                .fields
+                .iter()
+                .flat_map(|(field, value)| {
+                    [
+                        Element::BulkString(field.clone()),
+                        Element::BulkString(value.clone()),
+                    ]
+                })
+                .collect(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::protocol::TrimStrategy;
+
+    use super::*;
+
+    fn add(db: &mut Keyspace, id: XAddId, trim: Option<StreamTrim>) -> Result<String> {
+        let xadd = XAdd {
+            key: Bytes::from("s"),
+            no_mkstream: false,
+            trim,
+            id,
+            fields: vec![(Bytes::from("f"), Bytes::from("v"))],
+        };
+        match super::xadd(db, xadd)? {
+            Element::BulkString(id) => Ok(String::from_utf8(id.to_vec()).unwrap()),
+            other => panic!("replied {other:?}"),
+        }
+    }
+
+    fn explicit(ms: u64, seq: u64) -> XAddId {
+        XAddId::Explicit(StreamId::new(ms, seq))
+    }
+
+    /// The IDs of the entries replied for each stream.
+    fn read(
+        db: &mut Keyspace,
+        streams: &[(&str, Option<StreamId>)],
+        count: Option<usize>,
+    ) -> Vec<(String, Vec<String>)> {
+        let xread = XRead {
+            streams: streams
+                .iter()
+                .map(|&(key, id)| (Bytes::copy_from_slice(key.as_bytes()), id))
+                .collect(),
+            count,
+        };
+        let Some(reply) = super::xread(db, &xread).unwrap() else {
+            return Vec::new();
+        };
+        let Element::PairsMap(streams) = reply else {
+            panic!("replied {reply:?}");
+        };
+        streams
+            .into_iter()
+            .map(|(key, entries)| {
+                let (Element::BulkString(key), Element::Array(entries)) = (key, entries) else {
+                    panic!("replied an unexpected stream");
+                };
+                let ids = entries
+                    .into_iter()
+                    .map(|entry| match entry {
+                        Element::Array(entry) => match &entry[0] {
+                            Element::BulkString(id) => String::from_utf8(id.to_vec()).unwrap(),
+                            other => panic!("replied {other:?}"),
+                        },
+                        other => panic!("replied {other:?}"),
+                    })
+                    .collect();
+                (String::from_utf8(key.to_vec()).unwrap(), ids)
+            })
+            .collect()
+    }
+
+    fn len(db: &mut Keyspace) -> usize {
+        db.get_stream(b"s").unwrap().map_or(0, Stream::len)
+    }
+
+    #[test]
+    fn xadd_generates_ids_greater_than_the_last_one() {
+        let mut db = Keyspace::default();
+        let too_small =
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+        assert_eq!(add(&mut db, XAddId::AutoSeq(0), None).unwrap(), "0-1");
+        assert_eq!(add(&mut db, XAddId::AutoSeq(5), None).unwrap(), "5-0");
+        assert_eq!(add(&mut db, XAddId::AutoSeq(5), None).unwrap(), "5-1");
+        assert_eq!(
+            add(&mut db, explicit(5, 1), None).unwrap_err().to_string(),
+            too_small
+        );
+        assert_eq!(
+            add(&mut db, XAddId::AutoSeq(4), None)
+                .unwrap_err()
+                .to_string(),
+            too_small
+        );
+        assert_eq!(add(&mut db, explicit(5, 3), None).unwrap(), "5-3");
+
+        let now = unix_millis(SystemTime::now()) as u64;
+        let id = add(&mut db, XAddId::Auto, None).unwrap();
+        let (ms, seq) = id.split_once('-').unwrap();
+        assert!(ms.parse::<u64>().unwrap() >= now);
+        assert_eq!(seq, "0");
+
+        // Past the current time, IDs keep growing from the last one
+        assert_eq!(
+            add(&mut db, explicit(u64::MAX - 1, u64::MAX), None).unwrap(),
+            format!("{}-{}", u64::MAX - 1, u64::MAX)
+        );
+        assert_eq!(
+            add(&mut db, XAddId::Auto, None).unwrap(),
+            format!("{}-0", u64::MAX)
+        );
+        assert_eq!(
+            add(&mut db, explicit(u64::MAX, u64::MAX), None).unwrap(),
+            format!("{}-{}", u64::MAX, u64::MAX)
+        );
+        assert_eq!(
+            add(&mut db, XAddId::AutoSeq(u64::MAX), None)
+                .unwrap_err()
+                .to_string(),
+            too_small
+        );
+        assert_eq!(
+            add(&mut db, XAddId::Auto, None).unwrap_err().to_string(),
+            "ERR The stream has exhausted the last possible ID, unable to add more items"
+        );
+        assert_eq!(len(&mut db), 8);
+    }
+
+    #[test]
+    fn xadd_with_nomkstream_does_not_create_the_stream() {
+        let mut db = Keyspace::default();
+        let xadd = XAdd {
+            key: Bytes::from("s"),
+            no_mkstream: true,
+            trim: None,
+            id: XAddId::Auto,
+            fields: vec![(Bytes::from("f"), Bytes::from("v"))],
+        };
+        assert!(matches!(super::xadd(&mut db, xadd).unwrap(), Element::Null));
+        assert!(db.get(b"s").is_none());
+    }
+
+    #[test]
+    fn xtrim_approximately_removes_whole_blocks() {
+        let trim = |strategy, approximate, limit| StreamTrim {
+            strategy,
+            approximate,
+            limit,
+        };
+        // 250 entries make two full blocks followed by half of one
+        for (trim, removed) in [
+            (trim(TrimStrategy::MaxLen(120), false, None), 130),
+            (trim(TrimStrategy::MaxLen(120), true, None), 100),
+            (trim(TrimStrategy::MaxLen(0), true, None), 250),
+            (trim(TrimStrategy::MaxLen(300), true, None), 0),
+            (trim(TrimStrategy::MaxLen(120), true, Some(0)), 100),
+            // Blocks that don't fit within the limit are kept whole
+            (trim(TrimStrategy::MaxLen(0), true, Some(150)), 100),
+            (trim(TrimStrategy::MaxLen(0), true, Some(50)), 0),
+            (
+                trim(TrimStrategy::MinId(StreamId::new(1, 150)), false, None),
+                149,
+            ),
+            (
+                trim(TrimStrategy::MinId(StreamId::new(1, 150)), true, None),
+                100,
+            ),
+            (
+                trim(TrimStrategy::MinId(StreamId::new(2, 0)), true, None),
+                250,
+            ),
+        ] {
+            let mut db = Keyspace::default();
+            for seq in 1..=250 {
+                add(&mut db, explicit(1, seq), None).unwrap();
+            }
+            let context = format!("{trim:?}");
+            let reply = xtrim(&mut db, b"s", &trim).unwrap();
+            assert!(
+                matches!(reply, Element::Integer(n) if n == removed),
+                "{context}"
+            );
+            assert_eq!(len(&mut db), 250 - removed as usize, "{context}");
+
+            // The oldest entries left follow the ones removed
+            let first = db
+                .get_stream(b"s")
+                .unwrap()
+                .unwrap()
+                .range(StreamId::MIN, StreamId::MAX, false)
+                .next();
+            assert_eq!(
+                first.map(|entry| entry.id),
+                (removed < 250).then(|| StreamId::new(1, removed as u64 + 1)),
+                "{context}"
+            );
+        }
+
+        // Trimming while adding keeps the stream from growing past the limit
+        let mut db = Keyspace::default();
+        for seq in 1..=250 {
+            add(
+                &mut db,
+                explicit(1, seq),
+                Some(trim(TrimStrategy::MaxLen(100), false, None)),
+            )
+            .unwrap();
+        }
+        assert_eq!(len(&mut db), 100);
+    }
+
+    #[test]
+    fn xread_reads_after_the_given_ids() {
+        let mut db = Keyspace::default();
+        for (ms, seq) in [(1, 0), (1, 1), (2, 0)] {
+            add(&mut db, explicit(ms, seq), None).unwrap();
+        }
+        let all = vec![(
+            "s".to_string(),
+            vec!["1-0".to_string(), "1-1".to_string(), "2-0".to_string()],
+        )];
+        assert_eq!(read(&mut db, &[("s", Some(StreamId::MIN))], None), all);
+        assert_eq!(
+            read(&mut db, &[("s", Some(StreamId::new(0, u64::MAX)))], None),
+            all
+        );
+        let last = vec![("s".to_string(), vec!["2-0".to_string()])];
+        assert_eq!(
+            read(&mut db, &[("s", Some(StreamId::new(1, 1)))], None),
+            last
+        );
+        assert_eq!(
+            read(&mut db, &[("s", Some(StreamId::new(1, u64::MAX)))], None),
+            last
+        );
+
+        // Nothing comes after the last entry, or the greatest possible ID
+        assert!(read(&mut db, &[("s", Some(StreamId::new(2, 0)))], None).is_empty());
+        assert!(read(&mut db, &[("s", Some(StreamId::MAX))], None).is_empty());
+        assert!(read(&mut db, &[("s", None)], None).is_empty());
+
+        // Streams without new entries are left out of the reply
+        let first = vec![("s".to_string(), vec!["1-0".to_string()])];
+        let streams = [("missing", Some(StreamId::MIN)), ("s", Some(StreamId::MIN))];
+        assert_eq!(read(&mut db, &streams, Some(1)), first);
+
+        // Removed entries are skipped
+        xdel(&mut db, b"s", &[StreamId::new(1, 1)]).unwrap();
+        assert_eq!(
+            read(&mut db, &[("s", Some(StreamId::new(1, 0)))], None),
+            last
+        );
+    }
+}
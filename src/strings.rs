@@ -0,0 +1,397 @@
+//! Commands operating on string values.
+
+use std::time::SystemTime;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    error::RedisError,
+    keyspace::{Keyspace, Value},
+    long_double::LongDouble,
+    protocol::{Element, Expiration, GetEx, Lcs, Set, SetCondition},
+    reader::MAX_BULK_LEN,
+    utils::parse_i64,
+};
+
+pub fn set(db: &mut Keyspace, set: Set) -> Result<Element> {
+    let existing = db.get(&set.key);
+
+    let reply = match (set.get, existing) {
+        (true, Some(existing)) => Element::BulkString(existing.as_string()?.clone()),
+        (true, None) => Element::Null,
+        (false, _) => Element::SimpleString("OK".to_string()),
+    };
+
+    let should_set = match set.condition {
+        None => true,
+        Some(SetCondition::Nx) => existing.is_none(),
+        Some(SetCondition::Xx) => existing.is_some(),
+    };
+    if !should_set {
+        return Ok(if set.get { reply } else { Element::Null });
+    }
+
+    let expiration = match set.expiration {
+        Some(Expiration::After(duration)) => Some(SystemTime::now() + duration),
+        Some(Expiration::At(time)) => Some(time),
+        None if set.keep_ttl => existing.and_then(Value::expiration),
+        None => None,
+    };
+    db.insert(
+        set.key,
+        Value::string(set.value).with_expiration(expiration),
+    );
+    Ok(reply)
+}
+
+pub fn get(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    match db.get_string(key)? {
+        Some(value) => Ok(Element::BulkString(value.clone())),
+        None => Ok(Element::Null),
+    }
+}
+
+/// Replies with the string stored at each key, or nil for keys that are
+/// missing or hold another type.
+pub fn mget(db: &mut Keyspace, keys: &[Bytes]) -> Result<Element> {
+    Ok(Element::Array(
+        keys.iter()
+            .map(|key| match db.get(key).map(Value::as_string) {
+                Some(Ok(string)) => Element::BulkString(string.clone()),
+                _ => Element::Null,
+            })
+            .collect(),
+    ))
+}
+
+pub fn mset(db: &mut Keyspace, pairs: Vec<(Bytes, Bytes)>) -> Result<Element> {
+    for (key, value) in pairs {
+        db.insert(key, Value::string(value));
+    }
+    Ok(Element::SimpleString("OK".to_string()))
+}
+
+/// Sets all the keys, but only if none of them exists.
+pub fn msetnx(db: &mut Keyspace, pairs: Vec<(Bytes, Bytes)>) -> Result<Element> {
+    if pairs.iter().any(|(key, _)| db.get(key).is_some()) {
+        return Ok(Element::Integer(0));
+    }
+    mset(db, pairs)?;
+    Ok(Element::Integer(1))
+}
+
+pub fn incr_by(db: &mut Keyspace, key: Bytes, increment: i64) -> Result<Element> {
+    match db.get_mut(&key) {
+        Some(value) => {
+            let string = value.as_string_mut()?;
+            let result = parse_i64(string)
+                .ok_or(RedisError::NotInteger)?
+                .checked_add(increment)
+                .ok_or(RedisError::Err(
+                    "increment or decrement would overflow".to_string(),
+                ))?;
+            *string = result.to_string().into();
+            Ok(Element::Integer(result))
+        }
+        None => {
+            db.insert(key, Value::string(increment.to_string().into()));
+            Ok(Element::Integer(increment))
+        }
+    }
+}
+
+/// Increments the number stored at a key the way Redis does, with long doubles,
+/// so that e.g. `0.1` incremented by `0.2` is `0.3`.
+pub fn incr_by_float(db: &mut Keyspace, key: Bytes, increment: LongDouble) -> Result<Element> {
+    let current = match db.get_string(&key)? {
+        Some(string) => LongDouble::parse(string)
+            .ok_or(RedisError::Err("value is not a valid float".to_string()))?,
+        None => LongDouble::ZERO,
+    };
+    let result = current + increment;
+    if !result.is_finite() {
+        bail!(RedisError::Err(
+            "increment would produce NaN or Infinity".to_string()
+        ));
+    }
+
+    let result = Bytes::from(result.to_string());
+    match db.get_mut(&key) {
+        Some(value) => *value.as_string_mut()? = result.clone(),
+        None => {
+            db.insert(key, Value::string(result.clone()));
+        }
+    }
+    Ok(Element::BulkString(result))
+}
+
+pub fn append(db: &mut Keyspace, key: Bytes, suffix: Bytes) -> Result<Element> {
+    match db.get_mut(&key) {
+        Some(value) => {
+            let string = value.as_string_mut()?;
+            check_length(string.len() + suffix.len())?;
+            edit(string, |string| string.extend_from_slice(&suffix));
+            Ok(Element::Integer(string.len() as i64))
+        }
+        None => {
+            let len = suffix.len();
+            db.insert(key, Value::string(suffix));
+            Ok(Element::Integer(len as i64))
+        }
+    }
+}
+
+pub fn strlen(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    let len = db.get_string(key)?.map_or(0, Bytes::len);
+    Ok(Element::Integer(len as i64))
+}
+
+/// Replies with the bytes from `start` to `end`, both inclusive. Negative
+/// offsets count from the end of the string, and out of range offsets are
+/// clamped to the string.
+pub fn get_range(db: &mut Keyspace, key: &[u8], start: i64, end: i64) -> Result<Element> {
+    let string = db.get_string(key)?.cloned().unwrap_or_default();
+    let len = string.len() as i64;
+
+    if start < 0 && end < 0 && start > end {
+        return Ok(Element::BulkString(Bytes::new()));
+    }
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.max(0).min(len - 1);
+    if len == 0 || start > end {
+        return Ok(Element::BulkString(Bytes::new()));
+    }
+    Ok(Element::BulkString(
+        string.slice(start as usize..=end as usize),
+    ))
+}
+
+/// Overwrites part of the string starting at `offset`, padding it with zero
+/// bytes if it's shorter than that, and replies with the resulting length.
+pub fn set_range(db: &mut Keyspace, key: Bytes, offset: i64, data: Bytes) -> Result<Element> {
+    if offset < 0 {
+        bail!(RedisError::Err("offset is out of range".to_string()));
+    }
+    let offset = offset as usize;
+
+    match db.get_mut(&key) {
+        Some(value) => {
+            let string = value.as_string_mut()?;
+            if data.is_empty() {
+                return Ok(Element::Integer(string.len() as i64));
+            }
+            check_length(offset + data.len())?;
+
+            edit(string, |string| {
+                if string.len() < offset + data.len() {
+                    string.resize(offset + data.len(), 0);
+                }
+                string[offset..offset + data.len()].copy_from_slice(&data);
+            });
+            Ok(Element::Integer(string.len() as i64))
+        }
+        // Nothing to write, so the key isn't created either
+        None if data.is_empty() => Ok(Element::Integer(0)),
+        None => {
+            check_length(offset + data.len())?;
+            let mut string = vec![0; offset + data.len()];
+            string[offset..].copy_from_slice(&data);
+            let len = string.len();
+            db.insert(key, Value::string(string.into()));
+            Ok(Element::Integer(len as i64))
+        }
+    }
+}
+
+pub fn get_del(db: &mut Keyspace, key: &[u8]) -> Result<Element> {
+    match db.get_string(key)?.cloned() {
+        Some(string) => {
+            db.remove(key);
+            Ok(Element::BulkString(string))
+        }
+        None => Ok(Element::Null),
+    }
+}
+
+/// Replies with the string stored at the key, setting or clearing its
+/// expiration on the way.
+pub fn get_ex(db: &mut Keyspace, get_ex: GetEx) -> Result<Element> {
+    let Some(string) = db.get_string(&get_ex.key)?.cloned() else {
+        return Ok(Element::Null);
+    };
+
+    let now = SystemTime::now();
+    match get_ex.expiration {
+        Some(Expiration::After(duration)) => {
+            db.set_expiration(&get_ex.key, Some(now + duration));
+        }
+        // Like `EXPIREAT`, a time in the past deletes the key
+        Some(Expiration::At(time)) if time <= now => {
+            db.remove(&get_ex.key);
+        }
+        Some(Expiration::At(time)) => {
+            db.set_expiration(&get_ex.key, Some(time));
+        }
+        None if get_ex.persist => {
+            db.set_expiration(&get_ex.key, None);
+        }
+        None => {}
+    }
+    Ok(Element::BulkString(string))
+}
+
+/// Finds the longest common subsequence of the strings stored at two keys,
+/// with missing keys standing for empty strings.
+pub fn lcs(db: &mut Keyspace, lcs: Lcs) -> Result<Element> {
+    let mut string = |key: &[u8]| match db.get(key) {
+        None => Ok(Bytes::new()),
+        Some(value) => value.as_string().cloned().map_err(|_| {
+            RedisError::Err("The specified keys must contain string values".to_string())
+        }),
+    };
+    let a = string(&lcs.key1)?;
+    let b = string(&lcs.key2)?;
+
+    // Like Redis, bound the memory used by the table of subproblems
+    let table_len = (a.len() + 1)
+        .checked_mul(b.len() + 1)
+        .filter(|len| len.saturating_mul(std::mem::size_of::<u32>()) <= MAX_BULK_LEN)
+        .ok_or_else(|| {
+            RedisError::Err(
+                "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+                    .to_string(),
+            )
+        })?;
+
+    // `table[i][j]` is the length of the LCS of `a[..i]` and `b[..j]`
+    let width = b.len() + 1;
+    let mut table = vec![0u32; table_len];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+    let len = table[a.len() * width + b.len()] as usize;
+
+    if lcs.len {
+        return Ok(Element::Integer(len as i64));
+    }
+
+    // Walk the table back from the end of both strings, collecting the common
+    // subsequence and the ranges of contiguous matches, last ones first
+    let mut subsequence = vec![0; len];
+    let mut remaining = len;
+    let mut matches = Vec::new();
+    // Current range, as (start of a, end of a, start of b, end of b)
+    let mut range: Option<(usize, usize, usize, usize)> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        let mut emit_range = false;
+        if a[i - 1] == b[j - 1] {
+            subsequence[remaining - 1] = a[i - 1];
+            match &mut range {
+                None => range = Some((i - 1, i - 1, j - 1, j - 1)),
+                // Extend the range backwards if the match is contiguous with it
+                Some((a_start, _, b_start, _)) if *a_start == i && *b_start == j => {
+                    *a_start -= 1;
+                    *b_start -= 1;
+                }
+                Some(_) => emit_range = true,
+            }
+            if let Some((0, ..) | (_, _, 0, _)) = range {
+                emit_range = true;
+            }
+            remaining -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit_range = range.is_some();
+        }
+
+        if emit_range {
+            let (a_start, a_end, b_start, b_end) = range.take().expect("range is being tracked");
+            let match_len = a_end - a_start + 1;
+            if lcs.idx && match_len >= lcs.min_match_len {
+                let mut entry = vec![
+                    Element::Array(vec![
+                        Element::Integer(a_start as i64),
+                        Element::Integer(a_end as i64),
+                    ]),
+                    Element::Array(vec![
+                        Element::Integer(b_start as i64),
+                        Element::Integer(b_end as i64),
+                    ]),
+                ];
+                if lcs.with_match_len {
+                    entry.push(Element::Integer(match_len as i64));
+                }
+                matches.push(Element::Array(entry));
+            }
+        }
+    }
+
+    if lcs.idx {
+        let field = |name: &'static str| Element::BulkString(Bytes::from_static(name.as_bytes()));
+        Ok(Element::Map(vec![
+            (field("matches"), Element::Array(matches)),
+            (field("len"), Element::Integer(len as i64)),
+        ]))
+    } else {
+        Ok(Element::BulkString(subsequence.into()))
+    }
+}
+
+/// Modifies a string in place. The buffer is only copied if it's shared, e.g.
+/// with a reply that hasn't been sent yet.
+pub fn edit<T>(string: &mut Bytes, f: impl FnOnce(&mut Vec<u8>) -> T) -> T {
+    let mut bytes = Vec::from(std::mem::take(string));
+    let result = f(&mut bytes);
+    *string = bytes.into();
+    result
+}
+
+/// Fails if a string would grow beyond the largest size allowed.
+pub fn check_length(len: usize) -> Result<()> {
+    if len > MAX_BULK_LEN {
+        bail!(RedisError::Err(
+            "string exceeds maximum allowed size (proto-max-bulk-len)".to_string()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incr_by_float_rounds_like_redis() {
+        let mut db = Keyspace::default();
+        db.insert(
+            Bytes::from_static(b"k"),
+            Value::string(Bytes::from_static(b"0.1")),
+        );
+        let increment = LongDouble::parse(b"0.2").unwrap();
+        let reply = incr_by_float(&mut db, Bytes::from_static(b"k"), increment).unwrap();
+        assert!(matches!(reply, Element::BulkString(result) if result == "0.3"));
+        assert_eq!(db.get_string(b"k").unwrap().unwrap(), "0.3");
+    }
+
+    #[test]
+    fn incr_by_float_rejects_infinity() {
+        let mut db = Keyspace::default();
+        let increment = LongDouble::parse(b"inf").unwrap();
+        assert!(incr_by_float(&mut db, Bytes::from_static(b"k"), increment).is_err());
+        assert!(db.get_string(b"k").unwrap().is_none());
+    }
+}
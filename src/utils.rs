@@ -1,3 +1,9 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 pub fn decode_hex(s: &str) -> Vec<u8> {
     (0..s.len())
         .step_by(2)
@@ -6,3 +12,297 @@ pub fn decode_hex(s: &str) -> Vec<u8> {
         })
         .collect()
 }
+
+/// Parses an integer with the same strictness as Redis: no sign other than a
+/// leading `-`, no leading zeros and no surrounding whitespace.
+pub fn parse_i64(raw: &[u8]) -> Option<i64> {
+    let digits = raw.strip_prefix(b"-").unwrap_or(raw);
+    let well_formed = match digits {
+        [] => false,
+        [b'0'] => raw.len() == 1,
+        [first, ..] => *first != b'0' && digits.iter().all(u8::is_ascii_digit),
+    };
+    if !well_formed {
+        return None;
+    }
+    std::str::from_utf8(raw).ok()?.parse().ok()
+}
+
+/// Parses a floating point number, rejecting surrounding whitespace and NaN.
+pub fn parse_f64(raw: &[u8]) -> Option<f64> {
+    std::str::from_utf8(raw)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|f: &f64| !f.is_nan())
+}
+
+/// Formats a floating point number like Redis replies with scores: the shortest
+/// representation that parses back to it, laid out like `%.17g`, so that
+/// exponential notation is used for exponents below -4 or from 17 on. Results
+/// of `INCRBYFLOAT` and `HINCRBYFLOAT` are instead computed and formatted as
+/// long doubles, see [`crate::long_double::LongDouble`].
+pub fn format_f64(f: f64) -> String {
+    if f.is_nan() {
+        return "nan".to_string();
+    }
+    if f.is_infinite() {
+        return if f.is_sign_positive() { "inf" } else { "-inf" }.to_string();
+    }
+    let scientific = format!("{f:e}");
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("formatted with an exponent");
+    let exponent: i32 = exponent.parse().expect("formatted as an integer");
+    if (-4..17).contains(&exponent) {
+        format!("{f}")
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{mantissa}e{sign}{:02}", exponent.unsigned_abs())
+    }
+}
+
+/// Milliseconds since the unix epoch, negative for times before it, saturating
+/// for times too far away.
+pub fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => i64::try_from(duration.as_millis()).unwrap_or(i64::MAX),
+        Err(e) => i64::try_from(e.duration().as_millis()).map_or(i64::MIN, |millis| -millis),
+    }
+}
+
+pub fn from_unix_millis(millis: i64) -> SystemTime {
+    if millis >= 0 {
+        UNIX_EPOCH + Duration::from_millis(millis as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_millis(millis.unsigned_abs())
+    }
+}
+
+/// Small xorshift pseudo random number generator. Not suitable for anything that
+/// needs to be unpredictable, but good enough to sample keys.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new() -> Self {
+        // RandomState is seeded randomly by the standard library
+        let seed = RandomState::new().build_hasher().finish();
+        Rng(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniformly distributed number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Matches `string` against a glob-style pattern, with the same syntax as Redis:
+///
+/// - `?` matches any single byte
+/// - `*` matches any sequence of bytes, including an empty one
+/// - `[abc]` matches one of the bytes in the brackets, `[^abc]` any byte not in
+///   them, and `[a-z]` any byte in the range
+/// - `\` escapes the next byte, both inside and outside brackets
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last star if the rest of the pattern doesn't
+    // match: the position in the pattern after it, and in the string where the
+    // star stopped matching
+    let mut backtrack = None;
+
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            backtrack = Some((p, s));
+            continue;
+        }
+
+        if p < pattern.len() {
+            let (matched, len) = match_token(&pattern[p..], string[s]);
+            if matched {
+                p += len;
+                s += 1;
+                continue;
+            }
+        }
+
+        match backtrack {
+            Some((star_p, star_s)) => {
+                // Let the star swallow one more byte
+                p = star_p;
+                s = star_s + 1;
+                backtrack = Some((star_p, s));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Matches `b` against the token (anything but a star) at the start of `pattern`,
+/// returning whether it matched and the length of the token.
+fn match_token(pattern: &[u8], b: u8) -> (bool, usize) {
+    match pattern {
+        [b'?', ..] => (true, 1),
+        [b'[', class @ ..] => {
+            let (matched, rest) = match_class(class, b);
+            (matched, pattern.len() - rest.len())
+        }
+        [b'\\', escaped, ..] => (*escaped == b, 2),
+        [c, ..] => (*c == b, 1),
+        [] => (false, 0),
+    }
+}
+
+/// Matches `b` against the character class at the start of `pattern`, right after
+/// the opening bracket, and returns whether it matched along with the rest of the
+/// pattern after the closing bracket.
+fn match_class(pattern: &[u8], b: u8) -> (bool, &[u8]) {
+    let (negate, mut pattern) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    loop {
+        match pattern {
+            // An unterminated class ends with the pattern
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == b;
+                pattern = rest;
+            }
+            // Like Redis, a closing bracket can end a range, which then leaves
+            // the class unterminated
+            [start, b'-', end, rest @ ..] => {
+                let (low, high) = if start <= end {
+                    (*start, *end)
+                } else {
+                    (*end, *start)
+                };
+                matched |= (low..=high).contains(&b);
+                pattern = rest;
+            }
+            [c, rest @ ..] => {
+                matched |= *c == b;
+                pattern = rest;
+            }
+        }
+    }
+
+    (matched != negate, pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_f64_like_redis() {
+        for (f, formatted) in [
+            (0.0, "0"),
+            (-0.0, "-0"),
+            (1.5, "1.5"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (-12345.678, "-12345.678"),
+            (1e16, "10000000000000000"),
+            (1e17, "1e+17"),
+            (123456789012345680.0, "1.2345678901234568e+17"),
+            (1e300, "1e+300"),
+            (-2.5e300, "-2.5e+300"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (-1.25e-7, "-1.25e-07"),
+            (5e-324, "5e-324"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+        ] {
+            assert_eq!(format_f64(f), formatted);
+        }
+    }
+
+    #[test]
+    fn glob_matches_like_redis() {
+        let long = "a".repeat(50);
+        for (pattern, string, matches) in [
+            ("", "", true),
+            ("", "a", false),
+            ("a", "", false),
+            ("*", "", true),
+            ("*", "anything", true),
+            ("**", "a", true),
+            ("?", "", false),
+            ("?", "a", true),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("h*llo", "hllo", true),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "hello!", false),
+            // Stars give back bytes they matched when the rest doesn't match
+            ("*ab", "aab", true),
+            ("*a*b", "xaxxb", true),
+            ("a*b*c", "abxbxc", true),
+            ("a*b*c", "abxbx", false),
+            (
+                "*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b",
+                &long,
+                false,
+            ),
+            (
+                "*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a",
+                &long,
+                true,
+            ),
+            ("h[ae]llo", "hello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("[a-c]", "b", true),
+            ("[a-c]", "d", false),
+            ("[^a-c]", "d", true),
+            ("[^a-c]", "b", false),
+            ("[^a-c]", "", false),
+            // Reversed ranges are swapped
+            ("[z-a]", "m", true),
+            ("[z-a]", "-", false),
+            ("[a-cx-z]", "y", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("a\\?", "a?", true),
+            ("a\\?", "ab", false),
+            ("[\\]]", "]", true),
+            ("[\\-x]", "-", true),
+            ("[\\-x]", "a", false),
+            ("a\\", "a\\", true),
+            // Unterminated classes end with the pattern
+            ("a[", "a", false),
+            ("a[", "ab", false),
+            ("[abc", "b", true),
+            ("[abc", "d", false),
+            ("[^", "x", true),
+            ("[a-]", "_", true),
+            ("[a-]", "-", false),
+            ("[]]", "]", false),
+        ] {
+            let matched = glob_match(pattern.as_bytes(), string.as_bytes());
+            assert_eq!(matched, matches, "{pattern:?} against {string:?}");
+        }
+    }
+}
@@ -1,38 +1,40 @@
-use crate::protocol::{Command, Element, ReplOpt};
+use bytes::Bytes;
+
+use crate::{
+    protocol::{Command, Element, Protocol, ReplOpt},
+    utils::format_f64,
+};
 
 pub fn serialize_command(command: Command) -> Vec<u8> {
     let args = match command {
         Command::Ping(message) => {
             let mut elements = Vec::new();
-            elements.push(Element::BulkString(b"PING".to_vec()));
+            elements.push(Element::BulkString(Bytes::from_static(b"PING")));
             if let Some(message) = message {
-                elements.push(Element::BulkString(message.as_bytes().to_vec()));
+                elements.push(Element::BulkString(message));
             }
             elements
         }
         Command::Echo(message) => vec![
-            Element::BulkString(b"ECHO".to_vec()),
-            Element::BulkString(message.into()),
+            Element::BulkString(Bytes::from_static(b"ECHO")),
+            Element::BulkString(message),
         ],
-        Command::Set(_) => todo!(),
-        Command::Get(_) => todo!(),
-        Command::Info(_) => todo!(),
         Command::ReplConf(repl_opt) => {
-            let mut args = vec![Element::BulkString(b"REPLCONF".to_vec())];
+            let mut args = vec![Element::BulkString(Bytes::from_static(b"REPLCONF"))];
             match repl_opt {
                 ReplOpt::ListeningPort(port) => {
-                    args.push(Element::BulkString(b"listening-port".to_vec()));
+                    args.push(Element::BulkString(Bytes::from_static(b"listening-port")));
                     args.push(Element::BulkString(format!("{port}").into()));
                 }
                 ReplOpt::Capability => {
-                    args.push(Element::BulkString(b"capa".to_vec()));
-                    args.push(Element::BulkString(b"psync2".to_vec()));
+                    args.push(Element::BulkString(Bytes::from_static(b"capa")));
+                    args.push(Element::BulkString(Bytes::from_static(b"psync2")));
                 }
             }
             args
         }
         Command::Psync(psync) => vec![
-            Element::BulkString(b"PSYNC".to_vec()),
+            Element::BulkString(Bytes::from_static(b"PSYNC")),
             Element::BulkString(
                 psync
                     .replication_id
@@ -47,38 +49,143 @@ pub fn serialize_command(command: Command) -> Vec<u8> {
                     .into(),
             ),
         ],
+        // Only the commands a replica sends to its master need to be serialized
+        other => todo!("serializing {other:?}"),
     };
-    serialize_element(Element::Array(args))
+    serialize_element(Element::Array(args), Protocol::Resp2)
 }
 
-pub fn serialize_element(element: Element) -> Vec<u8> {
-    match element {
-        Element::SimpleString(message) => format!("+{}\r\n", message).as_bytes().to_vec(),
-        Element::BulkString(data) => {
-            let mut bytes = Vec::new();
-            bytes.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
-            bytes.extend_from_slice(&data);
-            bytes.extend_from_slice(b"\r\n");
-            bytes
-        }
-        Element::NullBulkString => b"$-1\r\n".to_vec(),
-        Element::Array(elements) => {
-            let mut bytes = Vec::new();
-            bytes.extend_from_slice(format!("*{}\r\n", elements.len()).as_bytes());
-            for element in elements.into_iter() {
-                bytes.extend_from_slice(&serialize_element(element))
-            }
-            bytes
+/// Serializes an element for a connection speaking `protocol`. RESP3-only types
+/// are downgraded to their RESP2 equivalent when talking to a RESP2 client.
+pub fn serialize_element(element: Element, protocol: Protocol) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_element(&mut bytes, element, protocol);
+    bytes
+}
+
+fn write_element(bytes: &mut Vec<u8>, element: Element, protocol: Protocol) {
+    match (element, protocol) {
+        (Element::SimpleString(message), _) => write_line(bytes, b'+', message.as_bytes()),
+        (Element::Error(message), _) => write_line(bytes, b'-', message.as_bytes()),
+        (Element::BulkString(data), _) => write_blob(bytes, b'$', &data),
+        (Element::Null, Protocol::Resp2) => bytes.extend_from_slice(b"$-1\r\n"),
+        (Element::NullArray, Protocol::Resp2) => bytes.extend_from_slice(b"*-1\r\n"),
+        (Element::Array(elements), _) | (Element::Push(elements), Protocol::Resp2) => {
+            write_aggregate(bytes, b'*', elements, protocol)
+        }
+        (Element::Integer(n), _) => write_line(bytes, b':', n.to_string().as_bytes()),
+        (Element::Null | Element::NullArray, Protocol::Resp3) => bytes.extend_from_slice(b"_\r\n"),
+        (Element::Boolean(b), Protocol::Resp2) => {
+            write_line(bytes, b':', if b { b"1" } else { b"0" })
+        }
+        (Element::Boolean(b), Protocol::Resp3) => {
+            write_line(bytes, b'#', if b { b"t" } else { b"f" })
+        }
+        (Element::Double(d), Protocol::Resp2) => write_blob(bytes, b'$', format_f64(d).as_bytes()),
+        (Element::Double(d), Protocol::Resp3) => write_line(bytes, b',', format_f64(d).as_bytes()),
+        (Element::BigNumber(n), Protocol::Resp2) => write_blob(bytes, b'$', n.as_bytes()),
+        (Element::BigNumber(n), Protocol::Resp3) => write_line(bytes, b'(', n.as_bytes()),
+        (Element::Map(pairs), Protocol::Resp2) => {
+            bytes.extend_from_slice(format!("*{}\r\n", pairs.len() * 2).as_bytes());
+            write_pairs(bytes, pairs, protocol);
+        }
+        (Element::Map(pairs), Protocol::Resp3) => {
+            bytes.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes());
+            write_pairs(bytes, pairs, protocol);
+        }
+        (Element::PairsMap(pairs), Protocol::Resp2) => write_aggregate(
+            bytes,
+            b'*',
+            pairs
+                .into_iter()
+                .map(|(first, second)| Element::Array(vec![first, second]))
+                .collect(),
+            protocol,
+        ),
+        (Element::PairsMap(pairs), Protocol::Resp3) => {
+            bytes.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes());
+            write_pairs(bytes, pairs, protocol);
+        }
+        (Element::Pairs(pairs), Protocol::Resp2) => {
+            bytes.extend_from_slice(format!("*{}\r\n", pairs.len() * 2).as_bytes());
+            write_pairs(bytes, pairs, protocol);
+        }
+        (Element::Pairs(pairs), Protocol::Resp3) => write_aggregate(
+            bytes,
+            b'*',
+            pairs
+                .into_iter()
+                .map(|(first, second)| Element::Array(vec![first, second]))
+                .collect(),
+            protocol,
+        ),
+        (Element::Set(elements), Protocol::Resp2) => {
+            write_aggregate(bytes, b'*', elements, protocol)
+        }
+        (Element::Set(elements), Protocol::Resp3) => {
+            write_aggregate(bytes, b'~', elements, protocol)
+        }
+        (Element::VerbatimString { data, .. }, Protocol::Resp2) => write_blob(bytes, b'$', &data),
+        (Element::VerbatimString { format, data }, Protocol::Resp3) => {
+            let mut payload = Vec::with_capacity(format.len() + 1 + data.len());
+            payload.extend_from_slice(format.as_bytes());
+            payload.push(b':');
+            payload.extend_from_slice(&data);
+            write_blob(bytes, b'=', &payload)
         }
-        Element::RdbFile(data) => {
-            let mut bytes = Vec::new();
+        (Element::Attribute { element, .. }, Protocol::Resp2) => {
+            write_element(bytes, *element, protocol)
+        }
+        (
+            Element::Attribute {
+                attributes,
+                element,
+            },
+            Protocol::Resp3,
+        ) => {
+            bytes.extend_from_slice(format!("|{}\r\n", attributes.len()).as_bytes());
+            write_pairs(bytes, attributes, protocol);
+            write_element(bytes, *element, protocol);
+        }
+        (Element::Push(elements), Protocol::Resp3) => {
+            write_aggregate(bytes, b'>', elements, protocol)
+        }
+        (Element::RdbFile(data), _) => {
             bytes.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
             bytes.extend_from_slice(&data);
-            bytes
         }
-        Element::MultiInternal(elements) => elements
-            .into_iter()
-            .flat_map(|element| serialize_element(element).into_iter())
-            .collect(),
+        (Element::MultiInternal(elements), _) => {
+            for element in elements {
+                write_element(bytes, element, protocol);
+            }
+        }
+    }
+}
+
+fn write_line(bytes: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    bytes.push(prefix);
+    bytes.extend_from_slice(line);
+    bytes.extend_from_slice(b"\r\n");
+}
+
+fn write_blob(bytes: &mut Vec<u8>, prefix: u8, data: &[u8]) {
+    bytes.push(prefix);
+    bytes.extend_from_slice(format!("{}\r\n", data.len()).as_bytes());
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(b"\r\n");
+}
+
+fn write_aggregate(bytes: &mut Vec<u8>, prefix: u8, elements: Vec<Element>, protocol: Protocol) {
+    bytes.push(prefix);
+    bytes.extend_from_slice(format!("{}\r\n", elements.len()).as_bytes());
+    for element in elements {
+        write_element(bytes, element, protocol);
+    }
+}
+
+fn write_pairs(bytes: &mut Vec<u8>, pairs: Vec<(Element, Element)>, protocol: Protocol) {
+    for (key, value) in pairs {
+        write_element(bytes, key, protocol);
+        write_element(bytes, value, protocol);
     }
 }